@@ -0,0 +1,48 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use libhackrf::util::{self, Dither};
+use num_complex::Complex;
+
+/// Samples in one default HackRF transfer.
+const SAMPLES: usize = 131_072;
+
+fn convert(c: &mut Criterion) {
+    let input_i8 = (0..SAMPLES)
+        .map(|x| Complex::new(x as i8, (x >> 8) as i8))
+        .collect::<Vec<_>>();
+    let input_f32 = (0..SAMPLES)
+        .map(|x| Complex::new((x as f32).sin(), (x as f32).cos()))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Elements(SAMPLES as u64));
+
+    let mut output = vec![Complex::default(); SAMPLES];
+    group.bench_function("i8_to_f32", |b| {
+        b.iter(|| util::i8_to_f32(black_box(&input_i8), &mut output))
+    });
+
+    let mut output = vec![Complex::default(); SAMPLES];
+    group.bench_function("i8_to_f64", |b| {
+        b.iter(|| util::i8_to_f64(black_box(&input_i8), &mut output))
+    });
+
+    let mut output = vec![Complex::default(); SAMPLES];
+    group.bench_function("i8_to_i16", |b| {
+        b.iter(|| util::i8_to_i16(black_box(&input_i8), &mut output))
+    });
+
+    let mut output = vec![Complex::default(); SAMPLES];
+    group.bench_function("f32_to_i8", |b| {
+        b.iter(|| util::f32_to_i8(black_box(&input_f32), &mut output))
+    });
+
+    let mut dither = Dither::default();
+    group.bench_function("f32_to_i8_dithered", |b| {
+        b.iter(|| util::f32_to_i8_dithered(black_box(&input_f32), &mut output, &mut dither))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
+use crate::consts::WAVE_SAMPLE_RATE;
+
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Args {
@@ -41,6 +43,12 @@ pub struct ReceiveArgs {
     /// The receive low noise amplifier power setting. (In db)
     #[arg(short, long, default_value_t = 30)]
     pub lna_gain: u32,
+    /// The sample rate of the created .wav file.
+    #[arg(short, long, default_value_t = WAVE_SAMPLE_RATE)]
+    pub sample_rate: u32,
+    /// Adjust the LNA and VGA gains automatically, starting from the given gains.
+    #[arg(long)]
+    pub agc: bool,
 
     /// Path of a .wav file that will be created and written to.
     pub audio: PathBuf,
@@ -1,24 +1,30 @@
 use std::{
-    io::stdin,
-    sync::{Arc, Mutex},
+    fs::File,
+    io::{self, stdin, BufWriter},
 };
 
 use anyhow::Result;
 use hound::WavWriter;
-use libhackrf::{util::ToComplexF32, HackRf};
+use libhackrf::{
+    agc::HardwareAgc,
+    flowgraph::{DeviceSource, Flowgraph, Sink, ToF32},
+    HackRf,
+};
 
 use crate::{
     args::ReceiveArgs,
-    consts::{SAMPLE_RATE, WAVE_SPEC},
+    consts::{wave_spec, SAMPLE_RATE},
     signal::demodulate::Demodulator,
 };
 
-pub fn run(args: ReceiveArgs) -> Result<()> {
-    let hackrf = HackRf::open()?;
+pub fn run(hackrf: HackRf, args: ReceiveArgs) -> Result<()> {
     hackrf.set_sample_rate(SAMPLE_RATE)?;
     hackrf.set_freq(args.frequency)?;
     hackrf.set_lna_gain(args.lna_gain)?;
     hackrf.set_rxvga_gain(args.gain)?;
+    if args.agc {
+        hackrf.set_agc(Some(HardwareAgc::new(-18.0)));
+    }
 
     let serial_number = hackrf.get_serial_number()?;
     println!(
@@ -31,35 +37,43 @@ pub fn run(args: ReceiveArgs) -> Result<()> {
             .join("-")
     );
 
-    // Really could be an UnsafeCell, but whatecer
-    let audio = Arc::new(Mutex::new((Demodulator::new(), Vec::<f32>::new())));
-    hackrf.start_rx(
-        |_hackrf, buffer, user| {
-            let data = user
-                .downcast_ref::<Arc<Mutex<(Demodulator, Vec<f32>)>>>()
-                .unwrap();
-            let mut data = data.lock().unwrap();
-
-            let samples = buffer.iter().map(|x| x.to_f32()).collect::<Vec<_>>();
-
-            data.0.replace(samples);
-            let audio = data.0.audio(-900e3, 1.0);
-            data.1.extend_from_slice(&audio);
-        },
-        audio.clone(),
-    )?;
+    let mut graph = Flowgraph::new();
+    let iq = graph.source(DeviceSource::new(hackrf));
+    let iq = graph.block(iq, ToF32);
+    let audio = graph.block(iq, Demodulator::new(-900e3, 1.0, args.sample_rate));
+    let writer = WavWriter::create(args.audio, wave_spec(args.sample_rate))?;
+    graph.sink(audio, WavSink(Some(writer)));
+    let running = graph.start();
 
     println!("Press Enter to stop recording...");
 
     let mut string = String::new();
     stdin().read_line(&mut string)?;
-    hackrf.stop_tx()?;
+    running.stop();
+    running.wait()?;
 
-    let mut writer = WavWriter::create(args.audio, WAVE_SPEC)?;
-    for sample in audio.lock().unwrap().1.iter() {
-        writer.write_sample(*sample)?;
+    Ok(())
+}
+
+/// Writes the demodulated audio as it arrives, finalizing the file at the end.
+struct WavSink(Option<WavWriter<BufWriter<File>>>);
+
+impl Sink for WavSink {
+    type In = f32;
+
+    fn consume(&mut self, input: &[f32]) -> io::Result<()> {
+        if let Some(writer) = &mut self.0 {
+            for &sample in input {
+                writer.write_sample(sample).map_err(io::Error::other)?;
+            }
+        }
+        Ok(())
     }
-    writer.finalize()?;
 
-    Ok(())
+    fn finish(&mut self) -> io::Result<()> {
+        match self.0.take() {
+            Some(writer) => writer.finalize().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
 }
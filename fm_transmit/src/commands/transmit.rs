@@ -13,8 +13,7 @@ use crate::{
     signal::modulate::Modulator,
 };
 
-pub fn run(args: TransmitArgs) -> Result<()> {
-    let hackrf = HackRf::open()?;
+pub fn run(hackrf: HackRf, args: TransmitArgs) -> Result<()> {
     hackrf.set_sample_rate(SAMPLE_RATE)?;
     hackrf.set_freq(args.frequency)?;
     hackrf.set_txvga_gain(args.gain)?;
@@ -33,7 +32,7 @@ pub fn run(args: TransmitArgs) -> Result<()> {
     let wav = WavReader::open(args.audio)?;
     let audio = Arc::new(Mutex::new(Modulator::new(SAMPLE_RATE, TX_BANDWIDTH, wav)));
     hackrf.start_tx(
-        |_hackrf, buffer, user| {
+        |_hackrf, buffer, _info, user| {
             let data = user.downcast_ref::<Arc<Mutex<Modulator>>>().unwrap();
             let mut data = data.lock().unwrap();
 
@@ -1,3 +0,0 @@
-pub mod down_sample;
-pub mod low_pass;
-pub mod offset;
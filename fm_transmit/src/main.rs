@@ -1,18 +1,19 @@
 use anyhow::Result;
 use args::{Args, Command};
 use clap::Parser;
+use libhackrf::HackRf;
 
 mod args;
 mod commands;
 mod consts;
-mod filters;
 mod signal;
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let hackrf = HackRf::open()?;
 
     match args.command {
-        Command::Transmit(args) => commands::transmit::run(args),
-        Command::Receive(args) => commands::receive::run(args),
+        Command::Transmit(args) => commands::transmit::run(hackrf, args),
+        Command::Receive(args) => commands::receive::run(hackrf, args),
     }
 }
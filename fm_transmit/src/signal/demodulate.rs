@@ -1,46 +1,75 @@
-use std::iter;
-
-use itertools::Itertools;
+use libhackrf::{
+    dsp::{DcBlocker, IqBalance, OffsetFilter, Resampler},
+    flowgraph::Block,
+};
 use num_complex::Complex;
-use num_traits::Zero;
 
-use crate::{
-    consts::{AUDIO_CUTOFF_FREQ, IQ_CUTOFF_FREQ, SAMPLE_RATE, WAVE_SAMPLE_RATE},
-    filters::{down_sample::DownSampleExt, low_pass::LowPassExt, offset::OffsetExt},
-};
+use crate::consts::{IQ_SAMPLE_RATE, SAMPLE_RATE};
 
+/// Demodulates an FM signal received in blocks to audio, keeping the filter state between
+/// blocks.
 pub struct Demodulator {
+    gain: f32,
+    /// Removes the DC spike and the images of the direct conversion receiver.
+    dc_blocker: DcBlocker<Complex<f32>>,
+    iq_balance: IqBalance,
+    offset: OffsetFilter,
+    /// Selects the channel and reduces the sample rate to [`IQ_SAMPLE_RATE`].
+    iq_resampler: Resampler<Complex<f32>>,
+    audio_resampler: Resampler<f32>,
+    audio_dc_blocker: DcBlocker<f32>,
+    last_sample: Option<Complex<f32>>,
+
     iq: Vec<Complex<f32>>,
-    last_sample: Complex<f32>,
+    channel: Vec<Complex<f32>>,
+    phase: Vec<f32>,
 }
 
 impl Demodulator {
-    pub fn new() -> Self {
+    pub fn new(offset: f32, gain: f32, audio_sample_rate: u32) -> Self {
         Self {
+            gain,
+            dc_blocker: DcBlocker::new(SAMPLE_RATE, 100.0),
+            iq_balance: IqBalance::new(SAMPLE_RATE, 0.1),
+            offset: OffsetFilter::new(offset, SAMPLE_RATE),
+            iq_resampler: Resampler::from_rates(SAMPLE_RATE, IQ_SAMPLE_RATE),
+            audio_resampler: Resampler::from_rates(IQ_SAMPLE_RATE, audio_sample_rate),
+            audio_dc_blocker: DcBlocker::new(audio_sample_rate, 20.0),
+            last_sample: None,
+
             iq: Vec::new(),
-            last_sample: Complex::zero(),
+            channel: Vec::new(),
+            phase: Vec::new(),
         }
     }
 
-    pub fn replace(&mut self, data: Vec<Complex<f32>>) {
-        self.iq = data;
+    /// Demodulates a block of samples, appending the audio to `audio`.
+    pub fn process(&mut self, samples: &[Complex<f32>], audio: &mut Vec<f32>) {
+        self.iq.resize(samples.len(), Complex::default());
+        self.dc_blocker.process(samples, &mut self.iq);
+        self.iq_balance.process_in_place(&mut self.iq);
+        self.offset.process_in_place(&mut self.iq);
+
+        self.channel.clear();
+        self.iq_resampler.process(&self.iq, &mut self.channel);
+
+        self.phase.clear();
+        for &sample in &self.channel {
+            let last = self.last_sample.replace(sample).unwrap_or(sample);
+            self.phase.push((sample * last.conj()).arg() * self.gain);
+        }
+
+        let start = audio.len();
+        self.audio_resampler.process(&self.phase, audio);
+        self.audio_dc_blocker.process_in_place(&mut audio[start..]);
     }
+}
+
+impl Block for Demodulator {
+    type In = Complex<f32>;
+    type Out = f32;
 
-    pub fn audio(&mut self, offset: f32, gain: f32) -> Vec<f32> {
-        self.last_sample = self.iq.last().copied().unwrap_or_default();
-        let mut audio = iter::once(self.last_sample)
-            .chain(self.iq.iter().copied())
-            .offset(offset, SAMPLE_RATE)
-            .low_pass(SAMPLE_RATE, IQ_CUTOFF_FREQ)
-            .tuple_windows()
-            .map(|(a, b)| (b * a.conj()).arg() * gain)
-            .low_pass(SAMPLE_RATE, AUDIO_CUTOFF_FREQ)
-            .down_sample(SAMPLE_RATE, WAVE_SAMPLE_RATE)
-            .collect::<Vec<_>>();
-
-        let dc = audio.iter().copied().sum::<f32>() / audio.len() as f32;
-        audio.iter_mut().for_each(|x| *x -= dc);
-
-        audio
+    fn process(&mut self, input: &[Complex<f32>], output: &mut Vec<f32>) {
+        Demodulator::process(self, input, output);
     }
 }
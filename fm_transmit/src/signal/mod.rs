@@ -1,2 +1,116 @@
 pub mod demodulate;
 pub mod modulate;
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        f32::consts::TAU,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use hound::{WavReader, WavWriter};
+    use libhackrf::{
+        sim::{
+            loopback::{self, Channel},
+            Pacing,
+        },
+        util::{ToComplexF32, ToComplexI8},
+    };
+    use num_complex::Complex;
+
+    use super::{demodulate::Demodulator, modulate::Modulator};
+    use crate::consts::{wave_spec, SAMPLE_RATE, TX_BANDWIDTH};
+
+    const AUDIO_RATE: u32 = 48_000;
+    const TONE: f32 = 1_000.0;
+    /// Received samples kept after the transmission starts.
+    const RECEIVED: usize = 600_000;
+
+    type Received = Arc<Mutex<Vec<Complex<i8>>>>;
+
+    #[test]
+    fn recovers_tone_through_loopback() {
+        let path = std::env::temp_dir().join(format!("fm_transmit-{}.wav", std::process::id()));
+        let mut writer = WavWriter::create(&path, wave_spec(AUDIO_RATE)).unwrap();
+        for n in 0..AUDIO_RATE / 2 {
+            let t = n as f32 / AUDIO_RATE as f32;
+            writer.write_sample(0.5 * (TAU * TONE * t).sin()).unwrap();
+        }
+        writer.finalize().unwrap();
+        let wav = WavReader::open(&path).unwrap();
+        let modulator = Arc::new(Mutex::new(Modulator::new(SAMPLE_RATE, TX_BANDWIDTH, wav)));
+
+        let (tx, rx) = loopback::pair(Channel {
+            snr: None,
+            pacing: Pacing::Fast,
+            ..Channel::default()
+        });
+        tx.set_sample_rate(SAMPLE_RATE).unwrap();
+        tx.set_freq(100_000_000).unwrap();
+        tx.set_txvga_gain(47).unwrap();
+        // The receiver demodulates the channel 900 kHz above its center frequency
+        rx.set_sample_rate(SAMPLE_RATE).unwrap();
+        rx.set_freq(99_100_000).unwrap();
+        rx.set_lna_gain(24).unwrap();
+        rx.set_rxvga_gain(6).unwrap();
+
+        let received = Received::default();
+        rx.start_rx(
+            |_, samples, _, user| {
+                let received = user.downcast_ref::<Received>().unwrap();
+                let mut received = received.lock().unwrap();
+                // Skips the silence until the transmitter starts
+                if received.is_empty() && samples.iter().all(|x| *x == Complex::default()) {
+                    return;
+                }
+                let len = samples.len().min(RECEIVED - received.len());
+                received.extend_from_slice(&samples[..len]);
+            },
+            received.clone(),
+        )
+        .unwrap();
+        tx.start_tx(
+            |_, buffer, _, user| {
+                let modulator = user.downcast_ref::<Arc<Mutex<Modulator>>>().unwrap();
+                let mut modulator = modulator.lock().unwrap();
+                buffer
+                    .iter_mut()
+                    .for_each(|x| *x = modulator.sample().to_i8());
+            },
+            modulator,
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        while received.lock().unwrap().len() < RECEIVED {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "nothing received"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        tx.stop_tx().unwrap();
+        rx.stop_rx().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let iq = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| x.to_f32())
+            .collect::<Vec<_>>();
+        let mut audio = Vec::new();
+        Demodulator::new(-900e3, 1.0, AUDIO_RATE).process(&iq, &mut audio);
+
+        // Counts rising zero crossings after the filters have settled
+        let audio = &audio[audio.len() / 4..];
+        let crossings = audio
+            .windows(2)
+            .filter(|x| x[0] < 0.0 && x[1] >= 0.0)
+            .count();
+        let freq = crossings as f32 * AUDIO_RATE as f32 / audio.len() as f32;
+        assert!((freq - TONE).abs() < 20.0, "{freq} Hz");
+    }
+}
@@ -1,21 +1,29 @@
-use std::{f32::consts::TAU, fs::File, io::BufReader};
+use std::{fs::File, io::BufReader, iter};
 
 use hound::{SampleFormat, WavReader};
+use libhackrf::dsp::{Nco, Resampler};
 use num_complex::Complex;
 
 type Wav = WavReader<BufReader<File>>;
 
+/// Audio samples resampled at a time.
+const AUDIO_BLOCK: usize = 4096;
+
 pub struct Modulator {
-    samples: Box<dyn Iterator<Item = f32>>,
+    samples: Box<dyn Iterator<Item = f32> + Send>,
     audio_sample_rate: u32,
     audio_samples: u32,
     sample_rate: u64,
     bandwidth: f32,
+    /// Resamples the audio to the sample rate of the device.
+    resampler: Resampler<f32>,
 
     i: u64,
-    phase: f32,
-    sample: f32,
-    next_sample: f32,
+    /// Oscillator retuned to the deviation of every sample.
+    nco: Nco,
+    audio: Vec<f32>,
+    resampled: Vec<f32>,
+    position: usize,
 }
 
 impl Modulator {
@@ -24,9 +32,7 @@ impl Modulator {
         let audio_samples = wav.duration();
         let channels = wav.spec().channels;
 
-        assert!(sample_rate >= audio_sample_rate);
-
-        let samples: Box<dyn Iterator<Item = f32>> = match wav.spec().sample_format {
+        let samples: Box<dyn Iterator<Item = f32> + Send> = match wav.spec().sample_format {
             SampleFormat::Float => Box::new(
                 wav.into_samples::<f32>()
                     .map(|x| x.unwrap())
@@ -48,11 +54,13 @@ impl Modulator {
             audio_samples,
             sample_rate: sample_rate as _,
             bandwidth,
+            resampler: Resampler::from_rates(audio_sample_rate, sample_rate),
 
             i: 0,
-            phase: 0.0,
-            sample: 0.0,
-            next_sample: 0.0,
+            nco: Nco::new(0.0, sample_rate as f64),
+            audio: Vec::new(),
+            resampled: Vec::new(),
+            position: 0,
         }
     }
 
@@ -63,24 +71,23 @@ impl Modulator {
     }
 
     pub fn sample(&mut self) -> Complex<f32> {
-        let rate = self.sample_rate / self.audio_sample_rate as u64;
-
-        let sample = self.i % rate;
-        if sample == 0 {
-            self.sample = self.next_sample;
-            self.next_sample = self.samples.next().unwrap_or_default();
+        while self.position >= self.resampled.len() {
+            // Silence follows the end of the file
+            self.audio.clear();
+            let samples = self.samples.by_ref().take(AUDIO_BLOCK);
+            self.audio
+                .extend(samples.chain(iter::repeat(0.0)).take(AUDIO_BLOCK));
+
+            self.resampled.clear();
+            self.resampler.process(&self.audio, &mut self.resampled);
+            self.position = 0;
         }
 
+        let audio = self.resampled[self.position];
+        self.position += 1;
         self.i += 1;
 
-        let t = sample as f32 / rate as f32;
-        let deviation = lerp(self.sample, self.next_sample, t) * self.bandwidth;
-        self.phase += TAU * deviation / self.sample_rate as f32;
-
-        (Complex::i() * self.phase).exp()
+        self.nco.set_freq((audio * self.bandwidth) as f64);
+        self.nco.sample()
     }
 }
-
-fn lerp(a: f32, b: f32, t: f32) -> f32 {
-    a + (b - a) * t
-}
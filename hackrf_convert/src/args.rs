@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use libhackrf::recording::{file::FileFormat, raw::RawFormat, sigmf::DataType, wav::WavFormat};
+
+/// Converts IQ recordings between file formats.
+///
+/// Formats are detected from the file extensions unless given explicitly. Supported formats are
+/// cs8, cu8, cs16, cf32, wav-u8, wav-s16, wav-f32, sigmf-ci8, sigmf-cu8, sigmf-ci16 and
+/// sigmf-cf32. The sample format of WAV and SigMF inputs is always read from the file.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Format of the input file.
+    #[arg(long, value_parser = parse_format)]
+    pub input_format: Option<FileFormat>,
+    /// Format of the output file.
+    #[arg(long, value_parser = parse_format)]
+    pub output_format: Option<FileFormat>,
+
+    /// Sample rate of the input in Hz. Required for headerless inputs.
+    #[arg(short = 'r', long)]
+    pub sample_rate: Option<f64>,
+    /// Center frequency of the input in Hz, if not stored in the file.
+    #[arg(short, long)]
+    pub frequency: Option<u64>,
+
+    /// Shifts the spectrum up by this many Hz before decimating.
+    #[arg(short, long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub shift: f64,
+    /// Low pass filters and keeps one in this many samples.
+    #[arg(short, long, default_value_t = 1)]
+    pub decimate: usize,
+
+    /// Seconds to skip at the start of the input.
+    #[arg(long, default_value_t = 0.0)]
+    pub start: f64,
+    /// Seconds of the input to convert, instead of converting to the end.
+    #[arg(long)]
+    pub duration: Option<f64>,
+
+    /// Path of the file to read.
+    pub input: PathBuf,
+    /// Path of the file to create.
+    pub output: PathBuf,
+}
+
+fn parse_format(format: &str) -> Result<FileFormat, String> {
+    Ok(match format {
+        "wav" | "wav-f32" => FileFormat::Wav(WavFormat::F32),
+        "wav-u8" => FileFormat::Wav(WavFormat::U8),
+        "wav-s16" => FileFormat::Wav(WavFormat::I16),
+        "sigmf" | "sigmf-cf32" => FileFormat::SigMf(DataType::Cf32),
+        "sigmf-ci8" => FileFormat::SigMf(DataType::Ci8),
+        "sigmf-cu8" => FileFormat::SigMf(DataType::Cu8),
+        "sigmf-ci16" => FileFormat::SigMf(DataType::Ci16),
+        format => FileFormat::Raw(
+            RawFormat::from_extension(format).ok_or(format!("unknown format `{format}`"))?,
+        ),
+    })
+}
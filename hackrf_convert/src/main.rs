@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use args::Args;
+use clap::Parser;
+use libhackrf::dsp::{AlignedResampler, Nco, Resampler};
+use libhackrf::recording::file::{FileFormat, IqReader, IqWriter};
+use num_complex::Complex;
+
+mod args;
+
+const BLOCK_SIZE: usize = 65_536;
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.decimate == 0 {
+        bail!("Decimation factor must be at least one");
+    }
+
+    let mut reader = match args.input_format {
+        Some(format) => IqReader::open_as(&args.input, format),
+        None => IqReader::open(&args.input),
+    }
+    .with_context(|| format!("Failed to open {}", args.input.display()))?;
+
+    let sample_rate = match args.sample_rate.or(reader.sample_rate()) {
+        Some(sample_rate) => sample_rate,
+        None => bail!("The input has no sample rate, pass one with --sample-rate"),
+    };
+    let center_freq = args.frequency.or(reader.center_freq());
+
+    let output_format = match args.output_format.or(FileFormat::from_path(&args.output)) {
+        Some(format) => format,
+        None => bail!("Unknown output format, pass one with --output-format"),
+    };
+    let out_freq = center_freq.map(|x| (x as f64 - args.shift).max(0.0) as u64);
+    let mut writer = IqWriter::create(
+        &args.output,
+        output_format,
+        sample_rate / args.decimate as f64,
+        out_freq,
+    )
+    .with_context(|| format!("Failed to create {}", args.output.display()))?;
+
+    let skip = (args.start * sample_rate) as u64;
+    if reader.skip(skip)? < skip {
+        bail!("The input is shorter than the start time");
+    }
+    let mut remaining = args.duration.map_or(u64::MAX, |x| (x * sample_rate) as u64);
+
+    let mut shift = (args.shift != 0.0).then(|| Nco::new(args.shift, sample_rate));
+    let mut decimator =
+        (args.decimate > 1).then(|| AlignedResampler::new(Resampler::decimator(args.decimate)));
+
+    let mut input = vec![Complex::default(); BLOCK_SIZE];
+    let mut output = Vec::new();
+    while remaining > 0 {
+        let len = (remaining as usize).min(BLOCK_SIZE);
+        let read = reader.read(&mut input[..len])?;
+        if read == 0 {
+            break;
+        }
+        remaining -= read as u64;
+
+        let samples = &mut input[..read];
+        if let Some(shift) = &mut shift {
+            shift.mix(samples);
+        }
+
+        match &mut decimator {
+            Some(decimator) => {
+                output.clear();
+                decimator.process(samples, &mut output);
+                writer.write(&output)?;
+            }
+            None => writer.write(samples)?,
+        }
+    }
+
+    if let Some(decimator) = &mut decimator {
+        output.clear();
+        decimator.flush(&mut output);
+        writer.write(&output)?;
+    }
+
+    let samples = writer.samples();
+    writer.finish()?;
+    println!(
+        "Wrote {samples} samples at {} Hz to {}",
+        sample_rate / args.decimate as f64,
+        args.output.display()
+    );
+
+    Ok(())
+}
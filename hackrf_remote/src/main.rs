@@ -0,0 +1,30 @@
+use anyhow::Result;
+use clap::Parser;
+use libhackrf::{remote::RemoteServer, HackRf};
+
+/// Serves a HackRF to remote clients.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Address to listen on.
+    #[arg(short, long, default_value = "127.0.0.1:1235")]
+    address: String,
+    /// Only accept clients that send this token.
+    #[arg(short, long)]
+    token: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let hackrf = HackRf::open()?;
+    let mut server = RemoteServer::bind(hackrf, &args.address)?;
+    if let Some(token) = args.token {
+        server = server.with_token(token);
+    }
+
+    println!("Listening on {}", server.local_addr()?);
+    server.serve()?;
+
+    Ok(())
+}
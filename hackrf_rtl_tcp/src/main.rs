@@ -0,0 +1,32 @@
+use anyhow::Result;
+use clap::Parser;
+use libhackrf::{rtl_tcp::RtlTcpServer, HackRf};
+
+/// Serves a HackRF to rtl_tcp clients.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Address to listen on.
+    #[arg(short, long, default_value = "127.0.0.1:1234")]
+    address: String,
+    /// Center frequency until a client sets one.
+    #[arg(short, long, default_value_t = 100_000_000)]
+    frequency: u64,
+    /// Sample rate until a client sets one.
+    #[arg(short, long, default_value_t = 2_048_000)]
+    sample_rate: u32,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let hackrf = HackRf::open()?;
+    hackrf.set_sample_rate(args.sample_rate)?;
+    hackrf.set_freq(args.frequency)?;
+
+    let server = RtlTcpServer::bind(hackrf, &args.address)?;
+    println!("Listening on {}", server.local_addr()?);
+    server.serve()?;
+
+    Ok(())
+}
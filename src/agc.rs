@@ -0,0 +1,289 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    stats::{StreamMode, StreamStats},
+    HackRf,
+};
+
+/// Power of a full scale complex tone in 8-bit units.
+const FULL_SCALE_POWER: f64 = 127.0 * 127.0;
+/// Highest total gain of the LNA and VGA in dB.
+pub const MAX_GAIN: u32 = 102;
+
+/// Adjusts the LNA and VGA gains of a receive stream to keep the level of the samples near a
+/// target, and backs off when the converter clips. Set with [`HackRf::set_agc`].
+///
+/// The gain is changed at most once per interval and only when the level leaves the band of
+/// `hysteresis` around the target, so it does not hunt between neighbouring steps. The interval
+/// after a change is not measured, so samples received before the new gain settled do not count.
+/// The total gain is split with [`split_gain`], which fills the LNA first for the best noise
+/// figure.
+#[derive(Clone, Copy)]
+pub struct HardwareAgc {
+    /// Target mean power of the samples in dBFS, where a full scale complex tone is 0 dBFS.
+    pub target: f32,
+    /// Distance from the target in dB within which the gain is left alone.
+    pub hysteresis: f32,
+    /// Fraction of clipped samples above which the gain is reduced whatever the level.
+    pub max_clipping: f64,
+    /// Largest change of the total gain in dB per adjustment.
+    pub max_step: u32,
+    /// Lowest total gain in dB.
+    pub min_gain: u32,
+    /// Highest total gain in dB, at most [`MAX_GAIN`].
+    pub max_gain: u32,
+    /// Time between adjustments, over which the level is measured.
+    pub interval: Duration,
+    /// Called after the gains were changed.
+    pub on_change: Option<fn(hack_rf: &HackRf, lna_gain: u32, vga_gain: u32)>,
+}
+
+impl HardwareAgc {
+    /// Creates an AGC that keeps the level of the samples near `target` dBFS, such as -18 to
+    /// leave headroom for peaks.
+    pub fn new(target: f32) -> Self {
+        Self {
+            target,
+            hysteresis: 4.0,
+            max_clipping: 1e-4,
+            max_step: 12,
+            min_gain: 0,
+            max_gain: MAX_GAIN,
+            interval: Duration::from_millis(250),
+            on_change: None,
+        }
+    }
+
+    /// Change of the total gain in dB for a measured mean power in dBFS and fraction of clipped
+    /// samples. Always even, as that is the resolution of the VGA.
+    pub fn adjustment(&self, level: f32, clipping: f64) -> i32 {
+        let max_step = self.max_step as i32;
+        if clipping > self.max_clipping {
+            return -max_step / 2 * 2;
+        }
+
+        let error = self.target - level;
+        if error.abs() <= self.hysteresis {
+            return 0;
+        }
+
+        let step = (error / 2.0).round() as i32 * 2;
+        step.clamp(-max_step, max_step) / 2 * 2
+    }
+
+    pub(crate) fn spawn(self, hackrf: HackRf) {
+        self.spawn_while(hackrf, Arc::new(AtomicBool::new(true)));
+    }
+
+    /// Adjusts the gains of the current receive stream until it stops or `enabled` is cleared.
+    /// The level of the stream is measured from then on.
+    pub(crate) fn spawn_while(self, hackrf: HackRf, enabled: Arc<AtomicBool>) {
+        let Some(stats) = hackrf.stream_stats() else {
+            return;
+        };
+        if stats.mode() != StreamMode::Receive {
+            return;
+        }
+        stats.set_measure_level(true);
+
+        let config = hackrf.config();
+        let hackrf = hackrf.downgrade();
+        thread::spawn(move || {
+            let gain = config.lna_gain.unwrap_or_default() + config.rxvga_gain.unwrap_or_default();
+            let mut gain = gain.clamp(self.min_gain, self.max_gain.min(MAX_GAIN)) as i32;
+
+            let mut last = Level::read(&stats);
+            let mut settling = false;
+            while stats.is_running() {
+                thread::sleep(self.interval);
+                if !enabled.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Some(hackrf) = hackrf.upgrade() else {
+                    break;
+                };
+
+                let now = Level::read(&stats);
+                let previous = std::mem::replace(&mut last, now);
+                let samples = now.samples - previous.samples;
+                // Samples received while the last change settles are not measured
+                if std::mem::take(&mut settling) || samples == 0 {
+                    continue;
+                }
+
+                let power = (now.power - previous.power) as f64 / samples as f64;
+                let level = 10.0 * (power / FULL_SCALE_POWER).max(1e-12).log10();
+                let clipped = now.clipped_samples - previous.clipped_samples;
+                let clipping = clipped as f64 / samples as f64;
+
+                let max_gain = self.max_gain.min(MAX_GAIN) as i32;
+                let new_gain = (gain + self.adjustment(level as f32, clipping))
+                    .clamp(self.min_gain as i32, max_gain);
+                if new_gain == gain {
+                    continue;
+                }
+
+                let (lna, vga) = split_gain(new_gain as u32);
+                if hackrf.set_lna_gain(lna).is_err() || hackrf.set_rxvga_gain(vga).is_err() {
+                    continue;
+                }
+                gain = new_gain;
+
+                if let Some(callback) = self.on_change {
+                    callback(&hackrf, lna, vga);
+                }
+                settling = true;
+            }
+        });
+    }
+}
+
+/// Splits a total gain in dB into the LNA gain (0-40 dB in steps of 8) and VGA gain
+/// (0-62 dB in steps of 2), filling the LNA first.
+pub fn split_gain(gain: u32) -> (u32, u32) {
+    let lna = gain.min(40) / 8 * 8;
+    let vga = (gain - lna).min(62) / 2 * 2;
+    (lna, vga)
+}
+
+/// Level counters of a stream at one point in time.
+#[derive(Clone, Copy)]
+struct Level {
+    samples: u64,
+    power: u64,
+    clipped_samples: u64,
+}
+
+impl Level {
+    fn read(stats: &StreamStats) -> Self {
+        Self {
+            samples: stats.measured_samples(),
+            power: stats.power(),
+            clipped_samples: stats.clipped_samples(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::HardwareAgc;
+    use crate::{sim::Simulator, Config, HackRf};
+
+    /// Receives a carrier at `gain - 40` dBFS for the total gain of the LNA and VGA, which
+    /// clips from 40 dB on. Changes of the gain take effect a transfer late, as if settling.
+    struct Antenna {
+        lna_gain: u32,
+        settled: Mutex<Option<u32>>,
+    }
+
+    impl Simulator for Antenna {
+        fn initial_config(&self) -> Config {
+            Config {
+                sample_rate: Some(20_000_000),
+                lna_gain: Some(self.lna_gain),
+                rxvga_gain: Some(0),
+                ..Config::default()
+            }
+        }
+
+        fn receive(&self, samples: &mut [Complex<i8>], config: &Config) -> bool {
+            let new_gain =
+                config.lna_gain.unwrap_or_default() + config.rxvga_gain.unwrap_or_default();
+            let gain = self
+                .settled
+                .lock()
+                .unwrap()
+                .replace(new_gain)
+                .unwrap_or(new_gain);
+            let amplitude = 127.0 * 10f64.powf((gain as f64 - 40.0) / 20.0);
+            samples.fill(Complex::new(amplitude.round().min(127.0) as i8, 0));
+            true
+        }
+    }
+
+    /// Runs the AGC on a receive stream until it made `changes` changes of the gains, then for
+    /// a few more intervals, and returns the gains it set.
+    fn run(
+        agc: HardwareAgc,
+        lna_gain: u32,
+        changes: usize,
+        recorded: &'static Mutex<Vec<(u32, u32)>>,
+    ) -> (HackRf, Vec<(u32, u32)>) {
+        let hackrf = HackRf::simulated(Antenna {
+            lna_gain,
+            settled: Mutex::new(None),
+        });
+        hackrf.set_agc(Some(agc));
+        hackrf.start_rx(|_, _, _, _| {}, ()).unwrap();
+
+        let start = Instant::now();
+        while recorded.lock().unwrap().len() < changes {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(agc.interval * 5);
+        hackrf.stop_rx().unwrap();
+
+        let recorded = recorded.lock().unwrap().clone();
+        (hackrf, recorded)
+    }
+
+    fn agc(target: f32) -> HardwareAgc {
+        HardwareAgc {
+            interval: Duration::from_millis(50),
+            ..HardwareAgc::new(target)
+        }
+    }
+
+    #[test]
+    fn backs_off_once_from_clipping() {
+        static CHANGES: Mutex<Vec<(u32, u32)>> = Mutex::new(Vec::new());
+        let agc = HardwareAgc {
+            on_change: Some(|_, lna, vga| CHANGES.lock().unwrap().push((lna, vga))),
+            ..agc(-12.0)
+        };
+
+        // The first step ends the clipping and lands within the hysteresis of the target, so
+        // a second change would mean clipped samples received while it settled were measured
+        let (hackrf, changes) = run(agc, 40, 1, &CHANGES);
+        assert_eq!(changes, [(24, 4)]);
+        assert_eq!(hackrf.config().lna_gain, Some(24));
+        assert_eq!(hackrf.config().rxvga_gain, Some(4));
+    }
+
+    #[test]
+    fn stays_within_gain_limits() {
+        static RAISED: Mutex<Vec<(u32, u32)>> = Mutex::new(Vec::new());
+        let agc_max = HardwareAgc {
+            max_gain: 20,
+            on_change: Some(|_, lna, vga| RAISED.lock().unwrap().push((lna, vga))),
+            ..agc(-12.0)
+        };
+        let (_, changes) = run(agc_max, 0, 2, &RAISED);
+        assert_eq!(changes, [(8, 4), (16, 4)]);
+
+        static LOWERED: Mutex<Vec<(u32, u32)>> = Mutex::new(Vec::new());
+        let agc_min = HardwareAgc {
+            min_gain: 34,
+            on_change: Some(|_, lna, vga| LOWERED.lock().unwrap().push((lna, vga))),
+            ..agc(-12.0)
+        };
+        let (_, changes) = run(agc_min, 40, 1, &LOWERED);
+        assert_eq!(changes, [(32, 2)]);
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, HackRf};
+
+/// The last configuration applied to a device through [`HackRf`].
+/// Values are `None` until they have been set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// Center frequency in Hz.
+    pub freq: Option<u64>,
+    /// Sample rate in Hz.
+    pub sample_rate: Option<u32>,
+    /// State of the external amplifier.
+    pub amp_enable: Option<bool>,
+    /// Low noise amplifier gain in dB.
+    pub lna_gain: Option<u32>,
+    /// Receive variable gain amplifier gain in dB.
+    pub rxvga_gain: Option<u32>,
+    /// Transmit variable gain amplifier gain in dB.
+    pub txvga_gain: Option<u32>,
+    /// Baseband filter bandwidth in Hz.
+    pub baseband_filter_bandwidth: Option<u32>,
+}
+
+impl Config {
+    /// Applies every value that has been set to the device.
+    pub fn apply(&self, hackrf: &HackRf) -> Result<()> {
+        if let Some(sample_rate) = self.sample_rate {
+            hackrf.set_sample_rate(sample_rate)?;
+        }
+        if let Some(bandwidth) = self.baseband_filter_bandwidth {
+            hackrf.set_baseband_filter_bandwidth(bandwidth)?;
+        }
+        if let Some(freq) = self.freq {
+            hackrf.set_freq(freq)?;
+        }
+        if let Some(enable) = self.amp_enable {
+            hackrf.set_amp_enable(enable)?;
+        }
+        if let Some(gain) = self.lna_gain {
+            hackrf.set_lna_gain(gain)?;
+        }
+        if let Some(gain) = self.rxvga_gain {
+            hackrf.set_rxvga_gain(gain)?;
+        }
+        if let Some(gain) = self.txvga_gain {
+            hackrf.set_txvga_gain(gain)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use super::FilterSample;
+
+/// Digital automatic gain control, which scales a signal so its envelope stays at a target
+/// amplitude, such as to normalize the output of a channel filter before demodulation.
+///
+/// The envelope follows increases within the attack time and decreases within the decay time,
+/// so a short attack keeps strong bursts from overshooting while a long decay keeps the gain
+/// steady through fades.
+#[derive(Debug, Clone)]
+pub struct Agc<T> {
+    target: f32,
+    max_gain: f32,
+    attack: f32,
+    decay: f32,
+    envelope: Option<f32>,
+    _sample: std::marker::PhantomData<T>,
+}
+
+impl<T: FilterSample> Agc<T> {
+    /// Creates an AGC with attack and decay time constants in seconds.
+    pub fn new(sample_rate: u32, target: f32, attack_time: f32, decay_time: f32) -> Self {
+        let alpha = |time: f32| 1.0 - (-1.0 / (time * sample_rate as f32).max(1.0)).exp();
+        Self {
+            target,
+            max_gain: 1e4,
+            attack: alpha(attack_time),
+            decay: alpha(decay_time),
+            envelope: None,
+            _sample: std::marker::PhantomData,
+        }
+    }
+
+    /// Limits the gain, so silence and noise are not amplified without bound.
+    pub fn with_max_gain(mut self, max_gain: f32) -> Self {
+        self.max_gain = max_gain;
+        self
+    }
+
+    /// The gain applied to the last sample.
+    pub fn gain(&self) -> f32 {
+        match self.envelope {
+            Some(envelope) => (self.target / envelope).min(self.max_gain),
+            None => 1.0,
+        }
+    }
+
+    /// Forgets the envelope. The next sample is taken as the initial envelope.
+    pub fn reset(&mut self) {
+        self.envelope = None;
+    }
+
+    pub fn filter(&mut self, value: T) -> T {
+        let magnitude = value.magnitude();
+        let envelope = self.envelope.unwrap_or(magnitude);
+        let alpha = match magnitude > envelope {
+            true => self.attack,
+            false => self.decay,
+        };
+        self.envelope = Some(envelope + (magnitude - envelope) * alpha);
+        value * self.gain()
+    }
+
+    /// Filters a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[T], output: &mut [T]) {
+        assert_eq!(input.len(), output.len());
+        for (out, &value) in output.iter_mut().zip(input) {
+            *out = self.filter(value);
+        }
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [T]) {
+        for value in samples.iter_mut() {
+            *value = self.filter(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::Agc;
+
+    #[test]
+    fn normalizes_level_changes() {
+        let sample_rate = 48_000;
+        let mut samples = (0..96_000)
+            .map(|n| {
+                let amplitude = if n < 48_000 { 0.01 } else { 0.8 };
+                Complex::from_polar(amplitude, TAU * 1_000.0 * n as f32 / 48e3)
+            })
+            .collect::<Vec<_>>();
+
+        let mut agc = Agc::new(sample_rate, 0.5, 0.001, 0.1);
+        agc.process_in_place(&mut samples);
+
+        for range in [10_000..48_000, 49_000..96_000] {
+            for sample in &samples[range] {
+                assert!((sample.norm() - 0.5).abs() < 0.01);
+            }
+        }
+        // The attack is fast enough that the jump does not overshoot for long
+        assert!(samples[48_500].norm() < 0.6);
+    }
+
+    #[test]
+    fn limits_gain() {
+        let mut agc = Agc::new(1_000, 1.0, 0.01, 0.01).with_max_gain(10.0);
+        let mut samples = [1e-4f32; 1_000];
+        agc.process_in_place(&mut samples);
+        assert_eq!(agc.gain(), 10.0);
+        assert!((samples[999] - 1e-3).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,442 @@
+//! Splitting a wideband signal into many narrowband channels at once.
+//!
+//! [`PfbChannelizer`] is a polyphase filter bank for channels evenly spaced over the whole band,
+//! which costs about one FIR filter and one FFT for all of them. [`FftChannelizer`] uses
+//! overlap-save fast convolution for channels at arbitrary offsets, each with its own bandwidth
+//! and decimation.
+
+use std::sync::Arc;
+
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use super::{fir, Nco, Window};
+
+/// Stopband attenuation in dB of the designed channel filters.
+const ATTENUATION: f64 = 60.0;
+
+/// Splits a signal sampled at `fs` into `M` channels spaced `fs / M` apart, each decimated by `M`.
+///
+/// Channel `k` is centered on `k * fs / M`, so channels above `M / 2` hold the negative
+/// frequencies as in an FFT. The output of every channel is equivalent to mixing it to zero,
+/// filtering with the prototype low pass filter and keeping every `M`th sample.
+pub struct PfbChannelizer {
+    channels: usize,
+    /// The prototype filter split into `channels` branches, where branch `p` holds the taps
+    /// `p, p + M, p + 2M, ...`.
+    branches: Vec<Vec<f32>>,
+    fft: Arc<dyn Fft<f32>>,
+    scratch: Vec<Complex<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    /// The input samples needed by the next output, followed by the rest of the block.
+    buffer: Vec<Complex<f32>>,
+    /// Index in `buffer` of the newest input sample of the next output.
+    position: usize,
+}
+
+/// A channel of an [`FftChannelizer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSpec {
+    /// Center of the channel in Hz, relative to the center of the input.
+    pub offset: f64,
+    /// Width of the passband in Hz.
+    pub bandwidth: f32,
+    /// Factor the sample rate is reduced by, which must divide a quarter of the FFT size.
+    pub decimation: usize,
+}
+
+/// Extracts channels at arbitrary offsets with overlap-save fast convolution.
+///
+/// Each FFT of the input is shared by all channels. A channel takes the bins around its offset,
+/// applies its filter, folds the spectrum to decimate and transforms back with a smaller inverse
+/// FFT. The remainder of the offset that does not fall on a bin is removed with an [`Nco`] at the
+/// output rate.
+pub struct FftChannelizer {
+    fft: Arc<dyn Fft<f32>>,
+    scratch: Vec<Complex<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    channels: Vec<Channel>,
+    /// The last `overlap` samples, followed by the new samples of the next transform.
+    buffer: Vec<Complex<f32>>,
+    /// Index of the first sample of the next transform within the stream, modulo the FFT size.
+    start: usize,
+}
+
+struct Channel {
+    /// Bin the channel is centered on.
+    bin: usize,
+    decimation: usize,
+    /// Frequency response of the filter, scaled for the inverse FFT.
+    response: Vec<Complex<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    folded: Vec<Complex<f32>>,
+    nco: Nco,
+}
+
+/// Designs a prototype filter for a [`PfbChannelizer`] with `taps_per_channel` taps per branch,
+/// with its cutoff at the edge of the channels.
+pub fn design(channels: usize, taps_per_channel: usize) -> Vec<f32> {
+    let taps = (channels * taps_per_channel) | 1;
+    fir::low_pass(channels as u32, 0.5, taps, Window::kaiser(ATTENUATION))
+}
+
+impl PfbChannelizer {
+    /// Creates a channelizer with a prototype filter from [`design`] with 12 taps per branch.
+    pub fn new(channels: usize) -> Self {
+        Self::with_taps(channels, &design(channels, 12))
+    }
+
+    /// Creates a channelizer with a prototype low pass filter with a gain of one at DC.
+    ///
+    /// # Panics
+    /// If `channels` is zero or `taps` is empty.
+    pub fn with_taps(channels: usize, taps: &[f32]) -> Self {
+        assert!(channels > 0, "channelizers need at least one channel");
+        assert!(!taps.is_empty(), "filters must have at least one tap");
+
+        let len = taps.len().div_ceil(channels);
+        let branches = (0..channels)
+            .map(|p| {
+                (0..len)
+                    .map(|l| taps.get(l * channels + p).copied().unwrap_or(0.0))
+                    .collect()
+            })
+            .collect();
+
+        let fft = FftPlanner::new().plan_fft_inverse(channels);
+        let history = len * channels - 1;
+        Self {
+            channels,
+            branches,
+            scratch: vec![Complex::default(); fft.get_inplace_scratch_len()],
+            fft,
+            spectrum: vec![Complex::default(); channels],
+            buffer: vec![Complex::default(); history],
+            position: history,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Center of a channel in Hz relative to the center of the input.
+    pub fn channel_offset(&self, channel: usize, sample_rate: u32) -> f64 {
+        let channel = match channel > self.channels / 2 {
+            true => channel as f64 - self.channels as f64,
+            false => channel as f64,
+        };
+        channel * sample_rate as f64 / self.channels as f64
+    }
+
+    /// Clears the history.
+    pub fn reset(&mut self) {
+        let history = self.branches[0].len() * self.channels - 1;
+        self.buffer.clear();
+        self.buffer.resize(history, Complex::default());
+        self.position = history;
+    }
+
+    /// Appends the output of every channel to the matching vector of `outputs`.
+    ///
+    /// # Panics
+    /// If there is not one output per channel.
+    pub fn process(&mut self, input: &[Complex<f32>], outputs: &mut [Vec<Complex<f32>>]) {
+        assert_eq!(outputs.len(), self.channels);
+        let history = self.branches[0].len() * self.channels - 1;
+        self.buffer.extend_from_slice(input);
+
+        while self.position < self.buffer.len() {
+            let newest = self.position;
+            for (p, (branch, out)) in self.branches.iter().zip(&mut self.spectrum).enumerate() {
+                *out = branch
+                    .iter()
+                    .enumerate()
+                    .map(|(l, &tap)| self.buffer[newest - l * self.channels - p] * tap)
+                    .sum();
+            }
+
+            // The inverse transform rotates every branch by the phase of each channel
+            self.fft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+            for (output, &sample) in outputs.iter_mut().zip(&self.spectrum) {
+                output.push(sample);
+            }
+            self.position += self.channels;
+        }
+
+        let consumed = (self.position - history).min(self.buffer.len());
+        self.buffer.drain(..consumed);
+        self.position -= consumed;
+    }
+}
+
+impl FftChannelizer {
+    /// Creates a channelizer for a signal at `sample_rate`. A quarter of each transform overlaps
+    /// with the previous one, which limits the channel filters to `fft_size / 4 + 1` taps, so
+    /// narrower transition bands need larger transforms.
+    ///
+    /// # Panics
+    /// If `fft_size` is not a multiple of four, a decimation does not divide `fft_size / 4`,
+    /// a channel is wider than its output sample rate or its filter does not fit.
+    pub fn new(sample_rate: u32, fft_size: usize, channels: &[ChannelSpec]) -> Self {
+        assert!(
+            fft_size.is_multiple_of(4),
+            "the FFT size must be a multiple of four"
+        );
+        let overlap = fft_size / 4;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+
+        let channels = channels
+            .iter()
+            .map(|spec| {
+                let decimation = spec.decimation;
+                assert!(
+                    decimation > 0 && overlap.is_multiple_of(decimation),
+                    "decimations must divide a quarter of the FFT size"
+                );
+
+                // Pass the channel and attenuate everything that would alias into it
+                let output_rate = sample_rate as f32 / decimation as f32;
+                let stopband = output_rate / 2.0;
+                let passband = spec.bandwidth / 2.0;
+                assert!(
+                    passband < stopband,
+                    "channels must be narrower than their output"
+                );
+                let taps = fir::kaiser_taps(sample_rate, stopband - passband, ATTENUATION);
+                assert!(
+                    taps <= overlap + 1,
+                    "the FFT size is too small for the channel filter"
+                );
+                let taps = fir::low_pass(
+                    sample_rate,
+                    (passband + stopband) / 2.0,
+                    taps,
+                    Window::kaiser(ATTENUATION),
+                );
+
+                let mut response = vec![Complex::default(); fft_size];
+                for (out, &tap) in response.iter_mut().zip(&taps) {
+                    *out = Complex::new(tap / fft_size as f32, 0.0);
+                }
+                fft.process(&mut response);
+
+                let bin_width = sample_rate as f64 / fft_size as f64;
+                let bin = (spec.offset / bin_width).round();
+                let residual = spec.offset - bin * bin_width;
+
+                Channel {
+                    bin: (bin as i64).rem_euclid(fft_size as i64) as usize,
+                    decimation,
+                    response,
+                    ifft: planner.plan_fft_inverse(fft_size / decimation),
+                    folded: vec![Complex::default(); fft_size / decimation],
+                    nco: Nco::new(-residual, output_rate as f64),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let scratch = channels
+            .iter()
+            .map(|x| x.ifft.get_inplace_scratch_len())
+            .chain([fft.get_inplace_scratch_len()])
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            scratch: vec![Complex::default(); scratch],
+            fft,
+            spectrum: vec![Complex::default(); fft_size],
+            channels,
+            buffer: vec![Complex::default(); overlap],
+            start: 0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn fft_size(&self) -> usize {
+        self.spectrum.len()
+    }
+
+    /// Clears the history.
+    pub fn reset(&mut self) {
+        let overlap = self.fft_size() / 4;
+        self.buffer.clear();
+        self.buffer.resize(overlap, Complex::default());
+        self.start = 0;
+        for channel in &mut self.channels {
+            channel.nco.set_phase(0.0);
+        }
+    }
+
+    /// Appends the output of every channel to the matching vector of `outputs`.
+    ///
+    /// # Panics
+    /// If there is not one output per channel.
+    pub fn process(&mut self, input: &[Complex<f32>], outputs: &mut [Vec<Complex<f32>>]) {
+        assert_eq!(outputs.len(), self.channels.len());
+        let size = self.fft_size();
+        let overlap = size / 4;
+        let step = size - overlap;
+
+        self.buffer.extend_from_slice(input);
+        let mut offset = 0;
+        while offset + size <= self.buffer.len() {
+            self.spectrum
+                .copy_from_slice(&self.buffer[offset..offset + size]);
+            self.fft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+
+            for (channel, output) in self.channels.iter_mut().zip(outputs.iter_mut()) {
+                channel.extract(&self.spectrum, self.start, &mut self.scratch, output);
+            }
+
+            self.start = (self.start + step) % size;
+            offset += step;
+        }
+        self.buffer.drain(..offset);
+    }
+}
+
+impl Channel {
+    /// Filters, decimates and mixes the channel out of the spectrum of a transform starting at
+    /// `start` modulo the FFT size.
+    fn extract(
+        &mut self,
+        spectrum: &[Complex<f32>],
+        start: usize,
+        scratch: &mut [Complex<f32>],
+        output: &mut Vec<Complex<f32>>,
+    ) {
+        let size = spectrum.len();
+        let folded_len = self.folded.len();
+
+        // Shifting the bins mixes relative to the start of the transform, so the rotation of the
+        // whole block is undone to keep the phase continuous across transforms
+        let rotation = (self.bin * start) % size;
+        let rotation =
+            Complex::from_polar(1.0, -std::f32::consts::TAU * rotation as f32 / size as f32);
+
+        self.folded.fill(Complex::default());
+        for (k, &response) in self.response.iter().enumerate() {
+            let bin = (k + self.bin) % size;
+            self.folded[k % folded_len] += spectrum[bin] * response;
+        }
+        self.folded.iter_mut().for_each(|x| *x *= rotation);
+
+        let scratch = &mut scratch[..self.ifft.get_inplace_scratch_len()];
+        self.ifft.process_with_scratch(&mut self.folded, scratch);
+
+        // The first outputs are corrupted by the circular convolution
+        let overlap = size / 4 / self.decimation;
+        let start = output.len();
+        output.extend_from_slice(&self.folded[overlap..]);
+        self.nco.mix(&mut output[start..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::{ChannelSpec, FftChannelizer, PfbChannelizer};
+    use crate::dsp::{assert_chunks_match, test_signal};
+
+    fn tone(freq: f32, sample_rate: f32, len: usize) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| {
+                let turns = (freq as f64 * n as f64 / sample_rate as f64).fract();
+                Complex::from_polar(1.0, TAU * turns as f32)
+            })
+            .collect()
+    }
+
+    /// Amplitude of a frequency in the signal.
+    fn amplitude(signal: &[Complex<f32>], freq: f32, sample_rate: f32) -> f32 {
+        let reference = tone(-freq, sample_rate, signal.len());
+        let sum = signal.iter().zip(&reference).map(|(a, b)| a * b);
+        (sum.sum::<Complex<f32>>() / signal.len() as f32).norm()
+    }
+
+    #[test]
+    fn pfb_separates_channels() {
+        let (sample_rate, channels) = (800_000.0, 8);
+        let mut channelizer = PfbChannelizer::new(channels);
+        assert_eq!(channelizer.channel_offset(6, 800_000), -200_000.0);
+
+        // A tone 10 kHz above the center of channel 6, at -200 kHz
+        let input = tone(-190_000.0, sample_rate, 80_000);
+        let mut outputs = vec![Vec::new(); channels];
+        channelizer.process(&input, &mut outputs);
+
+        let output_rate = sample_rate / channels as f32;
+        for (channel, output) in outputs.iter().enumerate() {
+            assert_eq!(output.len(), 10_000);
+            let power = output[100..].iter().map(|x| x.norm_sqr()).sum::<f32>() / 9_900.0;
+            match channel {
+                6 => {
+                    assert!((amplitude(&output[100..], 10_000.0, output_rate) - 1.0).abs() < 1e-2)
+                }
+                _ => assert!(power < 1e-5, "{channel}: {power}"),
+            }
+        }
+    }
+
+    #[test]
+    fn pfb_blocks_match_whole_signal() {
+        assert_chunks_match(
+            || PfbChannelizer::new(5),
+            &test_signal(4_000),
+            13,
+            vec![Vec::new(); 5],
+            |channelizer, input, outputs| channelizer.process(input, outputs),
+        );
+    }
+
+    #[test]
+    fn fft_extracts_arbitrary_channels() {
+        let sample_rate = 2_000_000;
+        let channels = [
+            ChannelSpec {
+                offset: 312_345.0,
+                bandwidth: 12_500.0,
+                decimation: 64,
+            },
+            ChannelSpec {
+                offset: -500_000.0,
+                bandwidth: 100_000.0,
+                decimation: 8,
+            },
+        ];
+        let mut channelizer = FftChannelizer::new(sample_rate, 4096, &channels);
+
+        // A tone 2 kHz above the first channel and one far outside of both
+        let input = tone(314_345.0, 2e6, 400_000)
+            .iter()
+            .zip(tone(-700_000.0, 2e6, 400_000))
+            .map(|(a, b)| a + b)
+            .collect::<Vec<_>>();
+        let mut outputs = vec![Vec::new(); 2];
+        for block in input.chunks(10_000) {
+            channelizer.process(block, &mut outputs);
+        }
+
+        // The tone is continuous across transforms
+        let output = &outputs[0][100..];
+        let step = Complex::from_polar(1.0, TAU * 2_000.0 / 31_250.0);
+        for pair in output.windows(2) {
+            assert!((pair[1] - pair[0] * step).norm() < 1e-2);
+        }
+        assert!((amplitude(output, 2_000.0, 31_250.0) - 1.0).abs() < 1e-2);
+
+        let power = outputs[1][100..].iter().map(|x| x.norm_sqr()).sum::<f32>();
+        assert!(power / ((outputs[1].len() - 100) as f32) < 1e-5);
+    }
+}
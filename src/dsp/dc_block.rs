@@ -0,0 +1,87 @@
+use std::f32::consts::TAU;
+
+use super::FilterSample;
+
+/// Removes the DC offset of a signal by subtracting a running estimate of its mean, such as the
+/// spike a direct conversion receiver leaves at the center frequency.
+///
+/// This is a single pole high pass filter with a gain of one above the cutoff, so the cutoff
+/// should be well below the lowest frequency of interest.
+#[derive(Debug, Clone)]
+pub struct DcBlocker<T> {
+    alpha: f32,
+    offset: Option<T>,
+}
+
+impl<T: FilterSample> DcBlocker<T> {
+    pub fn new(sample_rate: u32, cutoff_freq: f32) -> Self {
+        Self {
+            alpha: 1.0 - (-TAU * cutoff_freq / sample_rate as f32).exp(),
+            offset: None,
+        }
+    }
+
+    /// The estimated DC offset, which is zero before the first sample.
+    pub fn offset(&self) -> T {
+        self.offset.unwrap_or_default()
+    }
+
+    /// Forgets the estimate. The next sample is taken as the initial offset, so a constant
+    /// signal is removed immediately rather than decaying.
+    pub fn reset(&mut self) {
+        self.offset = None;
+    }
+
+    pub fn filter(&mut self, value: T) -> T {
+        let offset = self.offset.unwrap_or(value);
+        self.offset = Some(offset + (value - offset) * self.alpha);
+        value - offset
+    }
+
+    /// Filters a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[T], output: &mut [T]) {
+        assert_eq!(input.len(), output.len());
+        for (out, &value) in output.iter_mut().zip(input) {
+            *out = self.filter(value);
+        }
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [T]) {
+        for value in samples.iter_mut() {
+            *value = self.filter(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::DcBlocker;
+
+    #[test]
+    fn removes_offset_and_keeps_signal() {
+        let sample_rate = 2_000_000;
+        let dc = Complex::new(0.2, -0.15);
+        let mut samples = (0..200_000)
+            .map(|n| Complex::from_polar(0.5, TAU * 10_000.0 * n as f32 / 2e6) + dc)
+            .collect::<Vec<_>>();
+
+        let mut blocker = DcBlocker::new(sample_rate, 20.0);
+        blocker.process_in_place(&mut samples);
+        assert!((blocker.offset() - dc).norm() < 2e-3);
+
+        // After settling only the tone is left
+        let settled = &samples[100_000..];
+        let mean = settled.iter().sum::<Complex<f32>>() / settled.len() as f32;
+        assert!(mean.norm() < 1e-3);
+        for sample in settled {
+            assert!((sample.norm() - 0.5).abs() < 5e-3);
+        }
+    }
+}
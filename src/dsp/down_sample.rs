@@ -0,0 +1,72 @@
+/// Reduces the sample rate by dropping samples, keeping every `in / out`th sample on average.
+/// Does not filter, so the signal should be low pass filtered first to avoid aliasing.
+#[derive(Debug, Clone)]
+pub struct DownSample {
+    step_by: f64,
+    error: f64,
+}
+
+impl DownSample {
+    pub fn new(in_sample_rate: u32, out_sample_rate: u32) -> Self {
+        Self {
+            step_by: in_sample_rate as f64 / out_sample_rate as f64,
+            error: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.error = 0.0;
+    }
+
+    /// Appends the kept samples of a block to `output`.
+    pub fn process<T: Copy>(&mut self, input: &[T], output: &mut Vec<T>) {
+        for &value in input {
+            self.error += 1.0;
+            if self.error >= self.step_by {
+                self.error -= self.step_by;
+                output.push(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DownSample;
+    use crate::dsp::assert_chunks_match;
+
+    #[test]
+    fn integer_ratio() {
+        let input = (0..20).collect::<Vec<u32>>();
+        let mut output = Vec::new();
+        DownSample::new(4, 1).process(&input, &mut output);
+        assert_eq!(output, [3, 7, 11, 15, 19]);
+    }
+
+    #[test]
+    fn fractional_ratio() {
+        // Sample n is kept whenever floor((n + 1) / step) increases
+        let input = (0..10_000).collect::<Vec<u32>>();
+        let mut output = Vec::new();
+        DownSample::new(2_000_000, 44_100).process(&input, &mut output);
+
+        let step = 2_000_000.0 / 44_100.0;
+        let expected = (0..10_000u32)
+            .filter(|&n| ((n + 1) as f64 / step).floor() > (n as f64 / step).floor())
+            .collect::<Vec<_>>();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        let input = (0..10_000).collect::<Vec<u32>>();
+
+        assert_chunks_match(
+            || DownSample::new(2_000_000, 44_100),
+            &input,
+            313,
+            Vec::new(),
+            DownSample::process,
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! Windowed-sinc FIR filter design and a FIR filter for real and complex signals.
+//!
+//! Frequencies are given in Hz along with the sample rate. Designed filters have an odd number
+//! of symmetric taps, so they have linear phase and a delay of `(taps - 1) / 2` samples.
+
+use std::f64::consts::{PI, TAU};
+
+use num_complex::Complex;
+
+use super::FilterSample;
+
+/// Window functions applied to the ideal sinc response to limit its length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    /// No tapering, for the narrowest main lobe at the cost of high sidelobes.
+    Rectangular,
+    /// About 44 dB of stopband attenuation, commonly used for spectral analysis.
+    Hann,
+    /// About 53 dB of stopband attenuation.
+    Hamming,
+    /// About 74 dB of stopband attenuation, with a wider transition than [`Window::Hamming`].
+    Blackman,
+    /// Adjustable tradeoff between transition width and stopband attenuation, see
+    /// [`Window::kaiser`].
+    Kaiser { beta: f64 },
+}
+
+/// A FIR filter that keeps the last samples of each block, so blocks are filtered as one
+/// continuous signal.
+#[derive(Debug, Clone)]
+pub struct FirFilter<T> {
+    /// Taps in reverse order, so each output is a dot product with consecutive samples.
+    taps: Vec<f32>,
+    /// The last `taps - 1` samples, followed by the current block while it is processed.
+    buffer: Vec<T>,
+}
+
+impl Window {
+    /// A Kaiser window for the given stopband attenuation in dB.
+    pub fn kaiser(attenuation_db: f64) -> Self {
+        let a = attenuation_db;
+        let beta = if a > 50.0 {
+            0.1102 * (a - 8.7)
+        } else if a >= 21.0 {
+            0.5842 * (a - 21.0).powf(0.4) + 0.07886 * (a - 21.0)
+        } else {
+            0.0
+        };
+        Window::Kaiser { beta }
+    }
+
+    /// Computes the window for a filter of `len` taps.
+    pub fn coefficients(&self, len: usize) -> Vec<f64> {
+        if len == 1 {
+            return vec![1.0];
+        }
+
+        let m = (len - 1) as f64;
+        (0..len)
+            .map(|n| {
+                let x = n as f64 / m;
+                match *self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * (TAU * x).cos(),
+                    Window::Hamming => 0.54 - 0.46 * (TAU * x).cos(),
+                    Window::Blackman => 0.42 - 0.5 * (TAU * x).cos() + 0.08 * (2.0 * TAU * x).cos(),
+                    Window::Kaiser { beta } => {
+                        let r = 2.0 * x - 1.0;
+                        bessel_i0(beta * (1.0 - r * r).sqrt()) / bessel_i0(beta)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Estimates the number of taps of a Kaiser window filter with the given stopband attenuation
+/// in dB and transition width in Hz. The result is odd, as required by [`high_pass`].
+pub fn kaiser_taps(sample_rate: u32, transition_width: f32, attenuation_db: f64) -> usize {
+    let width = TAU * transition_width as f64 / sample_rate as f64;
+    let order = ((attenuation_db - 7.95) / (2.285 * width)).ceil().max(2.0) as usize;
+    order + order % 2 + 1
+}
+
+/// Designs a low pass filter with unity gain at DC.
+///
+/// # Panics
+/// If `taps` is even.
+pub fn low_pass(sample_rate: u32, cutoff_freq: f32, taps: usize, window: Window) -> Vec<f32> {
+    let taps = sinc(sample_rate, cutoff_freq, taps, window);
+    normalize(taps, sample_rate, 0.0)
+}
+
+/// Designs a high pass filter with unity gain at the Nyquist frequency.
+///
+/// # Panics
+/// If `taps` is even.
+pub fn high_pass(sample_rate: u32, cutoff_freq: f32, taps: usize, window: Window) -> Vec<f32> {
+    // Spectral inversion of the complementary low pass filter
+    let mut taps = sinc(sample_rate, cutoff_freq, taps, window);
+    let low_pass_gain = taps.iter().sum::<f64>();
+    taps.iter_mut().for_each(|x| *x = -*x / low_pass_gain);
+    let center = taps.len() / 2;
+    taps[center] += 1.0;
+
+    normalize(taps, sample_rate, sample_rate as f32 / 2.0)
+}
+
+/// Designs a band pass filter with unity gain at the center of the band.
+///
+/// # Panics
+/// If `taps` is even.
+pub fn band_pass(
+    sample_rate: u32,
+    low_freq: f32,
+    high_freq: f32,
+    taps: usize,
+    window: Window,
+) -> Vec<f32> {
+    let high = sinc(sample_rate, high_freq, taps, window);
+    let low = sinc(sample_rate, low_freq, taps, window);
+    let taps = high.iter().zip(&low).map(|(h, l)| h - l).collect();
+    normalize(taps, sample_rate, (low_freq + high_freq) / 2.0)
+}
+
+/// Magnitude of the frequency response of a filter at a frequency in Hz.
+pub fn gain(taps: &[f32], sample_rate: u32, freq: f32) -> f32 {
+    response(taps, sample_rate, freq).norm() as f32
+}
+
+/// The windowed ideal low pass response.
+fn sinc(sample_rate: u32, cutoff_freq: f32, taps: usize, window: Window) -> Vec<f64> {
+    assert!(taps % 2 == 1, "filters must have an odd number of taps");
+
+    let cutoff = cutoff_freq as f64 / sample_rate as f64;
+    let center = (taps / 2) as f64;
+    window
+        .coefficients(taps)
+        .into_iter()
+        .enumerate()
+        .map(|(n, window)| {
+            let t = n as f64 - center;
+            let sinc = match t == 0.0 {
+                true => 2.0 * cutoff,
+                false => (TAU * cutoff * t).sin() / (PI * t),
+            };
+            sinc * window
+        })
+        .collect()
+}
+
+/// Scales the taps to a gain of one at a frequency.
+fn normalize(taps: Vec<f64>, sample_rate: u32, freq: f32) -> Vec<f32> {
+    let taps = taps.into_iter().map(|x| x as f32).collect::<Vec<_>>();
+    let gain = gain(&taps, sample_rate, freq);
+    taps.into_iter().map(|x| x / gain).collect()
+}
+
+fn response(taps: &[f32], sample_rate: u32, freq: f32) -> Complex<f64> {
+    let omega = TAU * freq as f64 / sample_rate as f64;
+    taps.iter()
+        .enumerate()
+        .map(|(n, &tap)| Complex::from_polar(tap as f64, -omega * n as f64))
+        .sum()
+}
+
+/// Modified Bessel function of the first kind of order zero, from its power series.
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    let half = x / 2.0;
+    for k in 1..100 {
+        term *= (half / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+impl<T: FilterSample> FirFilter<T> {
+    /// Creates a filter with the given taps and a history of zeros.
+    ///
+    /// # Panics
+    /// If `taps` is empty.
+    pub fn new(taps: &[f32]) -> Self {
+        assert!(!taps.is_empty(), "filters must have at least one tap");
+        Self {
+            taps: taps.iter().rev().copied().collect(),
+            buffer: vec![T::default(); taps.len() - 1],
+        }
+    }
+
+    /// Number of taps.
+    pub fn len(&self) -> usize {
+        self.taps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taps.is_empty()
+    }
+
+    /// Clears the history.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(self.taps.len() - 1, T::default());
+    }
+
+    pub fn filter(&mut self, value: T) -> T {
+        let mut output = [value];
+        self.process_in_place(&mut output);
+        output[0]
+    }
+
+    /// Filters a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[T], output: &mut [T]) {
+        assert_eq!(input.len(), output.len());
+        output.copy_from_slice(input);
+        self.process_in_place(output);
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [T]) {
+        self.buffer.extend_from_slice(samples);
+        for (n, out) in samples.iter_mut().enumerate() {
+            *out = dot(&self.buffer[n..n + self.taps.len()], &self.taps);
+        }
+        self.buffer.drain(..samples.len());
+    }
+}
+
+/// Sum of the products of samples and taps.
+#[inline]
+pub(crate) fn dot<T: FilterSample>(samples: &[T], taps: &[f32]) -> T {
+    let mut sum = T::default();
+    for (&sample, &tap) in samples.iter().zip(taps) {
+        sum += sample * tap;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::*;
+    use crate::dsp::{assert_in_place_chunks_match, test_signal};
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn db(gain: f32) -> f32 {
+        20.0 * gain.log10()
+    }
+
+    #[test]
+    fn low_pass_response() {
+        let taps = low_pass(SAMPLE_RATE, 4_000.0, 101, Window::Blackman);
+        assert!(taps.iter().zip(taps.iter().rev()).all(|(a, b)| a == b));
+        assert!((gain(&taps, SAMPLE_RATE, 0.0) - 1.0).abs() < 1e-6);
+        assert!((gain(&taps, SAMPLE_RATE, 2_000.0) - 1.0).abs() < 1e-3);
+        assert!((db(gain(&taps, SAMPLE_RATE, 4_000.0)) + 6.02).abs() < 0.1);
+        for freq in (7_000..24_000).step_by(250) {
+            assert!(db(gain(&taps, SAMPLE_RATE, freq as f32)) < -70.0, "{freq}");
+        }
+    }
+
+    #[test]
+    fn high_pass_response() {
+        let taps = high_pass(SAMPLE_RATE, 10_000.0, 81, Window::Hamming);
+        assert!((gain(&taps, SAMPLE_RATE, 24_000.0) - 1.0).abs() < 1e-6);
+        assert!((gain(&taps, SAMPLE_RATE, 16_000.0) - 1.0).abs() < 1e-2);
+        for freq in (0..7_000).step_by(250) {
+            assert!(db(gain(&taps, SAMPLE_RATE, freq as f32)) < -50.0, "{freq}");
+        }
+    }
+
+    #[test]
+    fn band_pass_response() {
+        let taps = band_pass(SAMPLE_RATE, 6_000.0, 12_000.0, 129, Window::kaiser(60.0));
+        assert!((gain(&taps, SAMPLE_RATE, 9_000.0) - 1.0).abs() < 1e-6);
+        assert!((gain(&taps, SAMPLE_RATE, 7_500.0) - 1.0).abs() < 1e-2);
+        for freq in (0..4_000).chain(14_000..24_000).step_by(250) {
+            assert!(db(gain(&taps, SAMPLE_RATE, freq as f32)) < -58.0, "{freq}");
+        }
+    }
+
+    #[test]
+    fn kaiser_design_meets_specification() {
+        let (cutoff, width, attenuation) = (200_000.0, 50_000.0, 60.0);
+        let taps = kaiser_taps(2_000_000, width, attenuation);
+        assert_eq!(taps % 2, 1);
+
+        let taps = low_pass(2_000_000, cutoff, taps, Window::kaiser(attenuation));
+        for freq in (225_000..1_000_000).step_by(5_000) {
+            assert!(db(gain(&taps, 2_000_000, freq as f32)) < -59.0, "{freq}");
+        }
+        for freq in (0..175_000).step_by(5_000) {
+            assert!(
+                db(gain(&taps, 2_000_000, freq as f32)).abs() < 0.02,
+                "{freq}"
+            );
+        }
+    }
+
+    #[test]
+    fn kaiser_window_reference() {
+        // numpy.kaiser(7, 5.0)
+        let reference = [
+            0.03671089, 0.32820196, 0.7753221, 1.0, 0.7753221, 0.32820196, 0.03671089,
+        ];
+        let window = Window::Kaiser { beta: 5.0 }.coefficients(7);
+        for (a, b) in window.iter().zip(reference) {
+            assert!((a - b).abs() < 1e-6, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn impulse_response_is_taps() {
+        let taps = low_pass(SAMPLE_RATE, 5_000.0, 31, Window::Hamming);
+        let mut impulse = vec![0.0; 40];
+        impulse[0] = 1.0;
+
+        FirFilter::new(&taps).process_in_place(&mut impulse);
+        assert_eq!(&impulse[..31], &taps[..]);
+        assert!(impulse[31..].iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        let taps = low_pass(SAMPLE_RATE, 5_000.0, 31, Window::Hamming);
+        let input = test_signal(1000);
+        assert_in_place_chunks_match(
+            || FirFilter::new(&taps),
+            &input,
+            7,
+            FirFilter::process,
+            FirFilter::process_in_place,
+        );
+
+        let mut whole = vec![Complex::default(); input.len()];
+        FirFilter::new(&taps).process(&input, &mut whole);
+        let mut real = input.iter().map(|x| x.re).collect::<Vec<_>>();
+        FirFilter::new(&taps).process_in_place(&mut real);
+        assert!(real.iter().zip(&whole).all(|(a, b)| *a == b.re));
+    }
+}
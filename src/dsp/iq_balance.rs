@@ -0,0 +1,175 @@
+use num_complex::Complex;
+
+/// Blindly estimates and corrects the amplitude and phase imbalance between the I and Q
+/// channels of a receiver, which shows up as an image of every signal mirrored around the center
+/// frequency.
+///
+/// The estimate relies on the received signal being proper, meaning I and Q have the same power
+/// and are uncorrelated, which holds for noise and for signals spread over the band. The model
+/// is that Q is received as `gain * (Q cos(phase) + I sin(phase))`, and the correction
+/// orthogonalizes Q against I and restores its power. DC should be removed before, such as with
+/// a [`super::DcBlocker`], since an offset biases the estimate.
+#[derive(Debug, Clone)]
+pub struct IqBalance {
+    alpha: f32,
+    /// Running means of I², Q² and I·Q.
+    ii: f32,
+    qq: f32,
+    iq: f32,
+    /// Samples averaged so far, which are weighted equally until there are enough for the time
+    /// constant.
+    count: u32,
+}
+
+impl IqBalance {
+    /// Creates a corrector that averages its estimate over about `time_constant` seconds.
+    pub fn new(sample_rate: u32, time_constant: f32) -> Self {
+        Self {
+            alpha: 1.0 / (time_constant * sample_rate as f32).max(1.0),
+            ii: 0.0,
+            qq: 0.0,
+            iq: 0.0,
+            count: 0,
+        }
+    }
+
+    /// The estimated ratio of the amplitude of Q to that of I.
+    pub fn amplitude_imbalance(&self) -> f32 {
+        match self.ii > 0.0 {
+            true => (self.qq / self.ii).sqrt(),
+            false => 1.0,
+        }
+    }
+
+    /// The estimated phase error of Q in radians.
+    pub fn phase_imbalance(&self) -> f32 {
+        self.sin_phase().asin()
+    }
+
+    fn sin_phase(&self) -> f32 {
+        let power = (self.ii * self.qq).sqrt();
+        match power > 0.0 {
+            true => (self.iq / power).clamp(-1.0, 1.0),
+            false => 0.0,
+        }
+    }
+
+    /// Forgets the estimate.
+    pub fn reset(&mut self) {
+        (self.ii, self.qq, self.iq) = (0.0, 0.0, 0.0);
+        self.count = 0;
+    }
+
+    pub fn filter(&mut self, value: Complex<f32>) -> Complex<f32> {
+        let (i, q) = (value.re, value.im);
+        self.count = self.count.saturating_add(1);
+        let alpha = self.alpha.max(1.0 / self.count as f32);
+        self.ii += (i * i - self.ii) * alpha;
+        self.qq += (q * q - self.qq) * alpha;
+        self.iq += (i * q - self.iq) * alpha;
+
+        let sin = self.sin_phase();
+        let cos = (1.0 - sin * sin).sqrt();
+        if cos < 1e-3 {
+            return value;
+        }
+
+        let q = (q / self.amplitude_imbalance() - i * sin) / cos;
+        Complex::new(i, q)
+    }
+
+    /// Corrects a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[Complex<f32>], output: &mut [Complex<f32>]) {
+        assert_eq!(input.len(), output.len());
+        for (out, &value) in output.iter_mut().zip(input) {
+            *out = self.filter(value);
+        }
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [Complex<f32>]) {
+        for value in samples.iter_mut() {
+            *value = self.filter(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::IqBalance;
+    use crate::dsp::assert_in_place_chunks_match;
+
+    /// Applies the imbalance modelled by [`IqBalance`].
+    fn impair(samples: &mut [Complex<f32>], gain: f32, phase: f32) {
+        for x in samples {
+            x.im = gain * (x.im * phase.cos() + x.re * phase.sin());
+        }
+    }
+
+    /// Power of a frequency in the signal.
+    fn power(signal: &[Complex<f32>], freq: f32, sample_rate: f32) -> f32 {
+        let sum = signal
+            .iter()
+            .enumerate()
+            .map(|(n, x)| x * Complex::from_polar(1.0, -TAU * freq * n as f32 / sample_rate))
+            .sum::<Complex<f32>>();
+        (sum / signal.len() as f32).norm_sqr()
+    }
+
+    #[test]
+    fn rejects_image() {
+        let sample_rate = 1_000_000.0;
+        // Tones on one side of the band, with noise so the estimate is not biased by them
+        let mut state = 1u32;
+        let mut noise = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        let mut samples = (0..400_000)
+            .map(|n| {
+                let t = n as f32 / sample_rate;
+                Complex::from_polar(0.3, TAU * 50_000.0 * t)
+                    + Complex::from_polar(0.2, TAU * 120_000.0 * t + 1.0)
+                    + Complex::new(noise(), noise()) * 0.2
+            })
+            .collect::<Vec<_>>();
+        impair(&mut samples, 1.15, 0.1);
+
+        // Impairment of 15% and 0.1 rad leaves the image about 23 dB below the tone
+        let image =
+            power(&samples, -50_000.0, sample_rate) / power(&samples, 50_000.0, sample_rate);
+        assert!(image > 1e-3);
+
+        let mut balance = IqBalance::new(sample_rate as u32, 0.02);
+        balance.process_in_place(&mut samples);
+        assert!((balance.amplitude_imbalance() - 1.15).abs() < 0.02);
+        assert!((balance.phase_imbalance() - 0.1).abs() < 0.02);
+
+        let settled = &samples[200_000..];
+        for freq in [50_000.0, 120_000.0] {
+            let image = power(settled, -freq, sample_rate) / power(settled, freq, sample_rate);
+            assert!(image < 1e-4, "{freq}: {image}");
+        }
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        let input = (0..5_000)
+            .map(|n| Complex::new((n as f32 * 0.37).sin(), (n as f32 * 0.61).cos() * 1.2))
+            .collect::<Vec<_>>();
+
+        assert_in_place_chunks_match(
+            || IqBalance::new(48_000, 0.01),
+            &input,
+            17,
+            IqBalance::process,
+            IqBalance::process_in_place,
+        );
+    }
+}
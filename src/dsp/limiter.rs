@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+
+use num_complex::Complex;
+
+/// A lookahead peak limiter that keeps both components of a complex signal within a threshold,
+/// such as full scale before converting to 8-bit samples.
+///
+/// The output is delayed by the lookahead, so the gain is already reduced when a peak arrives
+/// and nothing is clipped. The gain ramps down over the lookahead instead of stepping, which
+/// would spread the signal into neighbouring channels, and recovers within the release time.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    threshold: f32,
+    lookahead: usize,
+    release: f32,
+    gain: f32,
+    delay: VecDeque<Complex<f32>>,
+    /// Index and required gain of the samples in the delay line that have a lower gain than all
+    /// samples after them, so the front is the lowest gain of the delay line.
+    minima: VecDeque<(u64, f32)>,
+    /// The lowest gains of the delay line for up to the last `lookahead + 1` samples, whose
+    /// mean is the gain before the release.
+    envelope: VecDeque<f32>,
+    envelope_sum: f64,
+    /// Number of samples input so far.
+    count: u64,
+}
+
+impl Limiter {
+    /// Creates a limiter with a threshold of one and times in seconds.
+    pub fn new(sample_rate: u32, lookahead_time: f32, release_time: f32) -> Self {
+        let lookahead = (lookahead_time * sample_rate as f32).round() as usize;
+        Self {
+            threshold: 1.0,
+            lookahead,
+            release: 1.0 - (-1.0 / (release_time * sample_rate as f32).max(1.0)).exp(),
+            gain: 1.0,
+            delay: VecDeque::with_capacity(lookahead + 1),
+            minima: VecDeque::new(),
+            envelope: VecDeque::with_capacity(lookahead + 2),
+            envelope_sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Delay of the output in samples.
+    pub fn delay(&self) -> usize {
+        self.lookahead
+    }
+
+    /// The gain applied to the last sample.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Clears the delay line and restores unity gain.
+    pub fn reset(&mut self) {
+        self.gain = 1.0;
+        self.delay.clear();
+        self.minima.clear();
+        self.envelope.clear();
+        self.envelope_sum = 0.0;
+        self.count = 0;
+    }
+
+    /// Appends the limited block to `output`, which lags the input by [`Limiter::delay`].
+    pub fn process(&mut self, input: &[Complex<f32>], output: &mut Vec<Complex<f32>>) {
+        for &value in input {
+            let peak = value.re.abs().max(value.im.abs());
+            let required = match peak > self.threshold {
+                true => self.threshold / peak,
+                false => 1.0,
+            };
+            while self.minima.back().is_some_and(|x| x.1 >= required) {
+                self.minima.pop_back();
+            }
+            self.minima.push_back((self.count, required));
+            self.delay.push_back(value);
+            self.count += 1;
+
+            if self.delay.len() > self.lookahead {
+                output.push(self.next());
+            }
+        }
+    }
+
+    /// Appends the samples still held in the delay line to `output`.
+    pub fn finish(&mut self, output: &mut Vec<Complex<f32>>) {
+        while !self.delay.is_empty() {
+            output.push(self.next());
+        }
+    }
+
+    /// Outputs the oldest sample of the delay line with the mean of the lowest gains required
+    /// until the newest over up to the last `lookahead + 1` samples. Every one of those covers
+    /// the current sample, so the mean never rises above what it needs, and it ramps down linearly
+    /// over the lookahead before a peak.
+    fn next(&mut self) -> Complex<f32> {
+        let index = self.count - self.delay.len() as u64;
+        while self.minima.front().is_some_and(|x| x.0 < index) {
+            self.minima.pop_front();
+        }
+
+        let lowest = self.minima.front().map_or(1.0, |x| x.1);
+        self.envelope.push_back(lowest);
+        self.envelope_sum += lowest as f64;
+        if self.envelope.len() > self.lookahead + 1 {
+            self.envelope_sum -= self.envelope.pop_front().unwrap() as f64;
+        }
+        let target = (self.envelope_sum / self.envelope.len() as f64).min(1.0) as f32;
+        self.gain = match target < self.gain {
+            true => target,
+            false => self.gain + (target - self.gain) * self.release,
+        };
+        self.delay.pop_front().unwrap() * self.gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::Limiter;
+    use crate::dsp::{Spectrum, Window};
+
+    #[test]
+    fn limits_peaks_without_clipping() {
+        let mut limiter = Limiter::new(1_000_000, 1e-4, 1e-3);
+        let input = (0..10_000)
+            .map(|n| {
+                let amplitude = if (4_000..4_010).contains(&n) {
+                    3.0
+                } else {
+                    0.5
+                };
+                Complex::from_polar(amplitude, n as f32 * 0.1)
+            })
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        for block in input.chunks(777) {
+            limiter.process(block, &mut output);
+        }
+        limiter.finish(&mut output);
+        assert_eq!(output.len(), input.len());
+
+        assert!(output
+            .iter()
+            .all(|x| x.re.abs() <= 1.0 + 1e-6 && x.im.abs() <= 1.0 + 1e-6));
+        // Quiet parts before the lookahead and after the release pass unchanged
+        assert_eq!(output[..3_900], input[..3_900]);
+        assert!((output[9_999] - input[9_999]).norm() < 1e-3);
+        assert!(output[4_005].norm() > 0.9);
+    }
+
+    #[test]
+    fn ramps_gain_without_spreading_into_other_channels() {
+        // Twenty tones a bin apart, whose sum peaks at 1.6 once every segment of the spectrum
+        let tones = 410..430;
+        let input = (0..1 << 17)
+            .map(|n| {
+                tones
+                    .clone()
+                    .map(|k| {
+                        let phase = (k * n % 4_096) as f32 / 4_096.0;
+                        Complex::from_polar(0.08, std::f32::consts::TAU * phase)
+                    })
+                    .sum::<Complex<f32>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut limiter = Limiter::new(1_000_000, 1e-4, 1e-3);
+        let mut output = Vec::new();
+        limiter.process(&input, &mut output);
+        limiter.finish(&mut output);
+        assert!(output
+            .iter()
+            .all(|x| x.re.abs() <= 1.0 + 1e-6 && x.im.abs() <= 1.0 + 1e-6));
+
+        let mut spectrum = Spectrum::new(4_096, Window::Blackman, 1_000_000);
+        spectrum.push(&output[16_384..output.len() - 16_384]);
+        let (mut channel, mut outside) = (0.0, 0.0);
+        for (bin, power) in spectrum.average().into_iter().enumerate() {
+            match (bin as i64 - 2_048 - 420).abs() < 100 {
+                true => channel += power,
+                false => outside += power,
+            }
+        }
+        // A gain that steps down at every peak leaves this at about -57 dB
+        assert!(10.0 * (outside / channel).log10() < -70.0);
+    }
+}
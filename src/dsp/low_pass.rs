@@ -0,0 +1,104 @@
+use std::f32::consts::PI;
+
+use super::FilterSample;
+
+/// A single pole IIR low pass filter, equivalent to an RC filter.
+#[derive(Debug, Clone)]
+pub struct LowPassFilter<T> {
+    alpha: f32,
+    last_value: Option<T>,
+}
+
+impl<T: FilterSample> LowPassFilter<T> {
+    pub fn new(sample_rate: u32, cutoff_freq: f32) -> Self {
+        let rc = (cutoff_freq * 2.0 * PI).recip();
+        let dt = (sample_rate as f32).recip();
+        let alpha = dt / (rc + dt);
+
+        Self {
+            alpha,
+            last_value: None,
+        }
+    }
+
+    /// Sets the output the filter continues from. Unless primed, the filter starts from the
+    /// first sample it is given so there is no step response at the start of a signal.
+    pub fn prime(&mut self, value: T) {
+        self.last_value = Some(value);
+    }
+
+    /// Forgets the previous samples.
+    pub fn reset(&mut self) {
+        self.last_value = None;
+    }
+
+    pub fn filter(&mut self, value: T) -> T {
+        let last_value = self.last_value.unwrap_or(value);
+        let value = last_value + (value - last_value) * self.alpha;
+        self.last_value = Some(value);
+        value
+    }
+
+    /// Filters a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[T], output: &mut [T]) {
+        assert_eq!(input.len(), output.len());
+        for (out, &value) in output.iter_mut().zip(input) {
+            *out = self.filter(value);
+        }
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [T]) {
+        for value in samples.iter_mut() {
+            *value = self.filter(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::LowPassFilter;
+    use crate::dsp::{assert_in_place_chunks_match, test_signal};
+
+    #[test]
+    fn step_response() {
+        let (sample_rate, cutoff) = (48_000, 1_000.0);
+        let mut filter = LowPassFilter::new(sample_rate, cutoff);
+        filter.prime(0.0);
+
+        let mut output = [0.0; 200];
+        filter.process(&[1.0; 200], &mut output);
+
+        // y[n] = 1 - (1 - alpha)^(n + 1)
+        let rc = 1.0 / (std::f64::consts::TAU * cutoff as f64);
+        let dt = 1.0 / sample_rate as f64;
+        let alpha = dt / (rc + dt);
+        for (n, &y) in output.iter().enumerate() {
+            let expected = 1.0 - (1.0 - alpha).powi(n as i32 + 1);
+            assert!((y as f64 - expected).abs() < 1e-5, "{n}: {y} != {expected}");
+        }
+    }
+
+    #[test]
+    fn starts_from_first_sample() {
+        let mut filter = LowPassFilter::new(48_000, 1_000.0);
+        let mut output = [Complex::default(); 4];
+        filter.process(&[Complex::new(0.5, -0.25); 4], &mut output);
+        assert!(output.iter().all(|&x| x == Complex::new(0.5, -0.25)));
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        assert_in_place_chunks_match(
+            || LowPassFilter::new(2_000_000, 200_000.0),
+            &test_signal(1000),
+            77,
+            LowPassFilter::process,
+            LowPassFilter::process_in_place,
+        );
+    }
+}
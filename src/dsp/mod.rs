@@ -0,0 +1,117 @@
+//! Block based signal processing primitives.
+//!
+//! Filters take a slice of samples at a time and keep their state between calls, so a signal
+//! split across transfer callbacks is processed exactly as if it arrived in one piece.
+
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+use num_complex::Complex;
+
+pub mod agc;
+pub mod channelizer;
+pub mod dc_block;
+pub mod down_sample;
+pub mod fir;
+pub mod iq_balance;
+pub mod limiter;
+pub mod low_pass;
+pub mod nco;
+pub mod offset;
+pub mod resample;
+pub mod spectrum;
+pub mod synthesizer;
+
+pub use agc::Agc;
+pub use channelizer::{ChannelSpec, FftChannelizer, PfbChannelizer};
+pub use dc_block::DcBlocker;
+pub use down_sample::DownSample;
+pub use fir::{FirFilter, Window};
+pub use iq_balance::IqBalance;
+pub use limiter::Limiter;
+pub use low_pass::LowPassFilter;
+pub use nco::Nco;
+pub use offset::OffsetFilter;
+pub use resample::{AlignedResampler, Resampler};
+pub use spectrum::Spectrum;
+pub use synthesizer::Synthesizer;
+
+/// Real or complex samples that filters can operate on.
+pub trait FilterSample:
+    Copy
+    + Default
+    + Send
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<f32, Output = Self>
+    + AddAssign
+    + 'static
+{
+    /// Magnitude of the sample, used to follow the envelope of a signal.
+    fn magnitude(self) -> f32;
+}
+
+impl FilterSample for f32 {
+    fn magnitude(self) -> f32 {
+        self.abs()
+    }
+}
+
+impl FilterSample for Complex<f32> {
+    fn magnitude(self) -> f32 {
+        self.norm()
+    }
+}
+
+/// A complex signal without structure a block could treat specially, for tests.
+#[cfg(test)]
+pub(crate) fn test_signal(len: usize) -> Vec<Complex<f32>> {
+    (0..len)
+        .map(|n| Complex::new((n as f32 * 0.37).sin(), (n as f32 * 0.11).cos()))
+        .collect()
+}
+
+/// Asserts that a block gives the same output when fed the input in chunks as when fed all of
+/// it at once. `process` appends the output for some input to `output`.
+#[cfg(test)]
+pub(crate) fn assert_chunks_match<B, T, O>(
+    new: impl Fn() -> B,
+    input: &[T],
+    chunk_len: usize,
+    output: O,
+    process: impl Fn(&mut B, &[T], &mut O),
+) where
+    O: Clone + PartialEq + std::fmt::Debug,
+{
+    let mut whole = output.clone();
+    process(&mut new(), input, &mut whole);
+
+    let mut block = new();
+    let mut chunks = output;
+    for chunk in input.chunks(chunk_len) {
+        process(&mut block, chunk, &mut chunks);
+    }
+    assert_eq!(whole, chunks);
+}
+
+/// [`assert_chunks_match`] for blocks with `process` and `process_in_place` methods, where the
+/// whole input goes through the first and the chunks through the second.
+#[cfg(test)]
+pub(crate) fn assert_in_place_chunks_match<B, T>(
+    new: impl Fn() -> B,
+    input: &[T],
+    chunk_len: usize,
+    process: impl Fn(&mut B, &[T], &mut [T]),
+    process_in_place: impl Fn(&mut B, &mut [T]),
+) where
+    T: Copy + Default + PartialEq + std::fmt::Debug,
+{
+    let mut whole = vec![T::default(); input.len()];
+    process(&mut new(), input, &mut whole);
+
+    let mut block = new();
+    let mut chunks = input.to_vec();
+    for chunk in chunks.chunks_mut(chunk_len) {
+        process_in_place(&mut block, chunk);
+    }
+    assert_eq!(whole, chunks);
+}
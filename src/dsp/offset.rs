@@ -0,0 +1,78 @@
+use num_complex::Complex;
+
+use super::Nco;
+
+/// Shifts a complex signal in frequency by multiplying it with a complex exponential.
+#[derive(Debug, Clone)]
+pub struct OffsetFilter {
+    nco: Nco,
+}
+
+impl OffsetFilter {
+    pub fn new(offset_freq: f32, sample_rate: u32) -> Self {
+        Self {
+            nco: Nco::new(offset_freq as f64, sample_rate as f64),
+        }
+    }
+
+    /// Changes the offset, continuing from the current phase.
+    pub fn set_offset(&mut self, offset_freq: f32) {
+        self.nco.set_freq(offset_freq as f64);
+    }
+
+    pub fn reset(&mut self) {
+        self.nco.set_phase(0.0);
+    }
+
+    pub fn filter(&mut self, iq: Complex<f32>) -> Complex<f32> {
+        iq * self.nco.sample()
+    }
+
+    /// Shifts a block of samples.
+    ///
+    /// # Panics
+    /// If the slices have different lengths.
+    pub fn process(&mut self, input: &[Complex<f32>], output: &mut [Complex<f32>]) {
+        assert_eq!(input.len(), output.len());
+        output.copy_from_slice(input);
+        self.nco.mix(output);
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [Complex<f32>]) {
+        self.nco.mix(samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::OffsetFilter;
+    use crate::dsp::{assert_in_place_chunks_match, test_signal};
+
+    #[test]
+    fn matches_complex_exponential() {
+        let (offset, sample_rate) = (-12_500.0, 100_000);
+        let mut output = vec![Complex::new(1.0, 0.0); 1000];
+        OffsetFilter::new(offset, sample_rate).process_in_place(&mut output);
+
+        for (n, y) in output.iter().enumerate() {
+            let phase = TAU * offset as f64 * n as f64 / sample_rate as f64;
+            let expected = Complex::new(phase.cos() as f32, phase.sin() as f32);
+            assert!((y - expected).norm() < 1e-3, "{n}: {y} != {expected}");
+        }
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        assert_in_place_chunks_match(
+            || OffsetFilter::new(1_000.0, 48_000),
+            &test_signal(1000),
+            64,
+            OffsetFilter::process,
+            OffsetFilter::process_in_place,
+        );
+    }
+}
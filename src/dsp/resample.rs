@@ -0,0 +1,361 @@
+//! Polyphase FIR resampling by a rational factor, which also covers integer decimation and
+//! interpolation.
+
+use super::{
+    fir::{self, dot, Window},
+    FilterSample,
+};
+
+/// Stopband attenuation in dB of the filters designed by [`design`].
+const ATTENUATION: f64 = 60.0;
+/// Most polyphase branches designed by [`Resampler::new`]. Ratios with a larger interpolation
+/// factor, such as between nearly coprime rates, interpolate linearly between the branches, which
+/// keeps the filter small and adds an error well below its stopband attenuation.
+pub const MAX_BRANCHES: usize = 256;
+
+/// Changes the sample rate of a signal by `interpolation / decimation`.
+///
+/// Conceptually the signal is upsampled by inserting zeros, low pass filtered and then
+/// downsampled, but only the taps that contribute to a kept output sample are evaluated.
+#[derive(Debug, Clone)]
+pub struct Resampler<T> {
+    interpolation: usize,
+    decimation: usize,
+    /// Delay of the filter in output samples.
+    delay: f64,
+    /// Polyphase branches of the filter, each in reverse order and scaled by their number. An
+    /// extra branch for the phase one input sample later follows the others, so outputs between
+    /// the last branch and the next input sample can be interpolated.
+    branches: Vec<Vec<f32>>,
+    /// The input samples needed by the next output, followed by the rest of the block.
+    buffer: Vec<T>,
+    /// Index in `buffer` of the newest input sample of the next output.
+    position: usize,
+    /// Branch of the next output.
+    phase: usize,
+}
+
+/// Designs the low pass filter for resampling by `interpolation / decimation`, at the
+/// interpolated sample rate or [`MAX_BRANCHES`] times the input sample rate, whichever is lower.
+/// Frequencies below 40% of the lower of the input and output sample rates are kept and those
+/// above half of it are attenuated by 60 dB.
+pub fn design(interpolation: usize, decimation: usize) -> Vec<f32> {
+    // Bandwidth in units of the input sample rate
+    let bandwidth = (interpolation as f32 / decimation as f32).min(1.0);
+    let rate = interpolation.min(MAX_BRANCHES) as u32;
+    let taps = fir::kaiser_taps(rate, 0.1 * bandwidth, ATTENUATION);
+    fir::low_pass(rate, 0.45 * bandwidth, taps, Window::kaiser(ATTENUATION))
+}
+
+impl<T: FilterSample> Resampler<T> {
+    /// Creates a resampler with a filter from [`design`]. The factors are reduced to their
+    /// simplest ratio, and at most [`MAX_BRANCHES`] branches are designed whatever the ratio.
+    ///
+    /// # Panics
+    /// If either factor is zero.
+    pub fn new(interpolation: usize, decimation: usize) -> Self {
+        assert!(interpolation > 0 && decimation > 0);
+        let divisor = gcd(interpolation, decimation);
+        let (interpolation, decimation) = (interpolation / divisor, decimation / divisor);
+        Self::with_branches(
+            interpolation,
+            decimation,
+            interpolation.min(MAX_BRANCHES),
+            &design(interpolation, decimation),
+        )
+    }
+
+    /// Creates a resampler from one sample rate to another.
+    pub fn from_rates(in_sample_rate: u32, out_sample_rate: u32) -> Self {
+        Self::new(out_sample_rate as usize, in_sample_rate as usize)
+    }
+
+    /// Keeps every `factor`th sample of the filtered signal.
+    pub fn decimator(factor: usize) -> Self {
+        Self::new(1, factor)
+    }
+
+    /// Outputs `factor` samples for every input sample.
+    pub fn interpolator(factor: usize) -> Self {
+        Self::new(factor, 1)
+    }
+
+    /// Creates a resampler with the taps of a low pass filter designed at the interpolated
+    /// sample rate with a gain of one at DC.
+    ///
+    /// # Panics
+    /// If either factor is zero or `taps` is empty.
+    pub fn with_taps(interpolation: usize, decimation: usize, taps: &[f32]) -> Self {
+        Self::with_branches(interpolation, decimation, interpolation, taps)
+    }
+
+    /// Creates a resampler with the taps of a filter designed at `branches` times the input
+    /// sample rate, which is less than the interpolated rate for large interpolation factors.
+    fn with_branches(
+        interpolation: usize,
+        decimation: usize,
+        branches: usize,
+        taps: &[f32],
+    ) -> Self {
+        assert!(interpolation > 0 && decimation > 0);
+        assert!(!taps.is_empty(), "filters must have at least one tap");
+
+        let len = taps.len().div_ceil(branches);
+        let delay = (taps.len() - 1) as f64 / 2.0 / branches as f64 * interpolation as f64;
+        let branches = (0..=branches)
+            .map(|phase| {
+                (0..len)
+                    .rev()
+                    .map(|k| taps.get(phase + k * branches).copied().unwrap_or(0.0))
+                    .map(|tap| tap * branches as f32)
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            interpolation,
+            decimation,
+            delay: delay / decimation as f64,
+            branches,
+            buffer: vec![T::default(); len - 1],
+            position: len - 1,
+            phase: 0,
+        }
+    }
+
+    pub fn interpolation(&self) -> usize {
+        self.interpolation
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+
+    /// Ratio of the output to the input sample rate.
+    pub fn ratio(&self) -> f64 {
+        self.interpolation as f64 / self.decimation as f64
+    }
+
+    /// Delay of the filter in output samples.
+    pub fn delay(&self) -> f64 {
+        self.delay
+    }
+
+    /// Clears the history.
+    pub fn reset(&mut self) {
+        let len = self.branches[0].len();
+        self.buffer.clear();
+        self.buffer.resize(len - 1, T::default());
+        self.position = len - 1;
+        self.phase = 0;
+    }
+
+    /// Appends the resampled block to `output`.
+    pub fn process(&mut self, input: &[T], output: &mut Vec<T>) {
+        let len = self.branches[0].len();
+        self.buffer.extend_from_slice(input);
+
+        let branches = self.branches.len() - 1;
+        while self.position < self.buffer.len() {
+            let window = &self.buffer[self.position + 1 - len..=self.position];
+
+            // The phase falls between two branches when there are fewer than the interpolation
+            let scaled = self.phase * branches;
+            let (branch, fraction) = (scaled / self.interpolation, scaled % self.interpolation);
+            let mut sample = dot(window, &self.branches[branch]);
+            if fraction > 0 {
+                let next = dot(window, &self.branches[branch + 1]);
+                sample = sample + (next - sample) * (fraction as f32 / self.interpolation as f32);
+            }
+            output.push(sample);
+
+            self.phase += self.decimation;
+            self.position += self.phase / self.interpolation;
+            self.phase %= self.interpolation;
+        }
+
+        let consumed = (self.position + 1 - len).min(self.buffer.len());
+        self.buffer.drain(..consumed);
+        self.position -= consumed;
+    }
+}
+
+/// A [`Resampler`] that drops the delay of its filter from the output, so the output is aligned
+/// with the input. The samples still held back by the filter are output by
+/// [`AlignedResampler::flush`] at the end of the signal.
+#[derive(Debug, Clone)]
+pub struct AlignedResampler<T> {
+    resampler: Resampler<T>,
+    /// Output samples still to drop to compensate for the delay.
+    skip: usize,
+}
+
+impl<T: FilterSample> AlignedResampler<T> {
+    pub fn new(resampler: Resampler<T>) -> Self {
+        Self {
+            skip: resampler.delay().round() as usize,
+            resampler,
+        }
+    }
+
+    pub fn resampler(&self) -> &Resampler<T> {
+        &self.resampler
+    }
+
+    /// Appends the resampled block to `output`, without the samples that precede the input.
+    pub fn process(&mut self, input: &[T], output: &mut Vec<T>) {
+        let start = output.len();
+        self.resampler.process(input, output);
+
+        let skip = self.skip.min(output.len() - start);
+        output.drain(start..start + skip);
+        self.skip -= skip;
+    }
+
+    /// Pushes the last samples through the filter with silence after the input.
+    pub fn flush(&mut self, output: &mut Vec<T>) {
+        let len = (self.resampler.delay() / self.resampler.ratio()).ceil() as usize;
+        self.process(&vec![T::default(); len], output);
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::{AlignedResampler, Resampler, MAX_BRANCHES};
+    use crate::dsp::assert_chunks_match;
+
+    fn tone(freq: f32, sample_rate: f32, len: usize) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| Complex::from_polar(1.0, TAU * freq * n as f32 / sample_rate))
+            .collect()
+    }
+
+    /// Amplitude of a frequency in the signal.
+    fn amplitude(signal: &[Complex<f32>], freq: f32, sample_rate: f32) -> f32 {
+        let reference = tone(-freq, sample_rate, signal.len());
+        let sum = signal.iter().zip(&reference).map(|(a, b)| a * b);
+        (sum.sum::<Complex<f32>>() / signal.len() as f32).norm()
+    }
+
+    #[test]
+    fn decimation_rejects_aliases() {
+        let mut resampler = Resampler::decimator(8);
+        let (pass, alias) = (tone(10_000.0, 2e6, 80_000), tone(260_000.0, 2e6, 80_000));
+
+        let mut output = Vec::new();
+        resampler.process(&pass, &mut output);
+        let settled = &output[200..];
+        assert!((amplitude(settled, 10_000.0, 250e3) - 1.0).abs() < 1e-2);
+
+        // 260 kHz would alias to 10 kHz without filtering
+        resampler.reset();
+        output.clear();
+        resampler.process(&alias, &mut output);
+        assert!(amplitude(&output[200..], 10_000.0, 250e3) < 1e-3);
+    }
+
+    #[test]
+    fn interpolation_keeps_amplitude() {
+        let mut output = Vec::new();
+        Resampler::interpolator(5).process(&tone(1_000.0, 48e3, 4_800), &mut output);
+        assert_eq!(output.len(), 24_000);
+        assert!((amplitude(&output[1_000..], 1_000.0, 240e3) - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn rational_resampling() {
+        let mut resampler = Resampler::from_rates(44_100, 48_000);
+        assert_eq!(
+            (resampler.interpolation(), resampler.decimation()),
+            (160, 147)
+        );
+
+        let mut output = Vec::new();
+        resampler.process(&tone(3_000.0, 44_100.0, 44_100), &mut output);
+        assert_eq!(output.len(), 48_000);
+        assert!((amplitude(&output[1_000..], 3_000.0, 48e3) - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn coprime_rates_stay_small() {
+        let mut resampler = Resampler::from_rates(44_099, 2_000_000);
+        assert_eq!(resampler.interpolation(), 2_000_000);
+        assert_eq!(resampler.branches.len(), MAX_BRANCHES + 1);
+        assert!(resampler.branches[0].len() < 100);
+
+        let mut output = Vec::new();
+        resampler.process(&tone(1_000.0, 44_099.0, 11_025), &mut output);
+        assert_eq!(output.len(), 500_012);
+        let settled = &output[10_000..];
+        assert!((amplitude(settled, 1_000.0, 2e6) - 1.0).abs() < 1e-2);
+        assert!(amplitude(settled, 43_099.0, 2e6) < 1e-3);
+
+        let mut resampler = Resampler::from_rates(500_000, 44_099);
+        assert_eq!(resampler.branches.len(), MAX_BRANCHES + 1);
+        let (pass, alias) = (tone(3_000.0, 5e5, 100_000), tone(47_099.0, 5e5, 100_000));
+        output.clear();
+        resampler.process(&pass, &mut output);
+        assert!((amplitude(&output[500..], 3_000.0, 44_099.0) - 1.0).abs() < 1e-2);
+
+        // 47.099 kHz would alias to 3 kHz without filtering
+        resampler.reset();
+        output.clear();
+        resampler.process(&alias, &mut output);
+        assert!(amplitude(&output[500..], 3_000.0, 44_099.0) < 1e-3);
+    }
+
+    #[test]
+    fn blocks_match_whole_signal() {
+        let input = (0..2000)
+            .map(|n| (n as f32 * 0.37).sin())
+            .collect::<Vec<_>>();
+
+        assert_chunks_match(
+            || Resampler::new(3, 7),
+            &input,
+            13,
+            Vec::new(),
+            Resampler::process,
+        );
+        assert_chunks_match(
+            || Resampler::new(1_000, 997),
+            &input,
+            13,
+            Vec::new(),
+            Resampler::process,
+        );
+    }
+
+    #[test]
+    fn aligns_output_with_input() {
+        let input = tone(1_000.0, 48_000.0, 4_800);
+        let mut resampler = AlignedResampler::new(Resampler::from_rates(48_000, 32_000));
+        let mut output = Vec::new();
+        for block in input.chunks(333) {
+            resampler.process(block, &mut output);
+        }
+        resampler.flush(&mut output);
+
+        assert!((3_200..3_203).contains(&output.len()));
+        let expected = tone(1_000.0, 32_000.0, 3_200);
+        let error = output[100..3_100]
+            .iter()
+            .zip(&expected[100..3_100])
+            .map(|(a, b)| (a - b).norm())
+            .fold(0.0, f32::max);
+        // The delay is dropped to the nearest output sample, half of which shifts the phase of the
+        // tone by up to 0.1
+        assert!(error < 0.1);
+    }
+}
@@ -0,0 +1,342 @@
+//! Power spectrum estimation with windowed FFTs.
+//!
+//! Power is calibrated in dBFS, where a complex tone with the full scale amplitude of an 8-bit
+//! sample (127, or 1.0 after [`crate::util::i8_to_f32`]) centered on a bin reads 0 dB. Bins are
+//! FFT-shifted, so they run from the lowest to the highest frequency, and are labeled with
+//! absolute frequencies around the center frequency the device is tuned to.
+
+use std::sync::Arc;
+
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use super::Window;
+use crate::{util::i8_to_f32, Config};
+
+/// Estimates the power spectrum of a signal with Welch's method, averaging the periodograms of
+/// overlapping windowed segments. The highest and lowest power of every bin over all segments
+/// is kept as well, for peak and min hold.
+///
+/// Samples can be pushed in blocks of any size, and segments span the blocks.
+#[derive(Clone)]
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    /// Window scaled so a full scale tone has a power of one.
+    window: Vec<f32>,
+    /// Samples between the starts of consecutive segments.
+    step: usize,
+    center_freq: u64,
+    sample_rate: u32,
+
+    /// Samples that are not part of a complete segment yet.
+    pending: Vec<Complex<f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    power: Vec<f32>,
+
+    /// Sum of the power of the averaged segments.
+    sum: Vec<f32>,
+    segments: usize,
+    peak: Vec<f32>,
+    min: Vec<f32>,
+}
+
+impl Spectrum {
+    /// Creates an estimator with `size` bins and segments that overlap by half.
+    ///
+    /// # Panics
+    /// If `size` is zero.
+    pub fn new(size: usize, window: Window, sample_rate: u32) -> Self {
+        assert!(size > 0, "spectrum needs at least one bin");
+        let fft = FftPlanner::new().plan_fft_forward(size);
+
+        // Spectral analysis uses the periodic form of the window, which drops the last point
+        let mut window = window.coefficients(size + 1);
+        window.pop();
+        let sum = window.iter().sum::<f64>();
+
+        Self {
+            scratch: vec![Complex::default(); fft.get_inplace_scratch_len()],
+            fft,
+            window: window.into_iter().map(|x| (x / sum) as f32).collect(),
+            step: size.div_ceil(2),
+            center_freq: 0,
+            sample_rate,
+
+            pending: Vec::new(),
+            buffer: vec![Complex::default(); size],
+            power: vec![0.0; size],
+
+            sum: vec![0.0; size],
+            segments: 0,
+            peak: vec![0.0; size],
+            min: vec![f32::INFINITY; size],
+        }
+    }
+
+    /// Creates an estimator labeled with the frequency and sample rate of a device
+    /// configuration. Values that are not set are zero.
+    pub fn from_config(size: usize, window: Window, config: &Config) -> Self {
+        let mut spectrum = Self::new(size, window, config.sample_rate.unwrap_or_default());
+        spectrum.set_center_freq(config.freq.unwrap_or_default());
+        spectrum
+    }
+
+    /// Sets the number of samples shared by consecutive segments.
+    ///
+    /// # Panics
+    /// If the overlap is not smaller than the size.
+    pub fn with_overlap(mut self, overlap: usize) -> Self {
+        assert!(
+            overlap < self.size(),
+            "overlap must be smaller than the size"
+        );
+        self.step = self.size() - overlap;
+        self
+    }
+
+    /// Number of bins.
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    pub fn center_freq(&self) -> u64 {
+        self.center_freq
+    }
+
+    /// Sets the frequency the device is tuned to, which only changes the labels of the bins.
+    pub fn set_center_freq(&mut self, center_freq: u64) {
+        self.center_freq = center_freq;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Width of a bin in Hz.
+    pub fn bin_width(&self) -> f64 {
+        self.sample_rate as f64 / self.size() as f64
+    }
+
+    /// Bandwidth in Hz of a rectangular filter that passes as much noise as a bin. Divide the
+    /// power of noise by it to get the power spectral density.
+    pub fn noise_bandwidth(&self) -> f64 {
+        let squares = self
+            .window
+            .iter()
+            .map(|&x| x as f64 * x as f64)
+            .sum::<f64>();
+        squares * self.sample_rate as f64
+    }
+
+    /// Absolute frequency of a bin in Hz.
+    pub fn bin_freq(&self, bin: usize) -> f64 {
+        let offset = bin as f64 - (self.size() / 2) as f64;
+        self.center_freq as f64 + offset * self.bin_width()
+    }
+
+    /// Absolute frequencies of all bins in Hz.
+    pub fn frequencies(&self) -> Vec<f64> {
+        (0..self.size()).map(|bin| self.bin_freq(bin)).collect()
+    }
+
+    /// Computes the power of a single windowed segment, without affecting the averages.
+    ///
+    /// # Panics
+    /// If the slices are not as long as the size.
+    pub fn periodogram(&mut self, samples: &[Complex<f32>], power: &mut [f32]) {
+        assert_eq!(samples.len(), self.size());
+        assert_eq!(power.len(), self.size());
+        self.transform(samples);
+        self.shifted_power(power);
+    }
+
+    /// Adds samples to the estimate.
+    pub fn push(&mut self, samples: &[Complex<f32>]) {
+        let size = self.size();
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(samples);
+
+        let mut start = 0;
+        while start + size <= pending.len() {
+            self.transform(&pending[start..start + size]);
+            self.accumulate();
+            start += self.step;
+        }
+
+        pending.drain(..start.min(pending.len()));
+        self.pending = pending;
+    }
+
+    /// Adds 8-bit samples to the estimate.
+    pub fn push_i8(&mut self, samples: &[Complex<i8>]) {
+        let mut converted = vec![Complex::default(); samples.len()];
+        i8_to_f32(samples, &mut converted);
+        self.push(&converted);
+    }
+
+    /// Windows a segment and computes its FFT in `buffer`.
+    fn transform(&mut self, samples: &[Complex<f32>]) {
+        for ((out, &x), &w) in self.buffer.iter_mut().zip(samples).zip(&self.window) {
+            *out = x * w;
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+    }
+
+    /// Writes the power of the transformed segment in order of frequency.
+    fn shifted_power(&self, power: &mut [f32]) {
+        // Negative frequencies are in the upper half of the FFT output
+        let (positive, negative) = self.buffer.split_at(self.size().div_ceil(2));
+        for (out, x) in power.iter_mut().zip(negative.iter().chain(positive)) {
+            *out = x.norm_sqr();
+        }
+    }
+
+    fn accumulate(&mut self) {
+        let mut power = std::mem::take(&mut self.power);
+        self.shifted_power(&mut power);
+
+        for (((&x, sum), peak), min) in power
+            .iter()
+            .zip(&mut self.sum)
+            .zip(&mut self.peak)
+            .zip(&mut self.min)
+        {
+            *sum += x;
+            *peak = peak.max(x);
+            *min = min.min(x);
+        }
+        self.power = power;
+        self.segments += 1;
+    }
+
+    /// Number of segments averaged since the last reset.
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Mean power of every bin, or zero if no segment is complete.
+    pub fn average(&self) -> Vec<f32> {
+        let scale = 1.0 / self.segments.max(1) as f32;
+        self.sum.iter().map(|x| x * scale).collect()
+    }
+
+    /// Highest power of every bin, which is zero before the first segment.
+    pub fn peak_hold(&self) -> &[f32] {
+        &self.peak
+    }
+
+    /// Lowest power of every bin, which is infinite before the first segment.
+    pub fn min_hold(&self) -> &[f32] {
+        &self.min
+    }
+
+    /// Clears the average, such as after displaying it, while keeping the holds.
+    pub fn reset_average(&mut self) {
+        self.sum.fill(0.0);
+        self.segments = 0;
+    }
+
+    pub fn reset_holds(&mut self) {
+        self.peak.fill(0.0);
+        self.min.fill(f32::INFINITY);
+    }
+
+    /// Clears the averages, holds and any incomplete segment.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.reset_average();
+        self.reset_holds();
+    }
+}
+
+/// Converts power to dBFS, such as `spectrum.average().iter().map(|&x| to_db(x))`.
+pub fn to_db(power: f32) -> f32 {
+    10.0 * power.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::{to_db, Spectrum};
+    use crate::{dsp::Window, util::ToComplexI8};
+
+    fn tone(freq: f32, amplitude: f32, sample_rate: f32, len: usize) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| Complex::from_polar(amplitude, TAU * freq * n as f32 / sample_rate))
+            .collect()
+    }
+
+    #[test]
+    fn labels_shifted_bins() {
+        let mut spectrum = Spectrum::new(8, Window::Hann, 2_000_000);
+        spectrum.set_center_freq(100_000_000);
+        assert_eq!(spectrum.bin_freq(0), 99_000_000.0);
+        assert_eq!(spectrum.bin_freq(4), 100_000_000.0);
+        assert_eq!(spectrum.bin_freq(7), 100_750_000.0);
+
+        // A tone at -250 kHz falls in the bin below the center
+        let mut power = [0.0; 8];
+        spectrum.periodogram(&tone(-250e3, 1.0, 2e6, 8), &mut power);
+        let loudest = (0..8).max_by(|&a, &b| power[a].total_cmp(&power[b]));
+        assert_eq!(loudest, Some(3));
+    }
+
+    #[test]
+    fn full_scale_tone_is_zero_dbfs() {
+        let mut spectrum = Spectrum::new(1024, Window::Blackman, 2_000_000);
+        let samples = tone(-125_000.0, 1.0, 2e6, 4096)
+            .into_iter()
+            .map(|x| x.to_i8())
+            .collect::<Vec<_>>();
+        spectrum.push_i8(&samples);
+        assert_eq!(spectrum.segments(), 7);
+
+        let average = spectrum.average();
+        let bin = spectrum.frequencies().iter().position(|&f| f == -125e3);
+        let bin = bin.unwrap();
+        assert!(to_db(average[bin]).abs() < 0.1);
+        // Far from the tone only the sidelobes and quantization noise remain
+        assert!(to_db(average[bin + 100]) < -60.0);
+    }
+
+    #[test]
+    fn welch_averages_across_blocks() {
+        // White noise from a linear congruential generator, with a power of one
+        let mut state = 1u32;
+        let mut uniform = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        let noise = (0..65_536)
+            .map(|_| Complex::new(uniform(), uniform()) * 6f32.sqrt())
+            .collect::<Vec<_>>();
+
+        let mut whole = Spectrum::new(256, Window::Hann, 1_000).with_overlap(192);
+        whole.push(&noise);
+        let mut blocks = Spectrum::new(256, Window::Hann, 1_000).with_overlap(192);
+        for block in noise.chunks(1000) {
+            blocks.push(block);
+        }
+        assert_eq!(whole.segments(), blocks.segments());
+        assert_eq!(whole.average(), blocks.average());
+
+        // Power per bin is the noise power times the fraction of the band the bin passes
+        let expected = whole.noise_bandwidth() / 1_000.0;
+        let mean = whole.average().iter().sum::<f32>() / 256.0;
+        assert!((mean as f64 / expected - 1.0).abs() < 0.05);
+
+        for ((&peak, &min), average) in whole
+            .peak_hold()
+            .iter()
+            .zip(whole.min_hold())
+            .zip(whole.average())
+        {
+            assert!(min <= average && average <= peak);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use num_complex::Complex;
+
+use super::{AlignedResampler, Limiter, Nco, Resampler};
+use crate::util::f32_to_i8;
+
+/// Combines several baseband signals at different offsets into one signal for transmission.
+///
+/// Each channel is resampled to the output rate, mixed to its offset and scaled by its gain.
+/// The sum is normalized so the channels fit in full scale together, and a [`Limiter`] catches
+/// the peaks that remain, so the combined signal is never clipped when converted to 8-bit
+/// samples.
+///
+/// By default the sum is scaled by the total gain of the channels, which can never exceed full
+/// scale but wastes most of the range when the channels are uncorrelated. A crest factor set with
+/// [`Synthesizer::with_crest_factor`] keeps a higher level and leaves the rare peaks to the
+/// limiter.
+pub struct Synthesizer {
+    sample_rate: u32,
+    crest_factor: Option<f32>,
+    channels: Vec<Channel>,
+    limiter: Limiter,
+    mixed: Vec<Complex<f32>>,
+}
+
+struct Channel {
+    resampler: Option<AlignedResampler<Complex<f32>>>,
+    nco: Nco,
+    gain: f32,
+    /// Samples at the output rate waiting for the other channels.
+    pending: Vec<Complex<f32>>,
+}
+
+impl Synthesizer {
+    /// Creates a synthesizer with output at `sample_rate` and a limiter with a lookahead of
+    /// 1 ms and a release of 50 ms.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            crest_factor: None,
+            channels: Vec::new(),
+            limiter: Limiter::new(sample_rate, 1e-3, 50e-3),
+            mixed: Vec::new(),
+        }
+    }
+
+    /// Places the combined RMS level the given number of dB below full scale, assuming every
+    /// channel has an RMS level of one, like a constant envelope signal at full scale.
+    pub fn with_crest_factor(mut self, crest_factor_db: f32) -> Self {
+        self.crest_factor = Some(crest_factor_db);
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Adds a channel with input at `sample_rate`, centered `offset` Hz from the center of the
+    /// output, and returns its index.
+    ///
+    /// # Panics
+    /// If the bandwidth of the channel at the offset does not fit in the output bandwidth.
+    pub fn add_channel(&mut self, sample_rate: u32, offset: f64, gain: f32) -> usize {
+        assert!(
+            offset.abs() + sample_rate as f64 / 2.0 <= self.sample_rate as f64 / 2.0,
+            "channels must be within the output bandwidth"
+        );
+        let resampler = (sample_rate != self.sample_rate)
+            .then(|| AlignedResampler::new(Resampler::from_rates(sample_rate, self.sample_rate)));
+        self.channels.push(Channel {
+            resampler,
+            nco: Nco::new(offset, self.sample_rate as f64),
+            gain,
+            pending: Vec::new(),
+        });
+        self.channels.len() - 1
+    }
+
+    pub fn set_gain(&mut self, channel: usize, gain: f32) {
+        self.channels[channel].gain = gain;
+    }
+
+    /// Moves a channel without a discontinuity in its phase.
+    pub fn set_offset(&mut self, channel: usize, offset: f64) {
+        self.channels[channel].nco.set_freq(offset);
+    }
+
+    /// Scale applied to the sum of the channels.
+    pub fn scale(&self) -> f32 {
+        let gains = self.channels.iter().map(|x| x.gain.abs());
+        let total = match self.crest_factor {
+            Some(crest_factor) => {
+                gains.map(|x| x * x).sum::<f32>().sqrt() * 10f32.powf(crest_factor / 20.0)
+            }
+            None => gains.sum(),
+        };
+        match total > 0.0 {
+            true => 1.0 / total,
+            false => 1.0,
+        }
+    }
+
+    /// Adds samples to a channel. They are output once all channels have samples for the same
+    /// time.
+    pub fn push(&mut self, channel: usize, input: &[Complex<f32>]) {
+        self.channels[channel].push(input);
+    }
+
+    /// Number of samples that all channels have been pushed for.
+    pub fn available(&self) -> usize {
+        self.channels
+            .iter()
+            .map(|x| x.pending.len())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Appends the combined signal for the samples available from all channels to `output`.
+    pub fn mix(&mut self, output: &mut Vec<Complex<f32>>) {
+        let len = self.available();
+        self.sum(len);
+        self.limiter.process(&self.mixed, output);
+    }
+
+    /// Appends the combined signal as 8-bit samples to `output`.
+    pub fn mix_i8(&mut self, output: &mut Vec<Complex<i8>>) {
+        let mut mixed = Vec::new();
+        self.mix(&mut mixed);
+        append_i8(&mixed, output);
+    }
+
+    /// Appends all remaining samples to `output`, treating channels that ended early as silent.
+    pub fn finish(&mut self, output: &mut Vec<Complex<f32>>) {
+        for channel in &mut self.channels {
+            channel.flush();
+        }
+        let len = self
+            .channels
+            .iter()
+            .map(|x| x.pending.len())
+            .max()
+            .unwrap_or(0);
+        for channel in &mut self.channels {
+            channel.pending.resize(len, Complex::default());
+        }
+        self.sum(len);
+        self.limiter.process(&self.mixed, output);
+        self.limiter.finish(output);
+    }
+
+    /// Appends all remaining samples as 8-bit samples to `output`.
+    pub fn finish_i8(&mut self, output: &mut Vec<Complex<i8>>) {
+        let mut mixed = Vec::new();
+        self.finish(&mut mixed);
+        append_i8(&mixed, output);
+    }
+
+    /// Sums and scales the first `len` pending samples of every channel into `mixed`.
+    fn sum(&mut self, len: usize) {
+        let scale = self.scale();
+        self.mixed.clear();
+        self.mixed.resize(len, Complex::default());
+        for channel in &mut self.channels {
+            for (out, x) in self.mixed.iter_mut().zip(channel.pending.drain(..len)) {
+                *out += x;
+            }
+        }
+        self.mixed.iter_mut().for_each(|x| *x *= scale);
+    }
+}
+
+impl Channel {
+    fn push(&mut self, input: &[Complex<f32>]) {
+        let start = self.pending.len();
+        match &mut self.resampler {
+            Some(resampler) => resampler.process(input, &mut self.pending),
+            None => self.pending.extend_from_slice(input),
+        }
+        self.mix_from(start);
+    }
+
+    /// Pushes the last samples through the resampler.
+    fn flush(&mut self) {
+        if let Some(resampler) = &mut self.resampler {
+            let start = self.pending.len();
+            resampler.flush(&mut self.pending);
+            self.mix_from(start);
+        }
+    }
+
+    /// Mixes the samples from `start` to the offset and applies the gain.
+    fn mix_from(&mut self, start: usize) {
+        let gain = self.gain;
+        let samples = &mut self.pending[start..];
+        self.nco.mix(samples);
+        samples.iter_mut().for_each(|x| *x *= gain);
+    }
+}
+
+fn append_i8(input: &[Complex<f32>], output: &mut Vec<Complex<i8>>) {
+    let start = output.len();
+    output.resize(start + input.len(), Complex::default());
+    f32_to_i8(input, &mut output[start..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use num_complex::Complex;
+
+    use super::Synthesizer;
+    use crate::util::ToComplexF32;
+
+    fn tone(freq: f64, sample_rate: f64, len: usize) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| {
+                let turns = (freq * n as f64 / sample_rate).fract();
+                Complex::from_polar(1.0, TAU * turns as f32)
+            })
+            .collect()
+    }
+
+    /// Amplitude of a frequency in the signal.
+    fn amplitude(signal: &[Complex<f32>], freq: f64, sample_rate: f64) -> f32 {
+        let reference = tone(-freq, sample_rate, signal.len());
+        let sum = signal.iter().zip(&reference).map(|(a, b)| a * b);
+        (sum.sum::<Complex<f32>>() / signal.len() as f32).norm()
+    }
+
+    #[test]
+    fn places_channels_at_offsets() {
+        let mut synthesizer = Synthesizer::new(1_000_000);
+        let a = synthesizer.add_channel(50_000, -200_000.0, 1.0);
+        let b = synthesizer.add_channel(200_000, 300_000.0, 0.5);
+        assert_eq!(synthesizer.scale(), 1.0 / 1.5);
+
+        // A 5 kHz tone on the first channel and DC on the second, below full scale so the limiter
+        // stays out of the way
+        let input = tone(5_000.0, 50_000.0, 5_000)
+            .into_iter()
+            .map(|x| x * 0.9)
+            .collect::<Vec<_>>();
+        let mut output = Vec::new();
+        for (block, n) in input.chunks(500).zip(0..) {
+            synthesizer.push(a, block);
+            synthesizer.push(b, &vec![Complex::new(1.0, 0.0); 2_000 - n % 3]);
+            synthesizer.mix(&mut output);
+        }
+        synthesizer.finish(&mut output);
+        assert!((100_000..100_020).contains(&output.len()));
+
+        let output = &output[5_000..95_000];
+        assert!((amplitude(output, -195_000.0, 1e6) - 0.9 / 1.5).abs() < 1e-2);
+        assert!((amplitude(output, 300_000.0, 1e6) - 0.5 / 1.5).abs() < 1e-2);
+        assert!(amplitude(output, 200_000.0, 1e6) < 1e-3);
+    }
+
+    #[test]
+    fn fits_crest_factor_into_full_scale() {
+        let mut synthesizer = Synthesizer::new(1_000_000).with_crest_factor(6.0);
+        let offsets = [-400_000.0, -150_000.0, 10_000.0, 250_000.0, 420_000.0];
+        for &offset in &offsets {
+            let channel = synthesizer.add_channel(50_000, offset, 1.0);
+            synthesizer.push(channel, &vec![Complex::new(1.0, 0.0); 2_500]);
+        }
+
+        let mut output = Vec::new();
+        synthesizer.mix_i8(&mut output);
+        synthesizer.finish_i8(&mut output);
+        assert!((50_000..50_020).contains(&output.len()));
+        let output = &output[1_000..49_000];
+
+        // The peaks would reach 5 / sqrt(5) / 2 = 1.12 of full scale without the limiter
+        let peak = output
+            .iter()
+            .map(|x| x.re.unsigned_abs().max(x.im.unsigned_abs()))
+            .max()
+            .unwrap();
+        assert!((120..=127).contains(&peak));
+        let rms = output.iter().map(|x| x.to_f32().norm_sqr()).sum::<f32>() / 48_000.0;
+        // The limiter lowers the level slightly from the 6 dB below full scale
+        assert!(rms.sqrt() > 0.4 && rms.sqrt() < 0.5);
+    }
+
+    #[test]
+    fn aligns_resampled_channels() {
+        // The same tone at two input rates and opposite gains cancels once both are aligned
+        let mut synthesizer = Synthesizer::new(1_000_000);
+        let a = synthesizer.add_channel(1_000_000, 0.0, 1.0);
+        let b = synthesizer.add_channel(250_000, 0.0, -1.0);
+        synthesizer.push(a, &tone(10_000.0, 1e6, 100_000));
+        synthesizer.push(b, &tone(10_000.0, 250e3, 25_000));
+
+        let mut output = Vec::new();
+        synthesizer.finish(&mut output);
+        assert!((100_000..100_004).contains(&output.len()));
+        assert!(output[1_000..99_000].iter().all(|x| x.norm() < 1e-2));
+    }
+}
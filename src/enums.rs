@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceType {
     Jellybean = 0,
     Jawbreaker = 1,
@@ -24,4 +25,16 @@ impl DeviceType {
             _ => DeviceType::Undetected,
         }
     }
+
+    /// The name libhackrf uses for the board.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceType::Jellybean => "Jellybean",
+            DeviceType::Jawbreaker => "Jawbreaker",
+            DeviceType::Hackrf1Og | DeviceType::Hackrf1R9 => "HackRF One",
+            DeviceType::Rad1O => "rad1o",
+            DeviceType::Unrecognized => "unrecognized",
+            DeviceType::Undetected => "undetected",
+        }
+    }
 }
@@ -1,25 +1,44 @@
 use std::{
     error::Error,
     fmt::{self, Display},
+    io,
 };
 
 pub type Result<T> = std::result::Result<T, HackrfError>;
 
 #[derive(Debug)]
 pub enum HackrfError {
-    InvalidParam = -2,
-    NotFound = -5,
-    Busy = -6,
-    NoMem = -11,
-    Libusb = -1000,
-    Thread = -1001,
-    StreamingThreadErr = -1002,
-    StreamingStopped = -1003,
-    StreamingExitCalled = -1004,
-    Other = -9999,
+    InvalidParam,
+    NotFound,
+    Busy,
+    NoMem,
+    Libusb,
+    Thread,
+    StreamingThreadErr,
+    StreamingStopped,
+    StreamingExitCalled,
+    Other,
+    /// Reading a recording replayed in place of a device failed.
+    Io(io::Error),
 }
 
 impl HackrfError {
+    /// The libhackrf error code. I/O errors have no code of their own and map to `Other`.
+    pub fn id(&self) -> i32 {
+        match self {
+            HackrfError::InvalidParam => -2,
+            HackrfError::NotFound => -5,
+            HackrfError::Busy => -6,
+            HackrfError::NoMem => -11,
+            HackrfError::Libusb => -1000,
+            HackrfError::Thread => -1001,
+            HackrfError::StreamingThreadErr => -1002,
+            HackrfError::StreamingStopped => -1003,
+            HackrfError::StreamingExitCalled => -1004,
+            HackrfError::Other | HackrfError::Io(_) => -9999,
+        }
+    }
+
     pub fn from_id(id: i32) -> Result<()> {
         Err(match id {
             0 | 1 => return Ok(()),
@@ -40,8 +59,24 @@ impl HackrfError {
 
 impl Display for HackrfError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!("{self:?}"))
+        match self {
+            HackrfError::Io(err) => err.fmt(f),
+            _ => f.write_fmt(format_args!("{self:?}")),
+        }
     }
 }
 
-impl Error for HackrfError {}
+impl Error for HackrfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HackrfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HackrfError {
+    fn from(err: io::Error) -> Self {
+        HackrfError::Io(err)
+    }
+}
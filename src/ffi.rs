@@ -1,6 +1,9 @@
 #![allow(improper_ctypes)]
 
-use std::ffi::{c_char, c_double, c_int, c_uchar, c_uint, c_ulonglong, c_void};
+use std::{
+    ffi::{c_char, c_double, c_int, c_uchar, c_uint, c_ulonglong, c_void},
+    fmt::{self, Display},
+};
 
 #[repr(C)]
 pub struct HackrfDevice;
@@ -15,19 +18,49 @@ pub struct HackrfTransfer {
     pub tx_ctx: *mut c_void,
 }
 
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct SerialNumber {
     pub part_id: [c_uint; 2],
     pub serial_no: [c_uint; 4],
 }
 
+impl Display for SerialNumber {
+    /// Formats the serial number the same way as the USB serial string descriptor.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in self.serial_no {
+            write!(f, "{part:08x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct M0State {
+    pub requested_mode: u16,
+    pub request_flag: u16,
+    pub active_mode: u32,
+    pub m0_count: u32,
+    pub m1_count: u32,
+    pub num_shortfalls: u32,
+    pub longest_shortfall: u32,
+    pub shortfall_limit: u32,
+    pub threshold: u32,
+    pub next_mode: u32,
+    pub error: u32,
+}
+
 #[link(name = "hackrf")]
 extern "C" {
     pub fn hackrf_init() -> c_int;
     pub fn hackrf_exit() -> c_int;
 
     pub fn hackrf_open(device: *mut *mut HackrfDevice) -> c_int;
+    pub fn hackrf_open_by_serial(
+        desired_serial_number: *const c_char,
+        device: *mut *mut HackrfDevice,
+    ) -> c_int;
     pub fn hackrf_close(device: *mut HackrfDevice) -> c_int;
 
     pub fn hackrf_start_rx(
@@ -45,6 +78,8 @@ extern "C" {
     pub fn hackrf_stop_tx(device: *mut HackrfDevice) -> c_int;
 
     pub fn hackrf_is_streaming(device: *mut HackrfDevice) -> c_int;
+    #[cfg(feature = "m0-state")]
+    pub fn hackrf_get_m0_state(device: *mut HackrfDevice, value: *mut M0State) -> c_int;
 
     pub fn hackrf_set_baseband_filter_bandwidth(
         device: *mut HackrfDevice,
@@ -0,0 +1,202 @@
+use std::{io, marker::PhantomData};
+
+use num_complex::Complex;
+
+use super::{Block, Sink, Source};
+use crate::{
+    dsp::{
+        Agc, DcBlocker, FilterSample, FirFilter, IqBalance, Limiter, LowPassFilter, OffsetFilter,
+        Resampler,
+    },
+    util::i8_to_f32,
+};
+
+/// Applies a function to every sample, see [`map`].
+pub struct Map<F, In, Out> {
+    function: F,
+    _types: PhantomData<fn(In) -> Out>,
+}
+
+/// A block implemented by a closure, see [`from_fn`].
+pub struct FnBlock<F, In, Out> {
+    function: F,
+    _types: PhantomData<fn(&[In]) -> Out>,
+}
+
+/// A source implemented by a closure, see [`source_fn`].
+pub struct FnSource<F, Out> {
+    function: F,
+    _types: PhantomData<fn() -> Out>,
+}
+
+/// A sink implemented by a closure, see [`sink_fn`].
+pub struct FnSink<F, In> {
+    function: F,
+    _types: PhantomData<fn(&[In])>,
+}
+
+/// Converts 8-bit samples to floating point samples, scaling 127 to 1 so -128 becomes -128/127.
+pub struct ToF32;
+
+/// Creates a block that applies a function to every sample.
+pub fn map<In, Out, F>(function: F) -> Map<F, In, Out>
+where
+    F: FnMut(In) -> Out + Send + 'static,
+    In: Copy + Send + 'static,
+    Out: Send + 'static,
+{
+    Map {
+        function,
+        _types: PhantomData,
+    }
+}
+
+/// Creates a block that processes each input with a closure, which appends to the output.
+pub fn from_fn<In, Out, F>(function: F) -> FnBlock<F, In, Out>
+where
+    F: FnMut(&[In], &mut Vec<Out>) + Send + 'static,
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    FnBlock {
+        function,
+        _types: PhantomData,
+    }
+}
+
+/// Creates a source from a closure that appends the next samples to the output and returns
+/// false at the end of the stream.
+pub fn source_fn<Out, F>(function: F) -> FnSource<F, Out>
+where
+    F: FnMut(&mut Vec<Out>) -> io::Result<bool> + Send + 'static,
+    Out: Send + 'static,
+{
+    FnSource {
+        function,
+        _types: PhantomData,
+    }
+}
+
+/// Creates a sink that passes each input to a closure.
+pub fn sink_fn<In, F>(function: F) -> FnSink<F, In>
+where
+    F: FnMut(&[In]) -> io::Result<()> + Send + 'static,
+    In: Send + 'static,
+{
+    FnSink {
+        function,
+        _types: PhantomData,
+    }
+}
+
+impl<F, In, Out> Block for Map<F, In, Out>
+where
+    F: FnMut(In) -> Out + Send + 'static,
+    In: Copy + Send + 'static,
+    Out: Send + 'static,
+{
+    type In = In;
+    type Out = Out;
+
+    fn process(&mut self, input: &[In], output: &mut Vec<Out>) {
+        output.extend(input.iter().map(|&x| (self.function)(x)));
+    }
+}
+
+impl<F, In, Out> Block for FnBlock<F, In, Out>
+where
+    F: FnMut(&[In], &mut Vec<Out>) + Send + 'static,
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    type In = In;
+    type Out = Out;
+
+    fn process(&mut self, input: &[In], output: &mut Vec<Out>) {
+        (self.function)(input, output);
+    }
+}
+
+impl<F, Out> Source for FnSource<F, Out>
+where
+    F: FnMut(&mut Vec<Out>) -> io::Result<bool> + Send + 'static,
+    Out: Send + 'static,
+{
+    type Out = Out;
+
+    fn produce(&mut self, output: &mut Vec<Out>) -> io::Result<bool> {
+        (self.function)(output)
+    }
+}
+
+impl<F, In> Sink for FnSink<F, In>
+where
+    F: FnMut(&[In]) -> io::Result<()> + Send + 'static,
+    In: Send + 'static,
+{
+    type In = In;
+
+    fn consume(&mut self, input: &[In]) -> io::Result<()> {
+        (self.function)(input)
+    }
+}
+
+impl Block for ToF32 {
+    type In = Complex<i8>;
+    type Out = Complex<f32>;
+
+    fn process(&mut self, input: &[Complex<i8>], output: &mut Vec<Complex<f32>>) {
+        let start = output.len();
+        output.resize(start + input.len(), Complex::default());
+        i8_to_f32(input, &mut output[start..]);
+    }
+}
+
+impl<T: FilterSample> Block for Resampler<T> {
+    type In = T;
+    type Out = T;
+
+    fn process(&mut self, input: &[T], output: &mut Vec<T>) {
+        Resampler::process(self, input, output);
+    }
+}
+
+impl Block for Limiter {
+    type In = Complex<f32>;
+    type Out = Complex<f32>;
+
+    fn process(&mut self, input: &[Complex<f32>], output: &mut Vec<Complex<f32>>) {
+        Limiter::process(self, input, output);
+    }
+
+    fn finish(&mut self, output: &mut Vec<Complex<f32>>) {
+        Limiter::finish(self, output);
+    }
+}
+
+/// Implements [`Block`] for filters with a `process_in_place` method.
+macro_rules! in_place_block {
+    ($($filter:ty => $sample:ty $(where $param:ident)?),* $(,)?) => {
+        $(
+            impl$(<$param: FilterSample>)? Block for $filter {
+                type In = $sample;
+                type Out = $sample;
+
+                fn process(&mut self, input: &[$sample], output: &mut Vec<$sample>) {
+                    let start = output.len();
+                    output.extend_from_slice(input);
+                    self.process_in_place(&mut output[start..]);
+                }
+            }
+        )*
+    };
+}
+
+in_place_block! {
+    Agc<T> => T where T,
+    DcBlocker<T> => T where T,
+    FirFilter<T> => T where T,
+    LowPassFilter<T> => T where T,
+    IqBalance => Complex<f32>,
+    OffsetFilter => Complex<f32>,
+}
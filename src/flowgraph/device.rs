@@ -0,0 +1,193 @@
+use std::{
+    any::Any,
+    collections::VecDeque,
+    io,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use num_complex::Complex;
+
+use super::{Sink, Source, DEFAULT_QUEUE_BLOCKS};
+use crate::{HackRf, TransferInfo};
+
+/// Time to wait for a transfer before checking whether the graph is stopping.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Samples queued for transmission before [`DeviceSink`] waits for the device.
+const TX_QUEUE_SAMPLES: usize = 4 * 131_072;
+
+/// Receives samples from a device. The stream is started with the first call to
+/// [`Source::produce`] and stopped when the source is dropped, so the device should be
+/// configured beforehand.
+///
+/// Transfers are queued between the device and the graph, and dropped when the graph falls
+/// behind, which shows up as overruns in [`HackRf::stream_stats`].
+pub struct DeviceSource {
+    hackrf: HackRf,
+    queue_blocks: usize,
+    receiver: Option<Receiver<Vec<Complex<i8>>>>,
+}
+
+/// Transmits samples with a device. The stream is started with the first samples and stopped
+/// once the queued samples have been sent at the end of the input.
+///
+/// Silence is transmitted when the graph does not keep up.
+pub struct DeviceSink {
+    hackrf: HackRf,
+    queue: Option<Arc<TxQueue>>,
+}
+
+struct TxQueue {
+    samples: Mutex<VecDeque<Complex<i8>>>,
+    /// Notified when samples are taken from the queue.
+    drained: Condvar,
+}
+
+impl DeviceSource {
+    pub fn new(hackrf: HackRf) -> Self {
+        Self {
+            hackrf,
+            queue_blocks: DEFAULT_QUEUE_BLOCKS,
+            receiver: None,
+        }
+    }
+
+    /// Sets the number of transfers queued before they are dropped.
+    pub fn with_queue_blocks(mut self, queue_blocks: usize) -> Self {
+        self.queue_blocks = queue_blocks;
+        self
+    }
+}
+
+impl Source for DeviceSource {
+    type Out = Complex<i8>;
+
+    fn produce(&mut self, output: &mut Vec<Complex<i8>>) -> io::Result<bool> {
+        if self.receiver.is_none() {
+            let (sender, receiver) = mpsc::sync_channel(self.queue_blocks);
+            self.hackrf
+                .start_rx(rx_callback, sender)
+                .map_err(io::Error::other)?;
+            self.receiver = Some(receiver);
+        }
+
+        match self
+            .receiver
+            .as_ref()
+            .unwrap()
+            .recv_timeout(RECEIVE_TIMEOUT)
+        {
+            Ok(samples) => {
+                output.extend_from_slice(&samples);
+                Ok(true)
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(self.hackrf.is_streaming()),
+            Err(RecvTimeoutError::Disconnected) => Ok(false),
+        }
+    }
+}
+
+impl Drop for DeviceSource {
+    fn drop(&mut self) {
+        if self.receiver.is_some() {
+            let _ = self.hackrf.stop_rx();
+        }
+    }
+}
+
+impl DeviceSink {
+    pub fn new(hackrf: HackRf) -> Self {
+        Self {
+            hackrf,
+            queue: None,
+        }
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        match self.queue.take() {
+            Some(_) => self.hackrf.stop_tx().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sink for DeviceSink {
+    type In = Complex<i8>;
+
+    fn consume(&mut self, input: &[Complex<i8>]) -> io::Result<()> {
+        if self.queue.is_none() {
+            let queue = Arc::new(TxQueue {
+                samples: Mutex::new(VecDeque::new()),
+                drained: Condvar::new(),
+            });
+            queue.samples.lock().unwrap().extend(input);
+            self.hackrf
+                .start_tx(tx_callback, queue.clone())
+                .map_err(io::Error::other)?;
+            self.queue = Some(queue);
+            return Ok(());
+        }
+
+        let queue = self.queue.as_ref().unwrap();
+        let mut samples = queue.samples.lock().unwrap();
+        while samples.len() > TX_QUEUE_SAMPLES {
+            self.hackrf.check_streaming().map_err(io::Error::other)?;
+            samples = queue
+                .drained
+                .wait_timeout(samples, RECEIVE_TIMEOUT)
+                .unwrap()
+                .0;
+        }
+        samples.extend(input);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(queue) = &self.queue {
+            let mut samples = queue.samples.lock().unwrap();
+            while !samples.is_empty() && self.hackrf.is_streaming() {
+                samples = queue
+                    .drained
+                    .wait_timeout(samples, RECEIVE_TIMEOUT)
+                    .unwrap()
+                    .0;
+            }
+        }
+        self.stop()
+    }
+}
+
+impl Drop for DeviceSink {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+fn rx_callback(hackrf: &HackRf, samples: &[Complex<i8>], _info: &TransferInfo, user: &dyn Any) {
+    let sender = user.downcast_ref::<SyncSender<Vec<Complex<i8>>>>().unwrap();
+    if let Err(TrySendError::Full(_)) = sender.try_send(samples.to_vec()) {
+        if let Some(stats) = hackrf.stream_stats() {
+            stats.record_dropped(samples.len());
+        }
+    }
+}
+
+fn tx_callback(
+    _hackrf: &HackRf,
+    samples: &mut [Complex<i8>],
+    _info: &TransferInfo,
+    user: &dyn Any,
+) {
+    let queue = user.downcast_ref::<Arc<TxQueue>>().unwrap();
+    let mut queued = queue.samples.lock().unwrap();
+
+    let available = queued.len().min(samples.len());
+    for (sample, queued) in samples.iter_mut().zip(queued.drain(..available)) {
+        *sample = queued;
+    }
+    samples[available..].fill(Complex::default());
+    queue.drained.notify_all();
+}
@@ -0,0 +1,63 @@
+use std::io;
+
+use num_complex::Complex;
+
+use super::{Sink, Source};
+use crate::recording::file::{IqReader, IqWriter};
+
+/// Samples read from a file at a time.
+const READ_BLOCK: usize = 65_536;
+
+/// Reads samples from an IQ file as fast as the graph consumes them.
+pub struct FileSource {
+    reader: IqReader,
+}
+
+/// Writes samples to an IQ file, which is finalized at the end of the input.
+pub struct FileSink {
+    writer: Option<IqWriter>,
+}
+
+impl FileSource {
+    pub fn new(reader: IqReader) -> Self {
+        Self { reader }
+    }
+}
+
+impl Source for FileSource {
+    type Out = Complex<f32>;
+
+    fn produce(&mut self, output: &mut Vec<Complex<f32>>) -> io::Result<bool> {
+        let start = output.len();
+        output.resize(start + READ_BLOCK, Complex::default());
+        let read = self.reader.read(&mut output[start..])?;
+        output.truncate(start + read);
+        Ok(read > 0)
+    }
+}
+
+impl FileSink {
+    pub fn new(writer: IqWriter) -> Self {
+        Self {
+            writer: Some(writer),
+        }
+    }
+}
+
+impl Sink for FileSink {
+    type In = Complex<f32>;
+
+    fn consume(&mut self, input: &[Complex<f32>]) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.write(input),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! A lightweight flowgraph for building streaming receivers and transmitters out of blocks.
+//!
+//! A graph starts at [`Source`]s, passes the samples through [`Block`]s and ends in [`Sink`]s.
+//! Every source, block and sink runs on its own worker thread, connected by bounded queues of
+//! sample vectors, so a slow stage applies back pressure instead of buffering without limit.
+//! Blocks keep their state for the whole stream, and [`Flowgraph::fan_out`] feeds one stream to
+//! several branches.
+//!
+//! ```ignore
+//! let mut graph = Flowgraph::new();
+//! let iq = graph.source(DeviceSource::new(hackrf));
+//! let iq = graph.block(iq, ToF32);
+//! let [spectrum, channel] = graph.fan_out(iq);
+//! let channel = graph.block(channel, Resampler::from_rates(2_000_000, 250_000));
+//! graph.sink(channel, FileSink::new(writer));
+//! graph.run()?;
+//! ```
+//!
+//! The graph ends when every source has ended, after the blocks have flushed the samples they
+//! held back. A sink that fails or a stream that is dropped without being connected ends the
+//! stages feeding it.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+mod blocks;
+mod device;
+mod file;
+pub use blocks::{from_fn, map, sink_fn, source_fn, FnBlock, FnSink, FnSource, Map, ToF32};
+pub use device::{DeviceSink, DeviceSource};
+pub use file::{FileSink, FileSource};
+
+/// Number of sample vectors queued between two stages before the producer waits.
+pub const DEFAULT_QUEUE_BLOCKS: usize = 16;
+
+/// A processing step that turns input samples into output samples.
+pub trait Block: Send + 'static {
+    type In: Send + 'static;
+    type Out: Send + 'static;
+
+    /// Processes a block of input, appending the output to `output`.
+    fn process(&mut self, input: &[Self::In], output: &mut Vec<Self::Out>);
+
+    /// Called once the input has ended, to output any samples held back.
+    fn finish(&mut self, _output: &mut Vec<Self::Out>) {}
+}
+
+/// The start of a stream, such as a device or a file.
+pub trait Source: Send + 'static {
+    type Out: Send + 'static;
+
+    /// Appends the next samples to `output`, which may add none if nothing is available yet.
+    /// Returns false at the end of the stream.
+    fn produce(&mut self, output: &mut Vec<Self::Out>) -> io::Result<bool>;
+}
+
+/// The end of a stream, such as a device or a file.
+pub trait Sink: Send + 'static {
+    type In: Send + 'static;
+
+    fn consume(&mut self, input: &[Self::In]) -> io::Result<()>;
+
+    /// Called once the input has ended.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The output of a stage, which must be connected to exactly one input.
+pub struct Stream<T> {
+    receiver: Receiver<Vec<T>>,
+}
+
+type Worker = Box<dyn FnOnce(&AtomicBool) -> io::Result<()> + Send>;
+
+/// A graph of stages that is built up and then started with [`Flowgraph::start`].
+pub struct Flowgraph {
+    queue_blocks: usize,
+    workers: Vec<Worker>,
+}
+
+/// A started flowgraph.
+pub struct Running {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl Flowgraph {
+    pub fn new() -> Self {
+        Self {
+            queue_blocks: DEFAULT_QUEUE_BLOCKS,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Sets the number of sample vectors queued between stages that are added afterwards.
+    pub fn with_queue_blocks(self, queue_blocks: usize) -> Self {
+        Self {
+            queue_blocks,
+            ..self
+        }
+    }
+
+    fn channel<T>(&self) -> (SyncSender<Vec<T>>, Stream<T>) {
+        let (sender, receiver) = mpsc::sync_channel(self.queue_blocks);
+        (sender, Stream { receiver })
+    }
+
+    pub fn source<S: Source>(&mut self, mut source: S) -> Stream<S::Out> {
+        let (sender, stream) = self.channel();
+        self.workers.push(Box::new(move |stop| {
+            while !stop.load(Ordering::Relaxed) {
+                let mut output = Vec::new();
+                let more = source.produce(&mut output)?;
+                if !output.is_empty() && sender.send(output).is_err() {
+                    break;
+                }
+                if !more {
+                    break;
+                }
+            }
+            Ok(())
+        }));
+        stream
+    }
+
+    pub fn block<B: Block>(&mut self, input: Stream<B::In>, mut block: B) -> Stream<B::Out> {
+        let (sender, stream) = self.channel();
+        self.workers.push(Box::new(move |_| {
+            for samples in input.receiver {
+                let mut output = Vec::new();
+                block.process(&samples, &mut output);
+                if !output.is_empty() && sender.send(output).is_err() {
+                    return Ok(());
+                }
+            }
+
+            let mut output = Vec::new();
+            block.finish(&mut output);
+            if !output.is_empty() {
+                let _ = sender.send(output);
+            }
+            Ok(())
+        }));
+        stream
+    }
+
+    /// Copies a stream to `N` branches. Branches that are dropped are skipped, and the stream
+    /// only ends early once all of them are.
+    pub fn fan_out<T: Clone + Send + 'static, const N: usize>(
+        &mut self,
+        input: Stream<T>,
+    ) -> [Stream<T>; N] {
+        let (senders, streams): (Vec<_>, Vec<_>) = (0..N).map(|_| self.channel()).unzip();
+        self.workers.push(Box::new(move |_| {
+            let mut senders = senders.into_iter().map(Some).collect::<Vec<_>>();
+            for samples in input.receiver {
+                for slot in senders.iter_mut() {
+                    if slot
+                        .as_ref()
+                        .is_some_and(|x| x.send(samples.clone()).is_err())
+                    {
+                        *slot = None;
+                    }
+                }
+                if senders.iter().all(Option::is_none) {
+                    break;
+                }
+            }
+            Ok(())
+        }));
+
+        match streams.try_into() {
+            Ok(streams) => streams,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    pub fn sink<S: Sink>(&mut self, input: Stream<S::In>, mut sink: S) {
+        self.workers.push(Box::new(move |_| {
+            for samples in input.receiver {
+                sink.consume(&samples)?;
+            }
+            sink.finish()
+        }));
+    }
+
+    /// Starts a worker thread for every stage.
+    pub fn start(self) -> Running {
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = self
+            .workers
+            .into_iter()
+            .map(|worker| {
+                let stop = stop.clone();
+                thread::spawn(move || worker(&stop))
+            })
+            .collect();
+
+        Running { stop, threads }
+    }
+
+    /// Runs the graph until every source has ended.
+    pub fn run(self) -> io::Result<()> {
+        self.start().wait()
+    }
+}
+
+impl Default for Flowgraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Running {
+    /// Ends the streams of all sources. The rest of the graph finishes once the queued samples
+    /// have been processed, see [`Running::wait`].
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once every stage has finished.
+    pub fn is_finished(&self) -> bool {
+        self.threads.iter().all(|x| x.is_finished())
+    }
+
+    /// Waits for every stage to finish and returns the first error of any of them.
+    pub fn wait(self) -> io::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            let stage = thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("flowgraph stage panicked")));
+            if result.is_ok() {
+                result = stage;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{sink_fn, source_fn, Block, DeviceSource, Flowgraph};
+    use crate::{
+        sim::{Pacing, Simulator},
+        Config, HackRf,
+    };
+
+    /// Outputs each sample with the next input, and the last one when the input ends.
+    struct HoldLast(Option<u32>);
+
+    impl Block for HoldLast {
+        type In = u32;
+        type Out = u32;
+
+        fn process(&mut self, input: &[u32], output: &mut Vec<u32>) {
+            for &x in input {
+                output.extend(self.0.replace(x));
+            }
+        }
+
+        fn finish(&mut self, output: &mut Vec<u32>) {
+            output.extend(self.0.take());
+        }
+    }
+
+    /// A source of the numbers below `end`, in blocks of ten.
+    fn count_to(end: u32) -> impl FnMut(&mut Vec<u32>) -> io::Result<bool> + Send + 'static {
+        let mut next = 0;
+        move |output| {
+            let stop = (next + 10).min(end);
+            output.extend(next..stop);
+            next = stop;
+            Ok(next < end)
+        }
+    }
+
+    type Collected<T> = Arc<Mutex<Vec<T>>>;
+
+    /// A sink appending to the returned vector.
+    fn collect<T: Copy + Send + 'static>(
+    ) -> (Collected<T>, impl FnMut(&[T]) -> io::Result<()> + Send) {
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let collected = collected.clone();
+            move |input: &[T]| {
+                collected.lock().unwrap().extend_from_slice(input);
+                Ok(())
+            }
+        };
+        (collected, sink)
+    }
+
+    #[test]
+    fn finish_reaches_sink() {
+        let mut graph = Flowgraph::new();
+        let numbers = graph.source(source_fn(count_to(95)));
+        let numbers = graph.block(numbers, HoldLast(None));
+        let (collected, sink) = collect();
+        graph.sink(numbers, sink_fn(sink));
+        graph.run().unwrap();
+
+        assert_eq!(*collected.lock().unwrap(), (0..95).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dropped_branch_leaves_others_running() {
+        let mut graph = Flowgraph::new().with_queue_blocks(1);
+        let numbers = graph.source(source_fn(count_to(1_000)));
+        let [kept, dropped] = graph.fan_out(numbers);
+        drop(dropped);
+        let (collected, sink) = collect();
+        graph.sink(kept, sink_fn(sink));
+        graph.run().unwrap();
+
+        assert_eq!(*collected.lock().unwrap(), (0..1_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sink_error_stops_graph() {
+        let mut graph = Flowgraph::new().with_queue_blocks(1);
+        let numbers = graph.source(source_fn(count_to(u32::MAX)));
+        let numbers = graph.block(numbers, HoldLast(None));
+        let mut consumed = 0;
+        graph.sink(
+            numbers,
+            sink_fn(move |_: &[u32]| {
+                consumed += 1;
+                match consumed {
+                    3 => Err(io::Error::other("sink failed")),
+                    _ => Ok(()),
+                }
+            }),
+        );
+
+        let err = graph.run().unwrap_err();
+        assert_eq!(err.to_string(), "sink failed");
+    }
+
+    /// Receives the same sample as fast as possible.
+    struct Constant;
+
+    impl Simulator for Constant {
+        fn initial_config(&self) -> Config {
+            Config {
+                sample_rate: Some(10_000_000),
+                ..Config::default()
+            }
+        }
+
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(Complex::new(1, -1));
+            true
+        }
+
+        fn pacing(&self) -> Pacing {
+            Pacing::Fast
+        }
+    }
+
+    #[test]
+    fn stop_ends_device_source() {
+        let hackrf = HackRf::simulated(Constant);
+        let mut graph = Flowgraph::new().with_queue_blocks(1);
+        let iq = graph.source(DeviceSource::new(hackrf.clone()).with_queue_blocks(1));
+        let (collected, mut sink) = collect();
+        // Falls behind the device, so transfers are dropped
+        graph.sink(
+            iq,
+            sink_fn(move |input: &[Complex<i8>]| {
+                thread::sleep(Duration::from_millis(5));
+                sink(input)
+            }),
+        );
+
+        let running = graph.start();
+        let start = Instant::now();
+        while hackrf.stream_stats().is_none_or(|x| x.overruns() == 0) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no transfers dropped"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        running.stop();
+        running.wait().unwrap();
+
+        assert!(!hackrf.is_streaming());
+        assert!(!collected.lock().unwrap().is_empty());
+        assert!(collected
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|&x| x == Complex::new(1, -1)));
+        let stats = hackrf.stream_stats().unwrap();
+        assert_eq!(stats.dropped_samples(), stats.overruns() * 131_072);
+    }
+}
@@ -2,24 +2,47 @@
 
 use std::{
     any::Any,
-    ffi::c_void,
+    ffi::{c_void, CString},
     mem, ptr,
     sync::{
-        atomic::{AtomicPtr, AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, Weak,
     },
+    thread,
 };
 
+pub mod agc;
+mod config;
+pub use config::Config;
+pub mod dsp;
 mod enums;
 pub mod error;
 pub mod ffi;
+pub mod flowgraph;
 pub use enums::DeviceType;
+pub mod recording;
+pub mod remote;
+pub mod rtl_tcp;
+pub mod sample;
+pub mod sdr;
+pub mod sim;
+pub mod stats;
+pub mod supervisor;
 mod transfer;
+pub use transfer::TransferInfo;
 pub mod util;
 
+use agc::HardwareAgc;
 use error::{HackrfError, Result};
+#[cfg(feature = "m0-state")]
+use ffi::M0State;
 use ffi::SerialNumber;
-use transfer::{rx_callback, tx_callback, ReceiveCallback, TransferContext, TransmitCallback};
+use sim::{replay::Replay, SimStream, Simulator};
+use stats::{StreamMode, StreamStats, Watchdog};
+use supervisor::Supervisor;
+use transfer::{
+    rx_callback, tx_callback, ContextHandle, ReceiveCallback, TransferContext, TransmitCallback,
+};
 
 static DEVICE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
@@ -29,14 +52,38 @@ pub struct HackRf {
     inner: Arc<HackRfInner>,
 }
 
+/// A handle that does not keep the device open, held by streams and monitor threads so that
+/// dropping the last [`HackRf`] stops them.
+#[derive(Clone)]
+pub(crate) struct WeakHackRf(Weak<HackRfInner>);
+
 struct HackRfInner {
-    device: *mut ffi::HackrfDevice,
-    user_data: AtomicPtr<c_void>,
+    device: AtomicPtr<ffi::HackrfDevice>,
+    serial_number: OnceLock<String>,
+    /// Context of the last stream, until it is stopped.
+    context: Mutex<Option<ContextHandle>>,
+    /// Set by [`HackRf::end_stream`] until the next stream is started.
+    stream_ended: AtomicBool,
+    config: Mutex<Config>,
+    stats: Mutex<Option<Arc<StreamStats>>>,
+    watchdog: Mutex<Option<Watchdog>>,
+    supervisor: Mutex<Option<Supervisor>>,
+    agc: Mutex<Option<HardwareAgc>>,
+    simulator: Option<Box<dyn Simulator>>,
+    sim_stream: Mutex<Option<SimStream>>,
 }
 
 impl HackRf {
     /// Connects to a HackRF device.
+    ///
+    /// If the `HACKRF_REPLAY` environment variable is set, a simulated device replaying the
+    /// recording at that path is returned instead, see [`Replay::from_env`]. Failing to open the
+    /// recording returns [`HackrfError::Io`].
     pub fn open() -> Result<HackRf> {
+        if let Some(replay) = Replay::from_env() {
+            return Ok(Self::simulated(replay?));
+        }
+
         if DEVICE_COUNT.fetch_add(1, Ordering::Relaxed) == 0 {
             unsafe { HackrfError::from_id(ffi::hackrf_init())? }
         }
@@ -44,23 +91,128 @@ impl HackRf {
         let mut device = std::ptr::null_mut();
         unsafe { HackrfError::from_id(ffi::hackrf_open(&mut device))? }
 
-        Ok(Self {
+        let hackrf = Self::new(device, None);
+        if let Ok(serial_number) = hackrf.get_serial_number() {
+            let _ = hackrf.inner.serial_number.set(serial_number.to_string());
+        }
+
+        Ok(hackrf)
+    }
+
+    /// Creates a simulated device that streams to and from the simulator instead of hardware.
+    pub fn simulated(simulator: impl Simulator + 'static) -> HackRf {
+        Self::new(ptr::null_mut(), Some(Box::new(simulator)))
+    }
+
+    fn new(device: *mut ffi::HackrfDevice, simulator: Option<Box<dyn Simulator>>) -> Self {
+        let config = simulator
+            .as_ref()
+            .map(|x| x.initial_config())
+            .unwrap_or_default();
+
+        Self {
             inner: Arc::new(HackRfInner {
-                device,
-                user_data: AtomicPtr::new(ptr::null_mut()),
+                device: AtomicPtr::new(device),
+                serial_number: OnceLock::new(),
+                context: Mutex::new(None),
+                stream_ended: AtomicBool::new(false),
+                config: Mutex::new(config),
+                stats: Mutex::new(None),
+                watchdog: Mutex::new(None),
+                supervisor: Mutex::new(None),
+                agc: Mutex::new(None),
+                simulator,
+                sim_stream: Mutex::new(None),
             }),
-        })
+        }
+    }
+
+    /// Reopens the device with the serial number it was originally opened with and re-applies
+    /// the last configuration. Used to recover after the device was unplugged.
+    pub fn reopen(&self) -> Result<()> {
+        if self.simulator().is_some() {
+            return self.config().apply(self);
+        }
+
+        let serial_number = self
+            .inner
+            .serial_number
+            .get()
+            .ok_or(HackrfError::NotFound)?;
+        let serial_number = CString::new(serial_number.as_str()).unwrap();
+
+        let mut device = std::ptr::null_mut();
+        unsafe {
+            HackrfError::from_id(ffi::hackrf_open_by_serial(
+                serial_number.as_ptr(),
+                &mut device,
+            ))?
+        }
+
+        // The old handle belongs to the unplugged device, and closing it can hang in libusb or
+        // free it while other threads still use it, so it is never closed.
+        self.inner.device.swap(device, Ordering::Relaxed);
+
+        self.config().apply(self)
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakHackRf {
+        WeakHackRf(Arc::downgrade(&self.inner))
     }
 
     /// Gets the internial representation of the HackRF device. This can be used
     /// with unsafe FFI functions if needed.
     #[inline(always)]
     pub fn device(&self) -> *mut ffi::HackrfDevice {
-        self.inner.device
+        self.inner.device.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if this is a simulated device created with [`HackRf::simulated`], other than
+    /// a remote device, which is backed by real hardware.
+    pub fn is_simulated(&self) -> bool {
+        self.simulator().is_some_and(|x| !x.is_remote())
+    }
+
+    /// Returns true if this device is controlled over the network, see [`remote::connect`].
+    pub fn is_remote(&self) -> bool {
+        self.simulator().is_some_and(|x| x.is_remote())
+    }
+
+    pub(crate) fn simulator(&self) -> Option<&dyn Simulator> {
+        self.inner.simulator.as_deref()
+    }
+
+    /// Gets the last configuration applied to the device.
+    pub fn config(&self) -> Config {
+        *self.inner.config.lock().unwrap()
+    }
+
+    /// Applies a setting with libhackrf, or checks it with the simulator of a simulated device,
+    /// and records it in the configuration.
+    fn set(
+        &self,
+        update: impl Fn(&mut Config),
+        apply: impl FnOnce(*mut ffi::HackrfDevice) -> i32,
+    ) -> Result<()> {
+        match self.simulator() {
+            Some(simulator) => {
+                let mut config = self.config();
+                update(&mut config);
+                simulator.configure(&config)?;
+            }
+            None => HackrfError::from_id(apply(self.device()))?,
+        }
+
+        update(&mut self.inner.config.lock().unwrap());
+        Ok(())
     }
 
     /// Gets the device serial number.
     pub fn get_serial_number(&self) -> Result<SerialNumber> {
+        if let Some(simulator) = self.simulator() {
+            return Ok(simulator.serial_number());
+        }
+
         let mut serial_number = SerialNumber::default();
         unsafe {
             HackrfError::from_id(ffi::hackrf_board_partid_serialno_read(
@@ -73,6 +225,10 @@ impl HackRf {
 
     /// Read hackrf_board_id from a device and convert it to a DeviceType.
     pub fn get_device_type(&self) -> Result<DeviceType> {
+        if let Some(simulator) = self.simulator() {
+            return Ok(simulator.device_type());
+        }
+
         let mut value = 0;
         unsafe { HackrfError::from_id(ffi::hackrf_board_id_read(self.device(), &mut value)) }?;
         Ok(DeviceType::from_id(value))
@@ -80,6 +236,10 @@ impl HackRf {
 
     /// Read HackRF firmware version as a string.
     pub fn version(&self) -> String {
+        if let Some(simulator) = self.simulator() {
+            return simulator.version();
+        }
+
         let mut version = vec![0; 32];
 
         unsafe {
@@ -101,107 +261,404 @@ impl HackRf {
 
     /// Sets the center frequency in Hz.
     pub fn set_freq(&self, freq: u64) -> Result<()> {
-        unsafe { HackrfError::from_id(ffi::hackrf_set_freq(self.device(), freq)) }
+        self.set(
+            |config| config.freq = Some(freq),
+            |device| unsafe { ffi::hackrf_set_freq(device, freq) },
+        )
     }
 
     /// Sets the sample rate in Hz.
     pub fn set_sample_rate(&self, sample_rate: u32) -> Result<()> {
-        unsafe {
-            HackrfError::from_id(ffi::hackrf_set_sample_rate_manual(
-                self.device(),
-                sample_rate,
-                1,
-            ))
-        }
+        self.set(
+            |config| config.sample_rate = Some(sample_rate),
+            |device| unsafe { ffi::hackrf_set_sample_rate_manual(device, sample_rate, 1) },
+        )
     }
 
     /// Sets the state of the externial amplifier.
     pub fn set_amp_enable(&self, enable: bool) -> Result<()> {
-        unsafe { HackrfError::from_id(ffi::hackrf_set_amp_enable(self.device(), enable as u8)) }
+        self.set(
+            |config| config.amp_enable = Some(enable),
+            |device| unsafe { ffi::hackrf_set_amp_enable(device, enable as u8) },
+        )
     }
 
     /// Low noise amplifier gain.
     /// Between 0d and 40d in steps of 8dB.
     pub fn set_lna_gain(&self, gain: u32) -> Result<()> {
-        unsafe { HackrfError::from_id(ffi::hackrf_set_lna_gain(self.device(), gain)) }
+        self.set(
+            |config| config.lna_gain = Some(gain),
+            |device| unsafe { ffi::hackrf_set_lna_gain(device, gain) },
+        )
     }
 
     /// Variable gain amplifier. Range 0-62 (step 2dB).
     pub fn set_rxvga_gain(&self, gain: u32) -> Result<()> {
-        unsafe { HackrfError::from_id(ffi::hackrf_set_vga_gain(self.device(), gain)) }
+        self.set(
+            |config| config.rxvga_gain = Some(gain),
+            |device| unsafe { ffi::hackrf_set_vga_gain(device, gain) },
+        )
     }
 
     /// Transmit variable gain amplifier. Range 0-47 (step 1dB).
     pub fn set_txvga_gain(&self, gain: u32) -> Result<()> {
-        unsafe { HackrfError::from_id(ffi::hackrf_set_txvga_gain(self.device(), gain)) }
+        self.set(
+            |config| config.txvga_gain = Some(gain),
+            |device| unsafe { ffi::hackrf_set_txvga_gain(device, gain) },
+        )
     }
 
     pub fn set_baseband_filter_bandwidth(&self, bandwidth_hz: u32) -> Result<()> {
+        self.set(
+            |config| config.baseband_filter_bandwidth = Some(bandwidth_hz),
+            |device| unsafe {
+                let bandwidth = ffi::hackrf_compute_baseband_filter_bw(bandwidth_hz);
+                ffi::hackrf_set_baseband_filter_bandwidth(device, bandwidth)
+            },
+        )
+    }
+
+    /// Gets the state of the M0 coprocessor, which includes the number of buffer shortfalls
+    /// (RX overruns or TX underruns) seen by the firmware. Requires firmware 2023.01.1 or newer
+    /// and the `m0-state` feature.
+    #[cfg(feature = "m0-state")]
+    pub fn get_m0_state(&self) -> Result<M0State> {
+        let mut state = M0State::default();
+        if self.simulator().is_some() {
+            return Ok(state);
+        }
+
+        unsafe { HackrfError::from_id(ffi::hackrf_get_m0_state(self.device(), &mut state))? }
+        Ok(state)
+    }
+
+    /// Gets the statistics of the current or most recent stream.
+    /// With the `m0-state` feature, gaps are detected from the shortfalls counted by the firmware
+    /// where available. Otherwise, and for the size of each gap, dropped samples are estimated
+    /// from the wall-clock time between transfers.
+    pub fn stream_stats(&self) -> Option<Arc<StreamStats>> {
+        self.inner.stats.lock().unwrap().clone()
+    }
+
+    /// Sets the watchdog that will monitor the achieved sample rate of the following streams.
+    pub fn set_watchdog(&self, watchdog: Option<Watchdog>) {
+        *self.inner.watchdog.lock().unwrap() = watchdog;
+    }
+
+    /// Sets the supervisor that will monitor the following streams for errors.
+    pub fn set_supervisor(&self, supervisor: Option<Supervisor>) {
+        *self.inner.supervisor.lock().unwrap() = supervisor;
+    }
+
+    /// Sets the automatic gain control that will adjust the LNA and VGA gains of the following
+    /// receive streams.
+    pub fn set_agc(&self, agc: Option<HardwareAgc>) {
+        *self.inner.agc.lock().unwrap() = agc;
+    }
+
+    fn new_transfer_context<Callback>(
+        &self,
+        mode: StreamMode,
+        callback: Callback,
+        user_data: Box<dyn Any>,
+    ) -> *mut c_void {
+        let sample_rate = self.config().sample_rate.unwrap_or_default();
+        let measure_level = mode == StreamMode::Receive && self.inner.agc.lock().unwrap().is_some();
+        let stats = Arc::new(StreamStats::new(mode, sample_rate, measure_level));
+        *self.inner.stats.lock().unwrap() = Some(stats.clone());
+
+        let context =
+            TransferContext::new(callback, self.downgrade(), user_data, stats, sample_rate);
+        let context = ContextHandle::new(context);
+        let pointer = context.pointer();
+        self.inner.stream_ended.store(false, Ordering::Relaxed);
+        *self.inner.context.lock().unwrap() = Some(context);
+        pointer
+    }
+
+    fn start_monitors(&self) {
+        #[cfg(feature = "m0-state")]
+        if self.simulator().is_none() {
+            stats::poll_shortfalls(self.clone());
+        }
+
+        if let Some(watchdog) = *self.inner.watchdog.lock().unwrap() {
+            watchdog.spawn(self.clone());
+        }
+
+        if let Some(supervisor) = *self.inner.supervisor.lock().unwrap() {
+            supervisor.spawn(self.clone());
+        }
+
+        if let Some(agc) = *self.inner.agc.lock().unwrap() {
+            agc.spawn(self.clone());
+        }
+    }
+
+    /// Restarts the last stream on the current device handle, reusing its callback and user data.
+    fn restart_stream(&self, mode: StreamMode) -> Result<()> {
+        let context = self
+            .inner
+            .context
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|x| x.pointer());
+        let context = context.ok_or(HackrfError::StreamingStopped)?;
+        self.start_stream(mode, context)
+    }
+
+    fn start_stream(&self, mode: StreamMode, context: *mut c_void) -> Result<()> {
+        if self.simulator().is_some() {
+            let stream = SimStream::spawn(self.downgrade(), mode, context);
+            *self.inner.sim_stream.lock().unwrap() = Some(stream);
+            return Ok(());
+        }
+
         unsafe {
-            HackrfError::from_id(ffi::hackrf_set_baseband_filter_bandwidth(
-                self.device(),
-                ffi::hackrf_compute_baseband_filter_bw(bandwidth_hz),
-            ))
+            HackrfError::from_id(match mode {
+                StreamMode::Receive => ffi::hackrf_start_rx(self.device(), rx_callback, context),
+                StreamMode::Transmit => ffi::hackrf_start_tx(self.device(), tx_callback, context),
+            })
+        }
+    }
+
+    fn stop_stream(&self, mode: StreamMode) -> Result<()> {
+        if self.simulator().is_some() {
+            if let Some(mut stream) = self.inner.sim_stream.lock().unwrap().take() {
+                stream.stop(self.inner.context.lock().unwrap().take());
+            }
+            return Ok(());
+        }
+
+        unsafe {
+            HackrfError::from_id(match mode {
+                StreamMode::Receive => ffi::hackrf_stop_rx(self.device()),
+                StreamMode::Transmit => ffi::hackrf_stop_tx(self.device()),
+            })
         }
     }
 
     /// Starts transmitting samples from the device.
     pub fn start_tx(&self, callback: TransmitCallback, user_data: impl Any) -> Result<()> {
-        let context = TransferContext::new(callback, self.clone(), Box::new(user_data));
-        let callback = Box::leak(Box::new(context)) as *mut _ as *mut _;
-        self.inner.user_data.store(callback, Ordering::Relaxed);
+        let context =
+            self.new_transfer_context(StreamMode::Transmit, callback, Box::new(user_data));
+        self.start_stream(StreamMode::Transmit, context)?;
 
-        unsafe { HackrfError::from_id(ffi::hackrf_start_tx(self.device(), tx_callback, callback)) }
+        self.start_monitors();
+        Ok(())
     }
 
     /// Stops the current transmit operation.
     pub fn stop_tx(&self) -> Result<()> {
-        let user_data = &self.inner.user_data;
-        let callback = user_data.swap(ptr::null_mut(), Ordering::Relaxed);
-        if !callback.is_null() {
-            let callback = unsafe { Box::from_raw(callback as *mut fn(*mut ffi::HackrfTransfer)) };
-            drop(callback);
-        }
-
-        unsafe { HackrfError::from_id(ffi::hackrf_stop_tx(self.device())) }
+        let result = self.stop_stream(StreamMode::Transmit);
+        unsafe { self.inner.free_transfer_context() };
+        result
     }
 
     /// Starts receiving samples from the device.
     pub fn start_rx(&self, callback: ReceiveCallback, user_data: impl Any + Sync) -> Result<()> {
-        let context = TransferContext::new(callback, self.clone(), Box::new(user_data));
-        let callback = Box::leak(Box::new(context)) as *mut _ as *mut _;
-        self.inner.user_data.store(callback, Ordering::Relaxed);
+        let context = self.new_transfer_context(StreamMode::Receive, callback, Box::new(user_data));
+        self.start_stream(StreamMode::Receive, context)?;
 
-        unsafe { HackrfError::from_id(ffi::hackrf_start_rx(self.device(), rx_callback, callback)) }
+        self.start_monitors();
+        Ok(())
     }
 
     /// Stops the current receive operation.
     pub fn stop_rx(&self) -> Result<()> {
-        let user_data = &self.inner.user_data;
-        let callback = user_data.swap(ptr::null_mut(), Ordering::Relaxed);
-        if !callback.is_null() {
-            let callback = unsafe { Box::from_raw(callback as *mut fn(*mut ffi::HackrfTransfer)) };
-            drop(callback);
-        }
+        let result = self.stop_stream(StreamMode::Receive);
+        unsafe { self.inner.free_transfer_context() };
+        result
+    }
+
+    /// Ends the current stream after the transfer being handled, such as when a transmit callback
+    /// runs out of samples. Meant to be called from the stream callback, which cannot stop its
+    /// own stream. The device stops streaming, see [`HackRf::is_streaming`], but the stream must
+    /// still be stopped with [`HackRf::stop_tx`] or [`HackRf::stop_rx`].
+    pub fn end_stream(&self) {
+        self.inner.stream_ended.store(true, Ordering::Relaxed);
+    }
 
-        unsafe { HackrfError::from_id(ffi::hackrf_stop_rx(self.device())) }
+    pub(crate) fn stream_ended(&self) -> bool {
+        self.inner.stream_ended.load(Ordering::Relaxed)
     }
 
     /// Returns true if the device is currently streaming samples (transmitting or receiving).
     pub fn is_streaming(&self) -> bool {
-        unsafe { ffi::hackrf_is_streaming(self.device()) != 0 }
+        self.check_streaming().is_ok()
+    }
+
+    /// Returns the reason the device is not streaming, such as
+    /// [`HackrfError::StreamingStopped`] after the device was unplugged.
+    pub fn check_streaming(&self) -> Result<()> {
+        if self.simulator().is_some() {
+            let stream = self.inner.sim_stream.lock().unwrap();
+            return match stream.as_ref().is_some_and(|x| x.is_running()) {
+                true => Ok(()),
+                false => Err(HackrfError::StreamingStopped),
+            };
+        }
+
+        match unsafe { ffi::hackrf_is_streaming(self.device()) } {
+            1 => Ok(()),
+            0 => Err(HackrfError::StreamingStopped),
+            id => HackrfError::from_id(id),
+        }
+    }
+}
+
+impl WeakHackRf {
+    pub fn upgrade(&self) -> Option<HackRf> {
+        self.0.upgrade().map(|inner| HackRf { inner })
+    }
+}
+
+impl HackRfInner {
+    /// Frees the context of the last stream, must only be called after the stream is stopped.
+    unsafe fn free_transfer_context(&self) {
+        if let Some(stats) = self.stats.lock().unwrap().as_ref() {
+            stats.finish();
+        }
+
+        if let Some(context) = self.context.lock().unwrap().take() {
+            context.free();
+        }
     }
 }
 
 unsafe impl Send for HackRfInner {}
 unsafe impl Sync for HackRfInner {}
 
-impl Drop for HackRf {
+impl Drop for HackRfInner {
     fn drop(&mut self) {
-        let _ = unsafe { HackrfError::from_id(ffi::hackrf_close(self.device())) };
+        // A stream that was never stopped ends with the last handle
+        if let Some(mut stream) = self.sim_stream.get_mut().unwrap().take() {
+            stream.stop(self.context.get_mut().unwrap().take());
+        }
+
+        if self.simulator.is_some() {
+            unsafe { self.free_transfer_context() };
+            return;
+        }
+
+        let device = AtomicPtr::new(*self.device.get_mut());
+        let context = self.context.get_mut().unwrap().take();
+        let stats = self.stats.get_mut().unwrap().take();
+        let close = move || unsafe {
+            let _ = HackrfError::from_id(ffi::hackrf_close(device.into_inner()));
+
+            // Closing the device stops its transfer thread
+            if let Some(stats) = stats {
+                stats.finish();
+            }
+            if let Some(context) = context {
+                context.free();
+            }
+
+            if DEVICE_COUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+                let _ = HackrfError::from_id(ffi::hackrf_exit());
+            }
+        };
+
+        // The last handle can be dropped by a stream callback. Closing the device there would
+        // join the transfer thread from itself and free the context of the running callback, so
+        // it is closed from another thread once the callback has returned.
+        match transfer::in_callback() {
+            true => drop(thread::spawn(close)),
+            false => close(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use crate::{
+        sim::{Pacing, Simulator},
+        Config, HackRf,
+    };
+
+    struct Silence;
+
+    impl Simulator for Silence {
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(Complex::default());
+            true
+        }
+
+        fn pacing(&self) -> Pacing {
+            Pacing::Fast
+        }
+    }
+
+    /// Sets a flag when the user data of a stream is freed.
+    struct Freed(Arc<AtomicBool>);
+
+    impl Drop for Freed {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn dropping_last_handle_stops_stream() {
+        let freed = Arc::new(AtomicBool::new(false));
+        let hackrf = HackRf::simulated(Silence);
+        hackrf
+            .start_rx(|_, _, _, _| {}, Freed(freed.clone()))
+            .unwrap();
+
+        let stats = hackrf.stream_stats().unwrap();
+        while stats.transfers() < 4 {
+            thread::yield_now();
+        }
+        drop(hackrf);
+
+        // The stream thread may hold the last handle for the rest of a transfer
+        let start = Instant::now();
+        while !freed.load(Ordering::Relaxed) {
+            assert!(start.elapsed() < Duration::from_secs(5), "context leaked");
+            thread::yield_now();
+        }
+        assert!(!stats.is_running());
+    }
 
-        if DEVICE_COUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
-            let _ = unsafe { HackrfError::from_id(ffi::hackrf_exit()) };
+    #[test]
+    fn stopping_from_callback_frees_context_after_transfer() {
+        // Whether the user data was still alive after the callback stopped the stream
+        static ALIVE: AtomicBool = AtomicBool::new(false);
+
+        let freed = Arc::new(AtomicBool::new(false));
+        let hackrf = HackRf::simulated(Silence);
+        hackrf
+            .start_rx(
+                |hackrf, _, _, user| {
+                    hackrf.stop_rx().unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                    let freed = user.downcast_ref::<Freed>().unwrap();
+                    ALIVE.store(!freed.0.load(Ordering::Relaxed), Ordering::Relaxed);
+                },
+                Freed(freed.clone()),
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        while !freed.load(Ordering::Relaxed) {
+            assert!(start.elapsed() < Duration::from_secs(5), "context leaked");
+            thread::yield_now();
         }
+        assert!(ALIVE.load(Ordering::Relaxed));
+        assert!(!hackrf.is_streaming());
     }
 }
@@ -0,0 +1,195 @@
+//! Raw interleaved signed 8-bit IQ files, as used by `hackrf_transfer`.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use num_complex::Complex;
+
+use super::{Repeat, RepeatReader, SampleSink, SampleSource, WriterThread, DEFAULT_QUEUE_BLOCKS};
+use crate::{sample, Config};
+
+/// Writes received samples to a `.cs8` file on a writer thread, compatible with
+/// `hackrf_transfer -r`. Blocks are dropped if the disk cannot keep up.
+pub struct Cs8Writer {
+    thread: WriterThread,
+}
+
+/// Plays back a `.cs8` file, compatible with `hackrf_transfer -t`.
+pub struct Cs8Source {
+    reader: Mutex<RepeatReader>,
+}
+
+impl Cs8Writer {
+    /// Creates a new file at the given path.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self::new(file, DEFAULT_QUEUE_BLOCKS))
+    }
+
+    /// Writes to any writer, queueing up to `queue` blocks.
+    pub fn new(mut writer: impl Write + Send + 'static, queue: usize) -> Self {
+        let thread = WriterThread::spawn(queue, move |blocks| {
+            for block in blocks {
+                writer.write_all(sample::as_bytes(&block))?;
+            }
+            writer.flush()
+        });
+
+        Self { thread }
+    }
+
+    /// Number of samples dropped because the writer could not keep up.
+    pub fn dropped_samples(&self) -> u64 {
+        self.thread.dropped_samples()
+    }
+
+    /// Waits for all queued samples to be written and closes the file.
+    pub fn finish(&self) -> io::Result<()> {
+        self.thread.finish()
+    }
+}
+
+impl SampleSink for Cs8Writer {
+    fn write(&self, samples: &[Complex<i8>], _config: &Config) {
+        self.thread.send(samples);
+    }
+}
+
+impl Cs8Source {
+    /// Opens the file at the given path.
+    pub fn open(path: impl AsRef<Path>, repeat: Repeat) -> io::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(Self::new(file, repeat))
+    }
+
+    /// Reads from any seekable reader.
+    pub fn new(reader: impl Read + Seek + Send + 'static, repeat: Repeat) -> Self {
+        Self {
+            reader: Mutex::new(RepeatReader::new(reader, repeat)),
+        }
+    }
+
+    /// Returns true once every repetition of the file has been played.
+    pub fn is_finished(&self) -> bool {
+        self.reader.lock().unwrap().is_finished()
+    }
+}
+
+impl SampleSource for Cs8Source {
+    fn read(&self, samples: &mut [Complex<i8>]) -> bool {
+        let mut reader = self.reader.lock().unwrap();
+        let buffer = sample::as_bytes_mut(samples);
+
+        let filled = reader.read(buffer);
+        buffer[filled..].fill(0);
+        !reader.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{Cs8Source, Cs8Writer};
+    use crate::{
+        recording::{self, temp_path, Repeat, SampleSink, SampleSource},
+        sim::{Pacing, Simulator, TRANSFER_SIZE},
+        Config, HackRf,
+    };
+
+    fn samples(len: usize) -> Vec<Complex<i8>> {
+        (0..len)
+            .map(|n| Complex::new(n as i8, (n / 256) as i8))
+            .collect()
+    }
+
+    /// Keeps every transmitted sample.
+    struct Capture(Arc<Mutex<Vec<Complex<i8>>>>);
+
+    impl Simulator for Capture {
+        fn receive(&self, _samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            false
+        }
+
+        fn transmit(&self, samples: &[Complex<i8>], _config: &Config) -> bool {
+            self.0.lock().unwrap().extend_from_slice(samples);
+            true
+        }
+
+        fn pacing(&self) -> Pacing {
+            Pacing::Fast
+        }
+    }
+
+    #[test]
+    fn writes_and_reads_interleaved_bytes() {
+        let path = temp_path("round-trip.cs8");
+        let writer = Cs8Writer::create(&path).unwrap();
+        let input = samples(1_000);
+        for block in input.chunks(300) {
+            writer.write(block, &Config::default());
+        }
+        writer.finish().unwrap();
+        assert_eq!(writer.dropped_samples(), 0);
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 2_000);
+        assert_eq!(bytes[..6], [0, 0, 1, 0, 2, 0]);
+
+        let source = Cs8Source::open(&path, Repeat::Times(2)).unwrap();
+        let mut output = vec![Complex::new(1, 1); 1_500];
+        assert!(source.read(&mut output));
+        assert_eq!(output[..1_000], input);
+        assert_eq!(output[1_000..], input[..500]);
+
+        assert!(!source.read(&mut output));
+        assert!(source.is_finished());
+        assert_eq!(output[..500], input[500..]);
+        assert!(output[500..].iter().all(|x| *x == Complex::default()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn transmission_ends_with_file() {
+        let path = temp_path("transmit.cs8");
+        let input = samples(200_000);
+        fs::write(&path, crate::sample::as_bytes(&input)).unwrap();
+
+        let transmitted = Arc::new(Mutex::new(Vec::new()));
+        let hackrf = HackRf::simulated(Capture(transmitted.clone()));
+        let source = Arc::new(Cs8Source::open(&path, Repeat::Once).unwrap());
+        hackrf
+            .start_tx(recording::tx_callback::<Cs8Source>, source)
+            .unwrap();
+
+        let start = Instant::now();
+        while hackrf.is_streaming() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "stream did not end"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        hackrf.stop_tx().unwrap();
+
+        // The last transfer holds the end of the file padded with zeros
+        let transmitted = transmitted.lock().unwrap();
+        assert_eq!(transmitted.len(), 2 * TRANSFER_SIZE / 2);
+        assert_eq!(transmitted[..200_000], input);
+        assert!(transmitted[200_000..]
+            .iter()
+            .all(|x| *x == Complex::default()));
+        fs::remove_file(path).unwrap();
+    }
+}
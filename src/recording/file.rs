@@ -0,0 +1,324 @@
+//! Blocking readers and writers of IQ files in any supported format, working with floating
+//! point samples. Meant for offline processing of recordings rather than streaming.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use num_complex::Complex;
+
+use super::{
+    raw::RawFormat,
+    sigmf::{self, Capture, DataType, Global, Metadata},
+    wav::{Header, WavFormat},
+};
+
+/// The container and sample format of an IQ file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Headerless interleaved samples.
+    Raw(RawFormat),
+    /// Two-channel WAV file.
+    Wav(WavFormat),
+    /// SigMF recording.
+    SigMf(DataType),
+}
+
+/// Reads samples from an IQ file.
+pub struct IqReader {
+    format: FileFormat,
+    reader: Box<dyn Read + Send>,
+    sample_rate: Option<f64>,
+    center_freq: Option<u64>,
+    buffer: Vec<u8>,
+}
+
+/// Writes samples to an IQ file. The header or metadata is finalized by [`IqWriter::finish`].
+pub struct IqWriter {
+    format: FileFormat,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    sample_rate: f64,
+    center_freq: Option<u64>,
+    start: SystemTime,
+    data_len: u64,
+    buffer: Vec<u8>,
+}
+
+impl FileFormat {
+    /// Guesses the format of a file from its extension. WAV and SigMF files are given their
+    /// widest sample format, as the actual one is read from the file when opening it.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        Some(match extension.to_ascii_lowercase().as_str() {
+            "wav" => FileFormat::Wav(WavFormat::F32),
+            "sigmf" | "sigmf-meta" | "sigmf-data" => FileFormat::SigMf(DataType::Cf32),
+            extension => FileFormat::Raw(RawFormat::from_extension(extension)?),
+        })
+    }
+
+    /// The encoding of the samples in the file.
+    pub fn raw(&self) -> RawFormat {
+        match self {
+            FileFormat::Raw(format) => *format,
+            FileFormat::Wav(format) => format.raw(),
+            FileFormat::SigMf(datatype) => datatype.raw(),
+        }
+    }
+}
+
+impl IqReader {
+    /// Opens a file, detecting its format from the extension and reading the sample format,
+    /// sample rate and center frequency from its header or metadata if it has one.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        match FileFormat::from_path(&path) {
+            Some(format) => Self::open_as(path, format),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown IQ file extension",
+            )),
+        }
+    }
+
+    /// Opens a file of the given format. The sample formats of WAV and SigMF files are read
+    /// from the file instead.
+    pub fn open_as(path: impl AsRef<Path>, format: FileFormat) -> io::Result<Self> {
+        let path = path.as_ref();
+        match format {
+            FileFormat::Raw(format) => {
+                let file = BufReader::new(File::open(path)?);
+                Ok(Self::new(file, FileFormat::Raw(format), None, None))
+            }
+            FileFormat::Wav(_) => {
+                let mut file = BufReader::new(File::open(path)?);
+                let header = Header::read(&mut file)?;
+                Ok(Self::new(
+                    file.take(header.data_len),
+                    FileFormat::Wav(header.format),
+                    Some(header.sample_rate as f64),
+                    header.center_freq,
+                ))
+            }
+            FileFormat::SigMf(_) => {
+                let metadata = sigmf::read_metadata(path)?;
+                let file = BufReader::new(File::open(sigmf::paths(path).0)?);
+                let center_freq = metadata.captures.first().and_then(|x| x.frequency);
+                Ok(Self::new(
+                    file,
+                    FileFormat::SigMf(metadata.global.datatype),
+                    metadata.global.sample_rate,
+                    center_freq.map(|x| x as u64),
+                ))
+            }
+        }
+    }
+
+    /// Reads samples of the given format from any reader.
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        format: FileFormat,
+        sample_rate: Option<f64>,
+        center_freq: Option<u64>,
+    ) -> Self {
+        Self {
+            format,
+            reader: Box::new(reader),
+            sample_rate,
+            center_freq,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> FileFormat {
+        self.format
+    }
+
+    /// Sample rate in Hz, if the file records it.
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    /// Center frequency in Hz, if the file records it.
+    pub fn center_freq(&self) -> Option<u64> {
+        self.center_freq
+    }
+
+    /// Fills as much of the buffer as possible, returning the number of samples read.
+    /// Less than the full buffer is only returned at the end of the file.
+    pub fn read(&mut self, samples: &mut [Complex<f32>]) -> io::Result<usize> {
+        let raw = self.format.raw();
+        self.buffer.resize(samples.len() * raw.sample_size(), 0);
+
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(raw.decode(&self.buffer[..filled], samples))
+    }
+
+    /// Skips over samples, returning the number skipped.
+    pub fn skip(&mut self, samples: u64) -> io::Result<u64> {
+        let size = self.format.raw().sample_size() as u64;
+        let skipped = io::copy(
+            &mut (&mut self.reader).take(samples * size),
+            &mut io::sink(),
+        )?;
+        Ok(skipped / size)
+    }
+}
+
+impl IqWriter {
+    /// Creates a file of the given format. SigMF recordings are written to the `.sigmf-data`
+    /// and `.sigmf-meta` files next to the path.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: FileFormat,
+        sample_rate: f64,
+        center_freq: Option<u64>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let data_path = match format {
+            FileFormat::SigMf(_) => sigmf::paths(&path).0,
+            _ => path.clone(),
+        };
+
+        let mut writer = Self {
+            format,
+            path,
+            writer: BufWriter::new(File::create(data_path)?),
+            sample_rate,
+            center_freq,
+            start: SystemTime::now(),
+            data_len: 0,
+            buffer: Vec::new(),
+        };
+
+        if let Some(header) = writer.wav_header() {
+            header.write(&mut writer.writer)?;
+        }
+
+        Ok(writer)
+    }
+
+    pub fn format(&self) -> FileFormat {
+        self.format
+    }
+
+    /// Number of samples written so far.
+    pub fn samples(&self) -> u64 {
+        self.data_len / self.format.raw().sample_size() as u64
+    }
+
+    pub fn write(&mut self, samples: &[Complex<f32>]) -> io::Result<()> {
+        self.buffer.clear();
+        self.format.raw().encode(samples, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        self.data_len += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes the samples and finalizes the WAV header or writes the SigMF metadata.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(header) = self.wav_header() {
+            self.writer.seek(SeekFrom::Start(0))?;
+            header.write(&mut self.writer)?;
+        }
+        self.writer.flush()?;
+
+        if let FileFormat::SigMf(datatype) = self.format {
+            let mut metadata = Metadata {
+                global: Global::new(datatype, Some(self.sample_rate)),
+                captures: vec![Capture::new(0, self.center_freq.map(|x| x as f64))],
+                annotations: Vec::new(),
+            };
+            metadata.write(sigmf::paths(&self.path).1)?;
+        }
+
+        Ok(())
+    }
+
+    fn wav_header(&self) -> Option<Header> {
+        let FileFormat::Wav(format) = self.format else {
+            return None;
+        };
+
+        Some(Header {
+            format,
+            sample_rate: self.sample_rate.round() as u32,
+            center_freq: self.center_freq,
+            start: self.start,
+            stop: SystemTime::now(),
+            data_len: self.data_len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use num_complex::Complex;
+
+    use super::{FileFormat, IqReader, IqWriter};
+    use crate::recording::{raw::RawFormat, sigmf, temp_path, wav::WavFormat};
+
+    #[test]
+    fn round_trips_every_format() {
+        let formats = [
+            FileFormat::Raw(RawFormat::Cs8),
+            FileFormat::Raw(RawFormat::Cu8),
+            FileFormat::Raw(RawFormat::Cs16),
+            FileFormat::Raw(RawFormat::Cf32),
+            FileFormat::Wav(WavFormat::U8),
+            FileFormat::Wav(WavFormat::I16),
+            FileFormat::Wav(WavFormat::F32),
+            FileFormat::SigMf(sigmf::DataType::Ci8),
+            FileFormat::SigMf(sigmf::DataType::Cf32),
+        ];
+        // Values every format represents exactly
+        let samples = (0..1_000)
+            .map(|n| Complex::new((n % 255 - 127) as f32 / 127.0, (n / 8) as f32 / 127.0))
+            .collect::<Vec<_>>();
+
+        for format in formats {
+            let path = temp_path(&format!("file-{format:?}"));
+            let mut writer = IqWriter::create(&path, format, 2e6, Some(915_000_000)).unwrap();
+            for block in samples.chunks(300) {
+                writer.write(block).unwrap();
+            }
+            assert_eq!(writer.samples(), 1_000);
+            writer.finish().unwrap();
+
+            let mut reader = IqReader::open_as(&path, format).unwrap();
+            assert_eq!(reader.format(), format);
+            let header = !matches!(format, FileFormat::Raw(_));
+            assert_eq!(reader.sample_rate(), header.then_some(2e6));
+            assert_eq!(reader.center_freq(), header.then_some(915_000_000));
+
+            assert_eq!(reader.skip(100).unwrap(), 100);
+            let mut output = vec![Complex::default(); 1_000];
+            assert_eq!(reader.read(&mut output).unwrap(), 900);
+            for (a, b) in samples[100..].iter().zip(&output) {
+                assert!((a - b).norm() < 1e-6, "{format:?}");
+            }
+
+            match format {
+                FileFormat::SigMf(_) => {
+                    let (data, meta) = sigmf::paths(&path);
+                    fs::remove_file(data).unwrap();
+                    fs::remove_file(meta).unwrap();
+                }
+                _ => fs::remove_file(path).unwrap(),
+            }
+        }
+    }
+}
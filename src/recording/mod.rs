@@ -0,0 +1,263 @@
+use std::{
+    any::Any,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use num_complex::Complex;
+
+use crate::{Config, HackRf, TransferInfo};
+
+pub mod cs8;
+pub mod file;
+pub mod raw;
+pub mod sigmf;
+pub mod trigger;
+pub mod wav;
+
+/// Number of transfers that can be queued for a writer thread before blocks are dropped.
+pub const DEFAULT_QUEUE_BLOCKS: usize = 64;
+
+/// A destination for received samples, such as a file writer.
+pub trait SampleSink: Send + Sync {
+    /// Writes a block of samples received by a device with the given configuration.
+    /// Called from the streaming thread, so must not block.
+    fn write(&self, samples: &[Complex<i8>], config: &Config);
+}
+
+/// A source of samples to transmit, such as a file reader.
+pub trait SampleSource: Send + Sync {
+    /// Fills the buffer with the next samples. Once the source is exhausted the rest of the
+    /// buffer is filled with zeros and false is returned.
+    fn read(&self, samples: &mut [Complex<i8>]) -> bool;
+}
+
+/// How many times a source is played back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Play the source once, like `hackrf_transfer -t`.
+    Once,
+    /// Play the source the given number of times.
+    Times(u32),
+    /// Loop the source until the stream is stopped, like `hackrf_transfer -t -R`.
+    Forever,
+}
+
+/// Receive callback that writes samples to the `Arc<S>` passed as user data.
+/// ```rust
+/// hackrf.start_rx(recording::rx_callback::<Cs8Writer>, writer.clone())?;
+/// ```
+pub fn rx_callback<S: SampleSink + 'static>(
+    hackrf: &HackRf,
+    samples: &[Complex<i8>],
+    _info: &TransferInfo,
+    user: &dyn Any,
+) {
+    let sink = user.downcast_ref::<Arc<S>>().unwrap();
+    sink.write(samples, &hackrf.config());
+}
+
+/// Transmit callback that reads samples from the `Arc<S>` passed as user data. The stream ends
+/// with the last samples of the source, like `hackrf_transfer -t`, so wait for
+/// [`HackRf::is_streaming`] to return false before stopping it.
+/// ```rust
+/// hackrf.start_tx(recording::tx_callback::<Cs8Source>, source.clone())?;
+/// while hackrf.is_streaming() {
+///     thread::sleep(Duration::from_millis(100));
+/// }
+/// hackrf.stop_tx()?;
+/// ```
+pub fn tx_callback<S: SampleSource + 'static>(
+    hackrf: &HackRf,
+    samples: &mut [Complex<i8>],
+    _info: &TransferInfo,
+    user: &dyn Any,
+) {
+    if !user.downcast_ref::<Arc<S>>().unwrap().read(samples) {
+        hackrf.end_stream();
+    }
+}
+
+impl Repeat {
+    /// Returns true if another pass should be played after `plays` passes.
+    pub(crate) fn again(&self, plays: u32) -> bool {
+        match self {
+            Repeat::Once => false,
+            Repeat::Times(times) => plays < *times,
+            Repeat::Forever => true,
+        }
+    }
+}
+
+pub(crate) trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Reads a file from the start again every time it ends, as configured by a [`Repeat`].
+pub(crate) struct RepeatReader {
+    reader: Box<dyn ReadSeek>,
+    repeat: Repeat,
+    plays: u32,
+    position: u64,
+    finished: bool,
+}
+
+impl RepeatReader {
+    pub fn new(reader: impl Read + Seek + Send + 'static, repeat: Repeat) -> Self {
+        Self {
+            reader: Box::new(reader),
+            repeat,
+            plays: 0,
+            position: 0,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Fills as much of the buffer as possible, returning the number of bytes read.
+    /// Less than the full buffer is only returned once every repetition has been read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut filled = 0;
+        while filled < buffer.len() && !self.finished {
+            match self.reader.read(&mut buffer[filled..]) {
+                Ok(0) => {
+                    self.plays += 1;
+                    let empty = self.position == 0;
+                    self.position = 0;
+                    self.finished = empty
+                        || !self.repeat.again(self.plays)
+                        || self.reader.seek(SeekFrom::Start(0)).is_err();
+                }
+                Ok(n) => {
+                    filled += n;
+                    self.position += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => self.finished = true,
+            }
+        }
+
+        filled
+    }
+}
+
+/// Moves blocks of samples to a background thread through a bounded queue, so the
+/// streaming thread never waits on the disk.
+pub(crate) struct WriterThread {
+    sender: Mutex<Option<SyncSender<Vec<Complex<i8>>>>>,
+    thread: Mutex<Option<JoinHandle<io::Result<()>>>>,
+    dropped_samples: AtomicU64,
+}
+
+impl WriterThread {
+    pub fn spawn(
+        queue: usize,
+        write: impl FnOnce(Receiver<Vec<Complex<i8>>>) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let thread = thread::spawn(move || write(receiver));
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            thread: Mutex::new(Some(thread)),
+            dropped_samples: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues a block of samples, dropping it if the queue is full or the writer has stopped.
+    /// Returns true if the block was queued.
+    pub fn send(&self, samples: &[Complex<i8>]) -> bool {
+        let sent = match &*self.sender.lock().unwrap() {
+            Some(sender) => sender.try_send(samples.to_vec()).is_ok(),
+            None => false,
+        };
+
+        if !sent {
+            self.dropped_samples
+                .fetch_add(samples.len() as u64, Ordering::Relaxed);
+        }
+
+        sent
+    }
+
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    /// Waits for all queued blocks to be written. Later blocks are dropped.
+    pub fn finish(&self) -> io::Result<()> {
+        drop(self.sender.lock().unwrap().take());
+        match self.thread.lock().unwrap().take() {
+            Some(thread) => thread.join().unwrap(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A calendar date and time in UTC.
+pub(crate) struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    /// Days since Sunday.
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+impl UtcTime {
+    pub fn from_system(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (days, secs) = (secs / 86_400, secs % 86_400);
+
+        // Converts days since the epoch to a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year,
+            month: month as u32,
+            day: day as u32,
+            // The epoch was a Thursday
+            weekday: ((days + 4) % 7) as u32,
+            hour: (secs / 3600) as u32,
+            minute: (secs / 60 % 60) as u32,
+            second: (secs % 60) as u32,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Formats a time as an ISO 8601 UTC timestamp, like `2024-01-31T12:00:00.000Z`.
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let time = UtcTime::from_system(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
+
+/// A path in the temporary directory that is unique to the test process.
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("libhackrf-{}-{name}", std::process::id()))
+}
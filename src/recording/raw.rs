@@ -0,0 +1,179 @@
+//! Encoding of headerless interleaved IQ sample formats, shared by the file formats that
+//! store samples in them.
+//!
+//! Full scale is that of the HackRF, so an 8-bit sample of 127 decodes to 1.0 in every format.
+
+use num_complex::Complex;
+
+use crate::util;
+
+/// Scale of a full scale 8-bit sample widened to 16 bits.
+const I16_SCALE: f32 = 127.0 * 256.0;
+
+/// Interleaved IQ sample formats, named after their usual file extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// Signed 8-bit, as used by `hackrf_transfer`.
+    Cs8,
+    /// Unsigned 8-bit with an offset of 128, as used by `rtl_sdr`.
+    Cu8,
+    /// Signed little-endian 16-bit.
+    Cs16,
+    /// Little-endian 32-bit float.
+    Cf32,
+}
+
+impl RawFormat {
+    /// Returns the format for a file extension like `cs8`, if it is one.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Some(match extension.to_ascii_lowercase().as_str() {
+            "cs8" | "ci8" | "s8" => RawFormat::Cs8,
+            "cu8" | "u8" => RawFormat::Cu8,
+            "cs16" | "ci16" | "s16" => RawFormat::Cs16,
+            "cf32" | "cfile" | "fc32" => RawFormat::Cf32,
+            _ => return None,
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RawFormat::Cs8 => "cs8",
+            RawFormat::Cu8 => "cu8",
+            RawFormat::Cs16 => "cs16",
+            RawFormat::Cf32 => "cf32",
+        }
+    }
+
+    /// Size of one IQ sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            RawFormat::Cs8 | RawFormat::Cu8 => 2,
+            RawFormat::Cs16 => 4,
+            RawFormat::Cf32 => 8,
+        }
+    }
+
+    /// Decodes whole samples from the bytes into `output`, returning the number decoded.
+    /// Stops at whichever of the bytes or the output runs out first.
+    pub fn decode(&self, bytes: &[u8], output: &mut [Complex<f32>]) -> usize {
+        let count = output.len().min(bytes.len() / self.sample_size());
+        let (bytes, output) = (&bytes[..count * self.sample_size()], &mut output[..count]);
+
+        match self {
+            RawFormat::Cs8 => util::i8_to_f32(crate::sample::from_bytes(bytes), output),
+            RawFormat::Cu8 => decode_parts(bytes, output, 1, |x| (x[0] as f32 - 128.0) / 127.0),
+            RawFormat::Cs16 => decode_parts(bytes, output, 2, |x| {
+                i16::from_le_bytes([x[0], x[1]]) as f32 / I16_SCALE
+            }),
+            RawFormat::Cf32 => decode_parts(bytes, output, 4, |x| {
+                f32::from_le_bytes(x.try_into().unwrap())
+            }),
+        }
+
+        count
+    }
+
+    /// Encodes the samples, appending them to `output`.
+    /// Values outside of [-1, 1] are saturated in the integer formats.
+    pub fn encode(&self, samples: &[Complex<f32>], output: &mut Vec<u8>) {
+        output.reserve(samples.len() * self.sample_size());
+
+        match self {
+            RawFormat::Cs8 | RawFormat::Cu8 => {
+                let mut quantized = vec![Complex::default(); samples.len()];
+                util::f32_to_i8(samples, &mut quantized);
+
+                let bytes = crate::sample::as_bytes(&quantized);
+                match self {
+                    RawFormat::Cu8 => output.extend(bytes.iter().map(|x| x ^ 0x80)),
+                    _ => output.extend_from_slice(bytes),
+                }
+            }
+            RawFormat::Cs16 => {
+                for part in flatten(samples) {
+                    let value = (part * I16_SCALE)
+                        .round()
+                        .clamp(i16::MIN as f32, i16::MAX as f32);
+                    output.extend_from_slice(&(value as i16).to_le_bytes());
+                }
+            }
+            RawFormat::Cf32 => {
+                for part in flatten(samples) {
+                    output.extend_from_slice(&part.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn decode_parts(
+    bytes: &[u8],
+    output: &mut [Complex<f32>],
+    part_size: usize,
+    decode: impl Fn(&[u8]) -> f32,
+) {
+    for (sample, bytes) in output.iter_mut().zip(bytes.chunks_exact(part_size * 2)) {
+        let (re, im) = bytes.split_at(part_size);
+        *sample = Complex::new(decode(re), decode(im));
+    }
+}
+
+fn flatten(samples: &[Complex<f32>]) -> impl Iterator<Item = f32> + '_ {
+    samples.iter().flat_map(|x| [x.re, x.im])
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::{RawFormat, I16_SCALE};
+
+    const FORMATS: [RawFormat; 4] = [
+        RawFormat::Cs8,
+        RawFormat::Cu8,
+        RawFormat::Cs16,
+        RawFormat::Cf32,
+    ];
+
+    #[test]
+    fn round_trips_every_format() {
+        let samples = (-127..=127)
+            .map(|n| Complex::new(n as f32 / 127.0, (n as f32 / 127.0 * 3.0).sin()))
+            .collect::<Vec<_>>();
+
+        for format in FORMATS {
+            let mut bytes = Vec::new();
+            format.encode(&samples, &mut bytes);
+            assert_eq!(bytes.len(), samples.len() * format.sample_size());
+
+            // Trailing bytes of an incomplete sample are ignored
+            bytes.push(0);
+            let mut decoded = vec![Complex::default(); samples.len() + 1];
+            assert_eq!(format.decode(&bytes, &mut decoded), samples.len());
+
+            let tolerance = match format {
+                RawFormat::Cs8 | RawFormat::Cu8 => 0.5 / 127.0,
+                RawFormat::Cs16 => 0.5 / I16_SCALE,
+                RawFormat::Cf32 => 0.0,
+            };
+            for (a, b) in samples.iter().zip(&decoded) {
+                assert!((a.re - b.re).abs() <= tolerance + 1e-7, "{format:?}");
+                assert!((a.im - b.im).abs() <= tolerance + 1e-7, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_full_scale_like_hackrf() {
+        let samples = [Complex::new(1.0, -1.0), Complex::new(2.0, 0.0)];
+        let encoded = FORMATS.map(|format| {
+            let mut bytes = Vec::new();
+            format.encode(&samples, &mut bytes);
+            bytes
+        });
+
+        assert_eq!(encoded[0], [127, 129, 127, 0]);
+        assert_eq!(encoded[1], [255, 1, 255, 128]);
+        assert_eq!(encoded[2], [0, 127, 0, 129, 255, 127, 0, 0]);
+    }
+}
@@ -0,0 +1,536 @@
+//! [SigMF](https://sigmf.org) recordings, a `.sigmf-data` file of samples described by a
+//! `.sigmf-meta` JSON file.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    iso8601, raw::RawFormat, Repeat, RepeatReader, SampleSink, SampleSource, WriterThread,
+    DEFAULT_QUEUE_BLOCKS,
+};
+use crate::{sample, util, Config, HackRf};
+
+pub const SIGMF_VERSION: &str = "1.0.0";
+
+/// Name of the extension namespace of the `hackrf:*` keys.
+pub const HACKRF_EXTENSION: &str = "hackrf";
+
+/// Sample formats supported in the data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataType {
+    /// Interleaved signed 8-bit IQ, the native format of the HackRF.
+    #[serde(rename = "ci8")]
+    Ci8,
+    /// Interleaved unsigned 8-bit IQ, as produced by RTL-SDR dongles.
+    #[serde(rename = "cu8")]
+    Cu8,
+    /// Interleaved little-endian signed 16-bit IQ.
+    #[serde(rename = "ci16_le")]
+    Ci16,
+    /// Interleaved little-endian 32-bit float IQ.
+    #[serde(rename = "cf32_le")]
+    Cf32,
+}
+
+/// The contents of a `.sigmf-meta` file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub global: Global,
+    #[serde(default)]
+    pub captures: Vec<Capture>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Global {
+    #[serde(rename = "core:datatype")]
+    pub datatype: DataType,
+    #[serde(rename = "core:sample_rate", skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    #[serde(rename = "core:version")]
+    pub version: String,
+    #[serde(rename = "core:hw", skip_serializing_if = "Option::is_none")]
+    pub hw: Option<String>,
+    #[serde(rename = "core:recorder", skip_serializing_if = "Option::is_none")]
+    pub recorder: Option<String>,
+    #[serde(rename = "core:description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(
+        rename = "core:extensions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub extensions: Vec<Extension>,
+    #[serde(rename = "hackrf:serial", skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(
+        rename = "hackrf:firmware_version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub firmware_version: Option<String>,
+}
+
+/// Declares an extension namespace used by keys in the metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Extension {
+    pub name: String,
+    pub version: String,
+    /// Whether readers may ignore the keys of the extension.
+    pub optional: bool,
+}
+
+/// A segment of the recording captured with the same device settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:frequency", skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    #[serde(rename = "core:datetime", skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+    #[serde(rename = "hackrf:amp_enable", skip_serializing_if = "Option::is_none")]
+    pub amp_enable: Option<bool>,
+    #[serde(rename = "hackrf:lna_gain", skip_serializing_if = "Option::is_none")]
+    pub lna_gain: Option<u32>,
+    #[serde(rename = "hackrf:vga_gain", skip_serializing_if = "Option::is_none")]
+    pub vga_gain: Option<u32>,
+    #[serde(rename = "hackrf:txvga_gain", skip_serializing_if = "Option::is_none")]
+    pub txvga_gain: Option<u32>,
+}
+
+/// Describes a range of samples in the recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:sample_count", skip_serializing_if = "Option::is_none")]
+    pub sample_count: Option<u64>,
+    #[serde(rename = "core:label", skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(rename = "core:comment", skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Writes received samples to a SigMF recording on a writer thread.
+/// A new capture segment is started whenever the center frequency of the device changes.
+pub struct SigMfWriter {
+    thread: WriterThread,
+    meta_path: PathBuf,
+    state: Mutex<WriterState>,
+}
+
+struct WriterState {
+    metadata: Metadata,
+    samples: u64,
+}
+
+/// Plays back a SigMF recording.
+pub struct SigMfSource {
+    metadata: Metadata,
+    reader: Mutex<RepeatReader>,
+}
+
+/// Returns the paths of the data and metadata files of a recording.
+/// A `.sigmf`, `.sigmf-data` or `.sigmf-meta` extension on the given path is replaced, and
+/// anything else is kept as part of the name, so `capture.2m4` becomes `capture.2m4.sigmf-data`.
+pub fn paths(path: impl AsRef<Path>) -> (PathBuf, PathBuf) {
+    let path = path.as_ref();
+    let base = match path.extension().and_then(|x| x.to_str()) {
+        Some("sigmf" | "sigmf-data" | "sigmf-meta") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    let with_suffix = |suffix: &str| {
+        let mut path = base.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    (with_suffix(".sigmf-data"), with_suffix(".sigmf-meta"))
+}
+
+/// Reads the `.sigmf-meta` file of a recording.
+pub fn read_metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let file = BufReader::new(File::open(paths(path).1)?);
+    Ok(serde_json::from_reader(file)?)
+}
+
+impl DataType {
+    /// Size of one sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        self.raw().sample_size()
+    }
+
+    /// The encoding of the samples in the data file.
+    pub fn raw(&self) -> RawFormat {
+        match self {
+            DataType::Ci8 => RawFormat::Cs8,
+            DataType::Cu8 => RawFormat::Cu8,
+            DataType::Ci16 => RawFormat::Cs16,
+            DataType::Cf32 => RawFormat::Cf32,
+        }
+    }
+}
+
+impl Metadata {
+    /// Declares the `hackrf` extension in `core:extensions` if any `hackrf:*` key is set.
+    pub fn declare_extensions(&mut self) {
+        let global = &self.global;
+        let used = global.serial.is_some()
+            || global.firmware_version.is_some()
+            || self.captures.iter().any(|x| {
+                x.amp_enable.is_some()
+                    || x.lna_gain.is_some()
+                    || x.vga_gain.is_some()
+                    || x.txvga_gain.is_some()
+            });
+
+        let extensions = &mut self.global.extensions;
+        if used && !extensions.iter().any(|x| x.name == HACKRF_EXTENSION) {
+            extensions.push(Extension::hackrf());
+        }
+    }
+
+    /// Declares the extensions in use and writes the metadata to a `.sigmf-meta` file.
+    pub fn write(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.declare_extensions();
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.flush()
+    }
+}
+
+impl Extension {
+    /// The extension of the `hackrf:*` keys, which readers may ignore.
+    pub fn hackrf() -> Self {
+        Self {
+            name: HACKRF_EXTENSION.into(),
+            version: "1.0.0".into(),
+            optional: true,
+        }
+    }
+}
+
+impl Global {
+    /// Creates the global metadata with only the core keys, recorded by this library.
+    pub fn new(datatype: DataType, sample_rate: Option<f64>) -> Self {
+        Self {
+            datatype,
+            sample_rate,
+            version: SIGMF_VERSION.into(),
+            hw: None,
+            recorder: Some(concat!("libhackrf-rs ", env!("CARGO_PKG_VERSION")).into()),
+            description: None,
+            extensions: Vec::new(),
+            serial: None,
+            firmware_version: None,
+        }
+    }
+
+    /// Creates the global metadata for a recording of the given device.
+    pub fn from_device(hackrf: &HackRf, datatype: DataType) -> Self {
+        let board = hackrf.get_device_type().ok().map(|x| x.name());
+        let serial = hackrf.get_serial_number().ok().map(|x| x.to_string());
+        let hw = match (board, &serial) {
+            (Some(board), Some(serial)) => Some(format!("{board} {serial}")),
+            (board, _) => board.map(String::from),
+        };
+
+        Self {
+            hw,
+            extensions: vec![Extension::hackrf()],
+            serial,
+            firmware_version: Some(hackrf.version()).filter(|x| !x.is_empty()),
+            ..Self::new(datatype, hackrf.config().sample_rate.map(|x| x as f64))
+        }
+    }
+}
+
+impl Capture {
+    /// Creates a capture segment with only the core keys.
+    pub fn new(sample_start: u64, frequency: Option<f64>) -> Self {
+        Self {
+            sample_start,
+            frequency,
+            datetime: None,
+            amp_enable: None,
+            lna_gain: None,
+            vga_gain: None,
+            txvga_gain: None,
+        }
+    }
+
+    /// Creates a capture segment starting now with the given device configuration.
+    pub fn from_config(sample_start: u64, config: &Config) -> Self {
+        Self {
+            datetime: Some(iso8601(SystemTime::now())),
+            amp_enable: config.amp_enable,
+            lna_gain: config.lna_gain,
+            vga_gain: config.rxvga_gain,
+            txvga_gain: config.txvga_gain,
+            ..Self::new(sample_start, config.freq.map(|x| x as f64))
+        }
+    }
+}
+
+impl SigMfWriter {
+    /// Creates a new recording at the given path, with metadata populated from the device.
+    pub fn create(path: impl AsRef<Path>, hackrf: &HackRf, datatype: DataType) -> io::Result<Self> {
+        let global = Global::from_device(hackrf, datatype);
+        Self::new(path, global, &hackrf.config())
+    }
+
+    /// Creates a new recording at the given path with the given global metadata, starting
+    /// with a capture segment for the device configuration.
+    pub fn new(path: impl AsRef<Path>, global: Global, config: &Config) -> io::Result<Self> {
+        Self::with_queue(path, global, config, DEFAULT_QUEUE_BLOCKS)
+    }
+
+    /// Like [`SigMfWriter::new`], queueing up to `queue` blocks.
+    pub fn with_queue(
+        path: impl AsRef<Path>,
+        global: Global,
+        config: &Config,
+        queue: usize,
+    ) -> io::Result<Self> {
+        let (data_path, meta_path) = paths(path);
+        let mut writer = BufWriter::new(File::create(data_path)?);
+        let datatype = global.datatype;
+
+        let thread = WriterThread::spawn(queue, move |blocks| {
+            let (mut samples, mut buffer) = (Vec::new(), Vec::new());
+            for block in blocks {
+                match datatype {
+                    DataType::Ci8 => writer.write_all(sample::as_bytes(&block))?,
+                    _ => {
+                        samples.resize(block.len(), Complex::default());
+                        util::i8_to_f32(&block, &mut samples);
+
+                        buffer.clear();
+                        datatype.raw().encode(&samples, &mut buffer);
+                        writer.write_all(&buffer)?;
+                    }
+                }
+            }
+            writer.flush()
+        });
+
+        let metadata = Metadata {
+            global,
+            captures: vec![Capture::from_config(0, config)],
+            annotations: Vec::new(),
+        };
+
+        Ok(Self {
+            thread,
+            meta_path,
+            state: Mutex::new(WriterState {
+                metadata,
+                samples: 0,
+            }),
+        })
+    }
+
+    /// Number of samples written to the recording so far.
+    pub fn samples(&self) -> u64 {
+        self.state.lock().unwrap().samples
+    }
+
+    /// Number of samples dropped because the writer could not keep up.
+    pub fn dropped_samples(&self) -> u64 {
+        self.thread.dropped_samples()
+    }
+
+    /// Sets the description of the recording.
+    pub fn set_description(&self, description: impl Into<String>) {
+        self.state.lock().unwrap().metadata.global.description = Some(description.into());
+    }
+
+    /// Sets the time the first sample of the recording was captured.
+    pub fn set_datetime(&self, time: SystemTime) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(capture) = state.metadata.captures.first_mut() {
+            capture.datetime = Some(iso8601(time));
+        }
+    }
+
+    /// Adds an annotation to the recording.
+    pub fn annotate(&self, annotation: Annotation) {
+        let mut state = self.state.lock().unwrap();
+        state.metadata.annotations.push(annotation);
+    }
+
+    /// Waits for all queued samples to be written and writes the metadata file.
+    pub fn finish(&self) -> io::Result<()> {
+        self.thread.finish()?;
+
+        let mut state = self.state.lock().unwrap();
+        state.metadata.write(&self.meta_path)
+    }
+}
+
+impl SampleSink for SigMfWriter {
+    fn write(&self, samples: &[Complex<i8>], config: &Config) {
+        let mut state = self.state.lock().unwrap();
+        let position = state.samples;
+
+        let freq = config.freq.map(|x| x as f64);
+        let captures = &mut state.metadata.captures;
+        if captures.last().map(|x| x.frequency) != Some(freq) {
+            let capture = Capture::from_config(position, config);
+            match captures.last_mut() {
+                // Replace segments that never received any samples
+                Some(last) if last.sample_start == position => *last = capture,
+                _ => captures.push(capture),
+            }
+        }
+
+        if self.thread.send(samples) {
+            state.samples += samples.len() as u64;
+        }
+    }
+}
+
+impl SigMfSource {
+    /// Opens the recording at the given path.
+    pub fn open(path: impl AsRef<Path>, repeat: Repeat) -> io::Result<Self> {
+        let metadata = read_metadata(&path)?;
+        let file = BufReader::new(File::open(paths(&path).0)?);
+
+        Ok(Self {
+            metadata,
+            reader: Mutex::new(RepeatReader::new(file, repeat)),
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Sample rate of the recording in Hz.
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.metadata.global.sample_rate
+    }
+
+    /// Returns true once every repetition of the recording has been played.
+    pub fn is_finished(&self) -> bool {
+        self.reader.lock().unwrap().is_finished()
+    }
+}
+
+impl SampleSource for SigMfSource {
+    fn read(&self, samples: &mut [Complex<i8>]) -> bool {
+        let mut reader = self.reader.lock().unwrap();
+
+        match self.metadata.global.datatype {
+            DataType::Ci8 => {
+                let buffer = sample::as_bytes_mut(samples);
+                let filled = reader.read(buffer);
+                buffer[filled..].fill(0);
+            }
+            datatype => {
+                let raw = datatype.raw();
+                let mut buffer = vec![0; samples.len() * raw.sample_size()];
+                let filled = reader.read(&mut buffer) / raw.sample_size();
+
+                let mut decoded = vec![Complex::default(); filled];
+                raw.decode(&buffer, &mut decoded);
+                util::f32_to_i8(&decoded, &mut samples[..filled]);
+                samples[filled..].fill(Complex::default());
+            }
+        }
+
+        !reader.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use num_complex::Complex;
+
+    use super::{paths, read_metadata, Annotation, DataType, Global, SigMfSource, SigMfWriter};
+    use crate::{
+        recording::{temp_path, Repeat, SampleSink, SampleSource},
+        Config,
+    };
+
+    fn global(datatype: DataType) -> Global {
+        Global {
+            datatype,
+            sample_rate: Some(2e6),
+            version: super::SIGMF_VERSION.into(),
+            hw: None,
+            recorder: None,
+            description: None,
+            extensions: Vec::new(),
+            serial: Some("0000000000000000a06063c8234e925f".into()),
+            firmware_version: None,
+        }
+    }
+
+    #[test]
+    fn keeps_dotted_names() {
+        let expected = (
+            PathBuf::from("capture.2m4.sigmf-data"),
+            PathBuf::from("capture.2m4.sigmf-meta"),
+        );
+        assert_eq!(paths("capture.2m4"), expected);
+        assert_eq!(paths("capture.2m4.sigmf-meta"), expected);
+        assert_eq!(paths("capture.2m4.sigmf"), expected);
+    }
+
+    #[test]
+    fn round_trips_metadata_and_samples() {
+        for datatype in [DataType::Ci8, DataType::Cf32] {
+            let path = temp_path(&format!("round-trip.{datatype:?}"));
+            let mut config = Config {
+                freq: Some(100_000_000),
+                lna_gain: Some(16),
+                ..Default::default()
+            };
+            let writer = SigMfWriter::new(&path, global(datatype), &config).unwrap();
+            writer.set_description("round trip");
+
+            let input = (0..2_000)
+                .map(|n| Complex::new((n % 255 - 127) as i8, (n / 16) as i8))
+                .collect::<Vec<_>>();
+            writer.write(&input[..1_000], &config);
+            config.freq = Some(101_000_000);
+            writer.write(&input[1_000..], &config);
+            writer.annotate(Annotation {
+                sample_start: 500,
+                sample_count: Some(100),
+                label: Some("burst".into()),
+                comment: None,
+            });
+            writer.finish().unwrap();
+
+            let metadata = read_metadata(&path).unwrap();
+            assert_eq!(metadata, writer.state.lock().unwrap().metadata);
+            assert_eq!(metadata.global.extensions[0].name, "hackrf");
+            assert_eq!(metadata.captures.len(), 2);
+            assert_eq!(metadata.captures[1].sample_start, 1_000);
+            assert_eq!(metadata.captures[1].frequency, Some(101e6));
+
+            let source = SigMfSource::open(&path, Repeat::Once).unwrap();
+            assert_eq!(source.metadata(), &metadata);
+            let mut output = vec![Complex::new(1, 1); 2_100];
+            assert!(!source.read(&mut output));
+            assert_eq!(output[..2_000], input);
+            assert!(output[2_000..].iter().all(|x| *x == Complex::default()));
+
+            let (data, meta) = paths(&path);
+            fs::remove_file(data).unwrap();
+            fs::remove_file(meta).unwrap();
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Records the samples around a trigger, keeping a fixed duration of samples from before the
+//! trigger in memory while waiting for it.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use num_complex::Complex;
+
+use super::{
+    cs8::Cs8Writer,
+    iso8601,
+    sigmf::{Annotation, Global, SigMfWriter},
+    SampleSink, DEFAULT_QUEUE_BLOCKS,
+};
+use crate::{sim::TRANSFER_SIZE, Config};
+
+/// Pre-trigger samples are queued for the writer in blocks of one transfer.
+const CHUNK_SAMPLES: usize = TRANSFER_SIZE / 2;
+
+/// Condition that starts a recording. Triggers are checked once per block of samples and the
+/// recording is split at the start of the block that fired.
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    /// Fires when the mean power of a block exceeds the threshold, in dB relative to full scale.
+    Power(f32),
+    /// Only fires when [`TriggerRecorder::trigger`] is called.
+    Manual,
+    /// Fires when the predicate returns true for a block.
+    Predicate(fn(samples: &[Complex<i8>]) -> bool),
+}
+
+/// Keeps the last `pre` duration of received samples in a ring buffer and, once the trigger
+/// fires, writes them followed by `post` duration of samples to a file.
+/// Manual triggers are always honored in addition to the configured trigger.
+///
+/// The durations are converted to sample counts with the sample rate given when the recorder is
+/// created. [`TriggerRecorder::rearm`] waits for the trigger again and appends the next recording
+/// to the same file.
+pub struct TriggerRecorder {
+    output: Output,
+    trigger: Trigger,
+    sample_rate: u32,
+    pre_samples: usize,
+    post_samples: u64,
+    manual: AtomicBool,
+    inner: Mutex<RecorderInner>,
+}
+
+enum Output {
+    Cs8(Cs8Writer),
+    SigMf(Box<SigMfWriter>),
+}
+
+struct RecorderInner {
+    state: State,
+    ring: VecDeque<Complex<i8>>,
+    /// Number of samples passed to the output so far.
+    written: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Armed,
+    Recording { remaining: u64 },
+    Done,
+}
+
+impl TriggerRecorder {
+    /// Records to a `.cs8` file of samples received at `sample_rate`.
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the sample rate is zero.
+    pub fn cs8(
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        trigger: Trigger,
+        pre: Duration,
+        post: Duration,
+    ) -> io::Result<Self> {
+        let (pre, post) = samples(sample_rate, pre, post)?;
+        let file = BufWriter::new(File::create(path)?);
+        let output = Output::Cs8(Cs8Writer::new(file, queue_blocks(pre)));
+        Ok(Self::new(output, sample_rate, trigger, pre, post))
+    }
+
+    /// Records to a SigMF recording at the sample rate of `config`, annotating the trigger with
+    /// its timestamp.
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the sample rate is not set.
+    pub fn sigmf(
+        path: impl AsRef<Path>,
+        global: Global,
+        config: &Config,
+        trigger: Trigger,
+        pre: Duration,
+        post: Duration,
+    ) -> io::Result<Self> {
+        let sample_rate = config.sample_rate.unwrap_or_default();
+        let (pre, post) = samples(sample_rate, pre, post)?;
+        let writer = SigMfWriter::with_queue(path, global, config, queue_blocks(pre))?;
+        let output = Output::SigMf(Box::new(writer));
+        Ok(Self::new(output, sample_rate, trigger, pre, post))
+    }
+
+    fn new(
+        output: Output,
+        sample_rate: u32,
+        trigger: Trigger,
+        pre_samples: usize,
+        post_samples: u64,
+    ) -> Self {
+        Self {
+            output,
+            trigger,
+            sample_rate,
+            pre_samples,
+            post_samples,
+            manual: AtomicBool::new(false),
+            inner: Mutex::new(RecorderInner {
+                state: State::Armed,
+                // Room for a full ring and the block that overfills it
+                ring: VecDeque::with_capacity(pre_samples + CHUNK_SAMPLES),
+                written: 0,
+            }),
+        }
+    }
+
+    /// Fires the trigger at the start of the next block of samples.
+    pub fn trigger(&self) {
+        self.manual.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once the trigger has fired.
+    pub fn is_triggered(&self) -> bool {
+        self.inner.lock().unwrap().state != State::Armed
+    }
+
+    /// Returns true once all post-trigger samples have been queued for writing.
+    pub fn is_done(&self) -> bool {
+        self.inner.lock().unwrap().state == State::Done
+    }
+
+    /// Waits for the trigger again, appending the next recording to the file. A recording that
+    /// is still in progress ends early.
+    pub fn rearm(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = State::Armed;
+        inner.ring.clear();
+        self.manual.store(false, Ordering::Relaxed);
+    }
+
+    /// Waits for all queued samples to be written and closes the file.
+    pub fn finish(&self) -> io::Result<()> {
+        match &self.output {
+            Output::Cs8(writer) => writer.finish(),
+            Output::SigMf(writer) => writer.finish(),
+        }
+    }
+
+    fn fired(&self, samples: &[Complex<i8>]) -> bool {
+        if self.manual.swap(false, Ordering::Relaxed) {
+            return true;
+        }
+
+        match self.trigger {
+            Trigger::Power(threshold) => power_dbfs(samples) > threshold,
+            Trigger::Manual => false,
+            Trigger::Predicate(predicate) => predicate(samples),
+        }
+    }
+
+    fn output(&self) -> &dyn SampleSink {
+        match &self.output {
+            Output::Cs8(writer) => writer,
+            Output::SigMf(writer) => writer.as_ref(),
+        }
+    }
+}
+
+impl SampleSink for TriggerRecorder {
+    fn write(&self, samples: &[Complex<i8>], config: &Config) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.state == State::Armed {
+            if !self.fired(samples) {
+                inner.ring.extend(samples);
+                let excess = inner.ring.len().saturating_sub(self.pre_samples);
+                inner.ring.drain(..excess);
+                return;
+            }
+
+            let time = SystemTime::now();
+            let pre = inner.ring.len();
+            let (front, back) = inner.ring.as_slices();
+            for chunk in front
+                .chunks(CHUNK_SAMPLES)
+                .chain(back.chunks(CHUNK_SAMPLES))
+            {
+                self.output().write(chunk, config);
+            }
+            inner.ring.clear();
+
+            if let Output::SigMf(writer) = &self.output {
+                if inner.written == 0 {
+                    let pre_duration =
+                        Duration::from_secs_f64(pre as f64 / self.sample_rate as f64);
+                    writer.set_datetime(time - pre_duration);
+                }
+                writer.annotate(Annotation {
+                    sample_start: inner.written + pre as u64,
+                    sample_count: Some(self.post_samples),
+                    label: Some("trigger".into()),
+                    comment: Some(iso8601(time)),
+                });
+            }
+
+            inner.written += pre as u64;
+            inner.state = State::Recording {
+                remaining: self.post_samples,
+            };
+        }
+
+        if let State::Recording { remaining } = inner.state {
+            let count = (remaining as usize).min(samples.len());
+            self.output().write(&samples[..count], config);
+            inner.written += count as u64;
+
+            let remaining = remaining - count as u64;
+            inner.state = match remaining {
+                0 => State::Done,
+                remaining => State::Recording { remaining },
+            };
+        }
+    }
+}
+
+/// Converts the pre- and post-trigger durations to sample counts.
+fn samples(sample_rate: u32, pre: Duration, post: Duration) -> io::Result<(usize, u64)> {
+    if sample_rate == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "trigger recordings need the sample rate",
+        ));
+    }
+
+    let sample_rate = sample_rate as f64;
+    let pre = (pre.as_secs_f64() * sample_rate).round() as usize;
+    let post = (post.as_secs_f64() * sample_rate).round() as u64;
+    Ok((pre, post))
+}
+
+/// Size of the writer queue needed to take the pre-trigger samples at once.
+fn queue_blocks(pre_samples: usize) -> usize {
+    pre_samples.div_ceil(CHUNK_SAMPLES) + DEFAULT_QUEUE_BLOCKS
+}
+
+/// Mean power of the samples in dB relative to a full scale sine wave.
+pub fn power_dbfs(samples: &[Complex<i8>]) -> f32 {
+    let sum = samples
+        .iter()
+        .map(|x| x.re as i64 * x.re as i64 + x.im as i64 * x.im as i64)
+        .sum::<i64>() as f32;
+    let mean = sum / samples.len().max(1) as f32 / (127.0 * 127.0);
+    10.0 * mean.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, time::Duration};
+
+    use num_complex::Complex;
+
+    use super::{Trigger, TriggerRecorder};
+    use crate::{
+        recording::{
+            sigmf::{self, DataType, Global},
+            temp_path, SampleSink,
+        },
+        sample, Config,
+    };
+
+    fn config() -> Config {
+        Config {
+            sample_rate: Some(1_000),
+            ..Default::default()
+        }
+    }
+
+    /// Samples that encode their index.
+    fn samples(range: std::ops::Range<usize>) -> Vec<Complex<i8>> {
+        range
+            .map(|n| Complex::new((n % 128) as i8, (n / 128) as i8))
+            .collect()
+    }
+
+    /// Writes blocks of 50 samples from `range`, triggering at `trigger`.
+    fn feed(recorder: &TriggerRecorder, range: std::ops::Range<usize>, trigger: usize) {
+        for start in range.step_by(50) {
+            if start == trigger {
+                recorder.trigger();
+            }
+            recorder.write(&samples(start..start + 50), &config());
+        }
+    }
+
+    #[test]
+    fn records_around_trigger() {
+        let path = temp_path("trigger.cs8");
+        let pre = Duration::from_millis(100);
+        let post = Duration::from_millis(200);
+        let recorder = TriggerRecorder::cs8(&path, 1_000, Trigger::Manual, pre, post).unwrap();
+
+        feed(&recorder, 0..300, 300);
+        assert!(!recorder.is_triggered());
+        feed(&recorder, 300..450, 300);
+        assert!(recorder.is_triggered() && !recorder.is_done());
+        feed(&recorder, 450..700, 300);
+        assert!(recorder.is_done());
+        recorder.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes, sample::as_bytes(&samples(200..500)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rearms_and_appends() {
+        let path = temp_path("trigger-rearm");
+        let global = Global {
+            datatype: DataType::Ci8,
+            sample_rate: Some(1_000.0),
+            version: sigmf::SIGMF_VERSION.into(),
+            hw: None,
+            recorder: None,
+            description: None,
+            extensions: Vec::new(),
+            serial: None,
+            firmware_version: None,
+        };
+        let pre = Duration::from_millis(100);
+        let post = Duration::from_millis(50);
+        let recorder =
+            TriggerRecorder::sigmf(&path, global, &config(), Trigger::Manual, pre, post).unwrap();
+
+        feed(&recorder, 0..400, 200);
+        assert!(recorder.is_done());
+        recorder.rearm();
+        assert!(!recorder.is_triggered());
+        // Only samples since re-arming are kept before the second trigger
+        feed(&recorder, 400..600, 450);
+        assert!(recorder.is_done());
+        recorder.finish().unwrap();
+
+        let (data, meta) = sigmf::paths(&path);
+        let expected = [samples(100..250), samples(400..500)].concat();
+        assert_eq!(fs::read(&data).unwrap(), sample::as_bytes(&expected));
+
+        let annotations = sigmf::read_metadata(&path).unwrap().annotations;
+        let starts = annotations
+            .iter()
+            .map(|x| x.sample_start)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [100, 200]);
+        assert!(annotations.iter().all(|x| x.sample_count == Some(50)));
+        fs::remove_file(data).unwrap();
+        fs::remove_file(meta).unwrap();
+    }
+
+    #[test]
+    fn requires_sample_rate() {
+        let path = temp_path("trigger-no-rate.cs8");
+        let duration = Duration::from_secs(1);
+        let result = TriggerRecorder::cs8(&path, 0, Trigger::Manual, duration, duration);
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+}
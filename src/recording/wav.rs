@@ -0,0 +1,528 @@
+//! Two-channel WAV files holding I in the left and Q in the right channel, as used by SDR#,
+//! SDR++ and baudline. The center frequency is stored in an SDR# compatible `auxi` chunk.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use num_complex::Complex;
+
+use super::{
+    raw::RawFormat, Repeat, RepeatReader, SampleSink, SampleSource, UtcTime, WriterThread,
+    DEFAULT_QUEUE_BLOCKS,
+};
+use crate::{
+    dsp::{AlignedResampler, Resampler},
+    util, Config, HackRf,
+};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const AUXI_LENGTH: u32 = 72;
+const HEADER_LENGTH: u64 = 12 + 8 + 16 + 8 + AUXI_LENGTH as u64 + 8;
+/// Longest `fmt ` or `auxi` chunk that is read into memory.
+const MAX_HEADER_CHUNK_LENGTH: u32 = 4096;
+/// Number of frames read from the file at a time.
+const READ_FRAMES: usize = 4096;
+
+/// Sample formats of a WAV IQ file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavFormat {
+    /// Unsigned 8-bit PCM.
+    U8,
+    /// Signed 16-bit PCM.
+    I16,
+    /// 32-bit IEEE float.
+    F32,
+}
+
+/// Writes received samples to a WAV IQ file on a writer thread.
+pub struct WavIqWriter {
+    thread: WriterThread,
+}
+
+/// Plays back a WAV IQ file, resampling it to the sample rate of the device.
+pub struct WavIqSource {
+    format: WavFormat,
+    sample_rate: u32,
+    center_freq: Option<u64>,
+    inner: Mutex<SourceInner>,
+}
+
+struct SourceInner {
+    reader: RepeatReader,
+    /// Converts the file to the sample rate of the device, if they differ.
+    resampler: Option<AlignedResampler<Complex<f32>>>,
+    /// Samples at the device rate that have not been read yet.
+    pending: Vec<Complex<f32>>,
+    /// Whether the end of the file has been pushed through the resampler.
+    flushed: bool,
+}
+
+/// Restricts a reader to the data chunk of the file.
+struct Region<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl WavFormat {
+    fn bits_per_sample(&self) -> u16 {
+        match self {
+            WavFormat::U8 => 8,
+            WavFormat::I16 => 16,
+            WavFormat::F32 => 32,
+        }
+    }
+
+    /// The encoding of the interleaved samples in the data chunk.
+    pub fn raw(&self) -> RawFormat {
+        match self {
+            WavFormat::U8 => RawFormat::Cu8,
+            WavFormat::I16 => RawFormat::Cs16,
+            WavFormat::F32 => RawFormat::Cf32,
+        }
+    }
+}
+
+impl WavIqWriter {
+    /// Creates a new file at the given path using the sample rate and center frequency
+    /// of the device.
+    pub fn create(path: impl AsRef<Path>, hackrf: &HackRf, format: WavFormat) -> io::Result<Self> {
+        let config = hackrf.config();
+        let sample_rate = config.sample_rate.unwrap_or_default();
+        Self::new(path, format, sample_rate, config.freq)
+    }
+
+    /// Creates a new file at the given path.
+    pub fn new(
+        path: impl AsRef<Path>,
+        format: WavFormat,
+        sample_rate: u32,
+        center_freq: Option<u64>,
+    ) -> io::Result<Self> {
+        let start = SystemTime::now();
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = Header {
+            format,
+            sample_rate,
+            center_freq,
+            start,
+            stop: start,
+            data_len: 0,
+        };
+        header.write(&mut writer)?;
+
+        let thread = WriterThread::spawn(DEFAULT_QUEUE_BLOCKS, move |blocks| {
+            let mut data_len = 0;
+            let (mut samples, mut buffer) = (Vec::new(), Vec::new());
+            for block in blocks {
+                samples.resize(block.len(), Complex::default());
+                util::i8_to_f32(&block, &mut samples);
+
+                buffer.clear();
+                format.raw().encode(&samples, &mut buffer);
+                writer.write_all(&buffer)?;
+                data_len += buffer.len() as u64;
+            }
+
+            writer.seek(SeekFrom::Start(0))?;
+            Header {
+                stop: SystemTime::now(),
+                data_len,
+                ..header
+            }
+            .write(&mut writer)?;
+            writer.flush()
+        });
+
+        Ok(Self { thread })
+    }
+
+    /// Number of samples dropped because the writer could not keep up.
+    pub fn dropped_samples(&self) -> u64 {
+        self.thread.dropped_samples()
+    }
+
+    /// Waits for all queued samples to be written and finalizes the file header.
+    pub fn finish(&self) -> io::Result<()> {
+        self.thread.finish()
+    }
+}
+
+impl SampleSink for WavIqWriter {
+    fn write(&self, samples: &[Complex<i8>], _config: &Config) {
+        self.thread.send(samples);
+    }
+}
+
+impl WavIqSource {
+    /// Opens the file at the given path, resampling it to `sample_rate`.
+    pub fn open(path: impl AsRef<Path>, sample_rate: u32, repeat: Repeat) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header = Header::read(&mut file)?;
+
+        let start = file.stream_position()?;
+        let region = Region {
+            inner: file,
+            start,
+            len: header.data_len,
+            position: 0,
+        };
+
+        let resampler = (header.sample_rate != sample_rate)
+            .then(|| AlignedResampler::new(Resampler::from_rates(header.sample_rate, sample_rate)));
+
+        Ok(Self {
+            format: header.format,
+            sample_rate: header.sample_rate,
+            center_freq: header.center_freq,
+            inner: Mutex::new(SourceInner {
+                reader: RepeatReader::new(region, repeat),
+                resampler,
+                pending: Vec::new(),
+                flushed: false,
+            }),
+        })
+    }
+
+    pub fn format(&self) -> WavFormat {
+        self.format
+    }
+
+    /// Sample rate of the file in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Center frequency from the `auxi` chunk, if the file has one.
+    pub fn center_freq(&self) -> Option<u64> {
+        self.center_freq
+    }
+
+    /// Returns true once every repetition of the file has been played.
+    pub fn is_finished(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.flushed && inner.pending.is_empty()
+    }
+}
+
+impl SourceInner {
+    /// Reads the next block of the file and appends it to `pending` at the device rate.
+    fn fill(&mut self, format: WavFormat) {
+        let raw = format.raw();
+        let mut buffer = vec![0; READ_FRAMES * raw.sample_size()];
+        let read = self.reader.read(&mut buffer) / raw.sample_size();
+        let mut samples = vec![Complex::default(); read];
+        raw.decode(&buffer[..read * raw.sample_size()], &mut samples);
+
+        self.flushed = self.reader.is_finished();
+        match &mut self.resampler {
+            Some(resampler) => {
+                resampler.process(&samples, &mut self.pending);
+                if self.flushed {
+                    resampler.flush(&mut self.pending);
+                }
+            }
+            None => self.pending.extend_from_slice(&samples),
+        }
+    }
+}
+
+impl SampleSource for WavIqSource {
+    fn read(&self, samples: &mut [Complex<i8>]) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        while inner.pending.len() < samples.len() && !inner.flushed {
+            inner.fill(self.format);
+        }
+
+        let len = inner.pending.len().min(samples.len());
+        util::f32_to_i8(&inner.pending[..len], &mut samples[..len]);
+        samples[len..].fill(Complex::default());
+        inner.pending.drain(..len);
+
+        !(inner.flushed && inner.pending.is_empty())
+    }
+}
+
+pub(crate) struct Header {
+    pub format: WavFormat,
+    pub sample_rate: u32,
+    pub center_freq: Option<u64>,
+    pub start: SystemTime,
+    pub stop: SystemTime,
+    /// Length of the data chunk in bytes, or `u64::MAX` if it extends to the end of the file.
+    pub data_len: u64,
+}
+
+impl Header {
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let bits = self.format.bits_per_sample();
+        let block_align = 2 * bits / 8;
+        let format_tag = match self.format {
+            WavFormat::F32 => FORMAT_IEEE_FLOAT,
+            _ => FORMAT_PCM,
+        };
+        let data_len = self.data_len.min((u32::MAX as u64) - HEADER_LENGTH) as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_LENGTH as u32 - 8 + data_len).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&format_tag.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&bits.to_le_bytes())?;
+
+        writer.write_all(b"auxi")?;
+        writer.write_all(&AUXI_LENGTH.to_le_bytes())?;
+        write_system_time(writer, self.start)?;
+        write_system_time(writer, self.stop)?;
+        // Center frequency, ADC frequency, IF frequency, bandwidth, IQ offset and four unused
+        let center_freq = self.center_freq.unwrap_or_default().min(u32::MAX as u64) as u32;
+        for field in [center_freq, self.sample_rate, 0, 0, 0, 0, 0, 0, 0, 0] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Reads chunks up to the start of the data chunk.
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut riff = [0; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+            return Err(invalid_data("not a WAV file"));
+        }
+
+        let mut fmt = None;
+        let mut center_freq = None;
+        let data_len = loop {
+            let mut chunk = [0; 8];
+            reader.read_exact(&mut chunk)?;
+            let len = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+
+            if &chunk[..4] == b"data" {
+                // Files that were not finalized have a data length of zero, so read to the end
+                break match len {
+                    0 | u32::MAX => u64::MAX,
+                    len => len as u64,
+                };
+            }
+
+            // Chunks are padded to an even length
+            let padded = len as u64 + (len & 1) as u64;
+            if !matches!(&chunk[..4], b"fmt " | b"auxi") {
+                let skipped = io::copy(&mut reader.by_ref().take(padded), &mut io::sink())?;
+                if skipped < padded {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                continue;
+            }
+            if len > MAX_HEADER_CHUNK_LENGTH {
+                return Err(invalid_data("oversized header chunk"));
+            }
+
+            let mut data = vec![0; padded as usize];
+            reader.read_exact(&mut data)?;
+            match &chunk[..4] {
+                b"fmt " if data.len() < 16 => return Err(invalid_data("invalid fmt chunk")),
+                b"fmt " => fmt = Some(data),
+                b"auxi" if data.len() >= 36 => {
+                    let freq = u32::from_le_bytes(data[32..36].try_into().unwrap());
+                    center_freq = Some(freq as u64).filter(|&x| x > 0);
+                }
+                _ => {}
+            }
+        };
+
+        let fmt = fmt.ok_or_else(|| invalid_data("missing fmt chunk"))?;
+        let field = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+        let format = match (field(0), field(14)) {
+            (FORMAT_PCM, 8) => WavFormat::U8,
+            (FORMAT_PCM, 16) => WavFormat::I16,
+            (FORMAT_IEEE_FLOAT, 32) => WavFormat::F32,
+            _ => return Err(invalid_data("unsupported sample format")),
+        };
+        if field(2) != 2 {
+            return Err(invalid_data("IQ files must have two channels"));
+        }
+
+        Ok(Self {
+            format,
+            sample_rate: u32::from_le_bytes(fmt[4..8].try_into().unwrap()),
+            center_freq,
+            start: SystemTime::UNIX_EPOCH,
+            stop: SystemTime::UNIX_EPOCH,
+            data_len,
+        })
+    }
+}
+
+fn write_system_time(writer: &mut impl Write, time: SystemTime) -> io::Result<()> {
+    let time = UtcTime::from_system(time);
+    let fields = [
+        time.year as u16,
+        time.month as u16,
+        time.weekday as u16,
+        time.day as u16,
+        time.hour as u16,
+        time.minute as u16,
+        time.second as u16,
+        time.millisecond as u16,
+    ];
+
+    for field in fields {
+        writer.write_all(&field.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> Read for Region<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = (self.len - self.position).min(buf.len() as u64) as usize;
+        let read = self.inner.read(&mut buf[..remaining])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Region<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => x as i64,
+            SeekFrom::Current(x) => self.position as i64 + x,
+            SeekFrom::End(x) => self.len.min(i64::MAX as u64) as i64 + x,
+        };
+
+        self.position = (position.max(0) as u64).min(self.len);
+        self.inner
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::TAU, fs, io, time::SystemTime};
+
+    use num_complex::Complex;
+
+    use super::{Header, WavFormat, WavIqSource, WavIqWriter};
+    use crate::{
+        recording::{temp_path, Repeat, SampleSink, SampleSource},
+        util::{ToComplexF32, ToComplexI8},
+        Config,
+    };
+
+    #[test]
+    fn round_trips_every_format() {
+        let input = (0..3_000)
+            .map(|n| Complex::new((n % 255 - 127) as i8, (n / 24) as i8))
+            .collect::<Vec<_>>();
+
+        for format in [WavFormat::U8, WavFormat::I16, WavFormat::F32] {
+            let path = temp_path(&format!("round-trip-{format:?}.wav"));
+            let writer = WavIqWriter::new(&path, format, 2_000_000, Some(433_920_000)).unwrap();
+            for block in input.chunks(1_000) {
+                writer.write(block, &Config::default());
+            }
+            writer.finish().unwrap();
+
+            let source = WavIqSource::open(&path, 2_000_000, Repeat::Once).unwrap();
+            assert_eq!(source.format(), format);
+            assert_eq!(source.sample_rate(), 2_000_000);
+            assert_eq!(source.center_freq(), Some(433_920_000));
+
+            let mut output = vec![Complex::default(); 2_000];
+            assert!(source.read(&mut output));
+            assert_eq!(output, input[..2_000]);
+            assert!(!source.read(&mut output));
+            assert_eq!(output[..1_000], input[2_000..]);
+            assert!(output[1_000..].iter().all(|x| *x == Complex::default()));
+            assert!(source.is_finished());
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn resamples_to_device_rate() {
+        let path = temp_path("resample.wav");
+        let writer = WavIqWriter::new(&path, WavFormat::F32, 500_000, None).unwrap();
+        let input = (0..50_000)
+            .map(|n| {
+                let turns = (10_000.0 * n as f64 / 500_000.0).fract();
+                Complex::from_polar(100.0 / 127.0, TAU * turns as f32).to_i8()
+            })
+            .collect::<Vec<_>>();
+        writer.write(&input, &Config::default());
+        writer.finish().unwrap();
+
+        let source = WavIqSource::open(&path, 2_000_000, Repeat::Once).unwrap();
+        let mut output = vec![Complex::default(); 250_000];
+        assert!(!source.read(&mut output));
+        fs::remove_file(path).unwrap();
+
+        // The file lasts 200,000 samples at the device rate, lined up with the input apart from
+        // the ringing of the filter at the edges
+        let output = output.iter().map(|x| x.to_f32()).collect::<Vec<_>>();
+        assert!(output[199_000..199_990].iter().all(|x| x.norm() > 0.7));
+        assert!(output[200_030..].iter().all(|x| x.norm() < 0.02));
+
+        // A 10 kHz tone at 2 MHz
+        let sum = output[1_000..199_000]
+            .iter()
+            .enumerate()
+            .map(|(n, x)| {
+                let turns = (10_000.0 * (n + 1_000) as f64 / 2e6).fract();
+                x * Complex::from_polar(1.0, -TAU * turns as f32)
+            })
+            .sum::<Complex<f32>>();
+        let amplitude = sum.norm() / 198_000.0;
+        assert!((amplitude - 100.0 / 127.0).abs() < 0.02, "{amplitude}");
+    }
+
+    #[test]
+    fn skips_unknown_chunks() {
+        let mut header = Vec::new();
+        Header {
+            format: WavFormat::I16,
+            sample_rate: 2_000_000,
+            center_freq: None,
+            start: SystemTime::UNIX_EPOCH,
+            stop: SystemTime::UNIX_EPOCH,
+            data_len: 0,
+        }
+        .write(&mut header)
+        .unwrap();
+
+        // A LIST chunk of odd length with its padding byte, between the riff and fmt chunks
+        let mut file = header[..12].to_vec();
+        file.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        file.extend_from_slice(&header[12..]);
+        let read = Header::read(&mut &file[..]).unwrap();
+        assert_eq!(read.format, WavFormat::I16);
+        assert_eq!(read.sample_rate, 2_000_000);
+
+        // A chunk claiming to be longer than the file is not read into memory
+        let mut file = header[..12].to_vec();
+        file.extend_from_slice(b"LIST\xf0\xff\xff\xffabc");
+        let error = Header::read(&mut &file[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
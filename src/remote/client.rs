@@ -0,0 +1,306 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use num_complex::Complex;
+
+use super::{
+    read_frame, read_message, write_message, write_samples, Frame, Request, Response,
+    PROTOCOL_VERSION,
+};
+use crate::{
+    error::{HackrfError, Result},
+    ffi::SerialNumber,
+    sim::{Pacing, Simulator, TRANSFER_SIZE},
+    stats::StreamMode,
+    Config, DeviceType, HackRf,
+};
+
+/// Time to wait for the server to answer a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for received samples before filling the rest of a transfer with silence.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Received samples buffered before the oldest are dropped.
+const RX_QUEUE_SAMPLES: usize = 16 * TRANSFER_SIZE / 2;
+
+/// Options of a connection to a [`super::RemoteServer`].
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Token expected by the server, if it requires one.
+    pub token: Option<String>,
+    /// Compress samples in both directions, which saves bandwidth at the cost of CPU time on
+    /// both ends.
+    pub compression: bool,
+}
+
+/// Connects to a [`super::RemoteServer`] and returns a device that is controlled over the
+/// connection. The device behaves like a local one, except that [`HackRf::is_remote`] is true.
+pub fn connect(addr: impl ToSocketAddrs, options: &ConnectOptions) -> io::Result<HackRf> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+
+    let hello = Request::Hello {
+        version: PROTOCOL_VERSION,
+        token: options.token.clone(),
+        compression: options.compression,
+    };
+    write_message(&mut writer, &hello)?;
+
+    let info = match read_message(&mut reader)? {
+        Response::Hello {
+            device_type,
+            part_id,
+            serial_no,
+            version,
+            config,
+        } => Info {
+            device_type: DeviceType::from_id(device_type),
+            serial_number: SerialNumber { part_id, serial_no },
+            version,
+            config,
+        },
+        Response::Denied { reason } => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response to hello",
+            ))
+        }
+    };
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            connected: true,
+            response: None,
+            streaming: false,
+            samples: VecDeque::new(),
+        }),
+        changed: Condvar::new(),
+    });
+    thread::spawn({
+        let shared = shared.clone();
+        move || shared.read(reader)
+    });
+
+    Ok(HackRf::simulated(Client {
+        stream,
+        writer: Mutex::new(writer),
+        compression: options.compression,
+        sent: Mutex::new(info.config),
+        info,
+        shared,
+    }))
+}
+
+/// The connection behind a remote [`HackRf`].
+struct Client {
+    stream: TcpStream,
+    /// Held for the whole of a request, so only one is in flight at a time.
+    writer: Mutex<BufWriter<TcpStream>>,
+    compression: bool,
+    /// Configuration last accepted by the server.
+    sent: Mutex<Config>,
+    info: Info,
+    shared: Arc<Shared>,
+}
+
+/// Board information sent by the server when connecting.
+struct Info {
+    device_type: DeviceType,
+    serial_number: SerialNumber,
+    version: String,
+    config: Config,
+}
+
+/// State shared with the thread reading from the connection.
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when a response or samples arrive, or the connection closes.
+    changed: Condvar,
+}
+
+struct State {
+    connected: bool,
+    response: Option<Response>,
+    streaming: bool,
+    samples: VecDeque<Complex<i8>>,
+}
+
+impl Shared {
+    fn read(&self, mut reader: BufReader<TcpStream>) {
+        while let Ok(frame) = read_frame(&mut reader) {
+            let mut state = self.state.lock().unwrap();
+            match frame {
+                Frame::Message(response) => state.response = Some(response),
+                Frame::Samples(samples) if state.streaming => {
+                    state.samples.extend(samples);
+                    let excess = state.samples.len().saturating_sub(RX_QUEUE_SAMPLES);
+                    state.samples.drain(..excess);
+                }
+                Frame::Samples(_) => {}
+            }
+            self.changed.notify_all();
+        }
+
+        self.state.lock().unwrap().connected = false;
+        self.changed.notify_all();
+    }
+}
+
+impl Client {
+    /// Sends a request and waits for its response.
+    fn request(&self, request: &Request) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.shared.state.lock().unwrap().response = None;
+        write_message(&mut *writer, request).map_err(|_| HackrfError::NotFound)?;
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            match state.response.take() {
+                Some(Response::Ok) => return Ok(()),
+                Some(Response::Error { code }) => {
+                    return Err(HackrfError::from_id(code)
+                        .err()
+                        .unwrap_or(HackrfError::Other))
+                }
+                Some(_) => return Err(HackrfError::Other),
+                None if !state.connected => return Err(HackrfError::NotFound),
+                None => {}
+            }
+
+            // A late response would be taken as the answer to the next request, so the
+            // connection is given up instead
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                state.connected = false;
+                let _ = self.stream.shutdown(Shutdown::Both);
+                return Err(HackrfError::NotFound);
+            }
+            state = self.shared.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+}
+
+impl Simulator for Client {
+    fn initial_config(&self) -> Config {
+        self.info.config
+    }
+
+    /// Sends the values that changed since the last accepted configuration.
+    fn configure(&self, config: &Config) -> Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        let update = Config {
+            freq: changed(config.freq, sent.freq),
+            sample_rate: changed(config.sample_rate, sent.sample_rate),
+            amp_enable: changed(config.amp_enable, sent.amp_enable),
+            lna_gain: changed(config.lna_gain, sent.lna_gain),
+            rxvga_gain: changed(config.rxvga_gain, sent.rxvga_gain),
+            txvga_gain: changed(config.txvga_gain, sent.txvga_gain),
+            baseband_filter_bandwidth: changed(
+                config.baseband_filter_bandwidth,
+                sent.baseband_filter_bandwidth,
+            ),
+        };
+
+        if update != Config::default() {
+            self.request(&Request::Configure { config: update })?;
+        }
+        *sent = *config;
+        Ok(())
+    }
+
+    fn stream_started(&self, mode: StreamMode) {
+        let request = match mode {
+            StreamMode::Receive => Request::StartRx,
+            StreamMode::Transmit => Request::StartTx,
+        };
+
+        // The stream ends with the first transfer if the server could not start it
+        let started = self.request(&request).is_ok();
+        let mut state = self.shared.state.lock().unwrap();
+        state.streaming = started;
+        state.samples.clear();
+    }
+
+    fn stream_stopped(&self, _mode: StreamMode) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.streaming = false;
+            state.samples.clear();
+        }
+        let _ = self.request(&Request::Stop);
+    }
+
+    fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        let mut state = self.shared.state.lock().unwrap();
+        while state.connected && state.streaming && state.samples.len() < samples.len() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            state = self.shared.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+
+        let available = state.samples.len().min(samples.len());
+        for (sample, received) in samples.iter_mut().zip(state.samples.drain(..available)) {
+            *sample = received;
+        }
+        samples[available..].fill(Complex::default());
+        state.connected && state.streaming
+    }
+
+    fn transmit(&self, samples: &[Complex<i8>], _config: &Config) -> bool {
+        if !self.shared.state.lock().unwrap().streaming {
+            return false;
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        write_samples(&mut *writer, samples, self.compression).is_ok()
+    }
+
+    /// The server paces the stream at the sample rate of the device.
+    fn pacing(&self) -> Pacing {
+        Pacing::Fast
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    fn device_type(&self) -> DeviceType {
+        self.info.device_type
+    }
+
+    fn serial_number(&self) -> SerialNumber {
+        self.info.serial_number
+    }
+
+    fn version(&self) -> String {
+        self.info.version.clone()
+    }
+}
+
+/// The new value if it differs from the old one.
+fn changed<T: PartialEq>(new: Option<T>, old: Option<T>) -> Option<T> {
+    match new != old {
+        true => new,
+        false => None,
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
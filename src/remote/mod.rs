@@ -0,0 +1,329 @@
+//! A framed TCP protocol for using a HackRF attached to another machine.
+//!
+//! [`RemoteServer`] wraps a [`crate::HackRf`] and serves one client at a time. [`connect`]
+//! returns a [`crate::HackRf`] backed by a server, so the same code can tune, set gains and
+//! stream to and from a local or remote device.
+//!
+//! Every frame starts with a one byte kind and the length of its payload as a little endian
+//! `u32`. Messages are JSON encoded [`Request`]s and [`Response`]s, and samples are sent as
+//! interleaved `i8` IQ pairs, optionally compressed with LZ4. The client starts with a
+//! [`Request::Hello`], and every following request is answered with a single response. Received
+//! samples flow from the server and transmitted samples from the client while a stream runs.
+
+use std::io::{self, Read, Write};
+
+use num_complex::Complex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{sample, Config};
+
+mod client;
+mod server;
+pub use client::{connect, ConnectOptions};
+pub use server::RemoteServer;
+
+/// Version of the protocol, which the client and server must agree on.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Largest accepted frame payload in bytes.
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+const KIND_MESSAGE: u8 = 0;
+const KIND_SAMPLES: u8 = 1;
+const KIND_COMPRESSED_SAMPLES: u8 = 2;
+
+/// Messages sent from the client to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Opens the session. Must be the first request of a connection.
+    Hello {
+        version: u32,
+        token: Option<String>,
+        /// Compress the samples sent by the server.
+        compression: bool,
+    },
+    /// Applies every value that is set.
+    Configure {
+        config: Config,
+    },
+    StartRx,
+    StartTx,
+    /// Stops the current stream.
+    Stop,
+}
+
+/// Messages sent from the server to the client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// Accepts the session and describes the device.
+    Hello {
+        device_type: u8,
+        part_id: [u32; 2],
+        serial_no: [u32; 4],
+        version: String,
+        config: Config,
+    },
+    /// Refuses the session, such as for a wrong token or protocol version.
+    Denied {
+        reason: String,
+    },
+    Ok,
+    /// A request failed with the id of a [`crate::error::HackrfError`].
+    Error {
+        code: i32,
+    },
+}
+
+/// A frame of the protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame<M> {
+    Message(M),
+    Samples(Vec<Complex<i8>>),
+}
+
+/// Reads the next frame, decompressing samples if needed.
+pub fn read_frame<M: DeserializeOwned>(reader: &mut impl Read) -> io::Result<Frame<M>> {
+    let (kind, payload) = read_payload(reader)?;
+    match kind {
+        KIND_MESSAGE => Ok(Frame::Message(serde_json::from_slice(&payload)?)),
+        KIND_SAMPLES => Ok(Frame::Samples(samples_from_bytes(&payload)?)),
+        KIND_COMPRESSED_SAMPLES => {
+            // The decompressed size is checked before lz4_flex allocates a buffer of that size
+            let size = payload
+                .get(..4)
+                .map(|x| u32::from_le_bytes(x.try_into().unwrap()));
+            if size.is_none_or(|x| x as usize > MAX_FRAME_LENGTH) {
+                return Err(invalid_data("decompressed frame too long"));
+            }
+
+            let payload = lz4_flex::decompress_size_prepended(&payload)
+                .map_err(|err| invalid_data(&err.to_string()))?;
+            Ok(Frame::Samples(samples_from_bytes(&payload)?))
+        }
+        _ => Err(invalid_data("unknown frame kind")),
+    }
+}
+
+/// Reads the next frame, which must be a message, such as the hello that is read before the
+/// peer is authenticated.
+pub fn read_message<M: DeserializeOwned>(reader: &mut impl Read) -> io::Result<M> {
+    let (kind, payload) = read_payload(reader)?;
+    match kind {
+        KIND_MESSAGE => Ok(serde_json::from_slice(&payload)?),
+        _ => Err(invalid_data("expected a message")),
+    }
+}
+
+fn read_payload(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(invalid_data("frame too long"));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+/// Writes a message frame.
+pub fn write_message(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    write_frame(writer, KIND_MESSAGE, &serde_json::to_vec(message)?)
+}
+
+/// Writes a frame of samples, compressed with LZ4 if `compress` is set.
+pub fn write_samples(
+    writer: &mut impl Write,
+    samples: &[Complex<i8>],
+    compress: bool,
+) -> io::Result<()> {
+    let bytes = sample::as_bytes(samples);
+    match compress {
+        true => write_frame(
+            writer,
+            KIND_COMPRESSED_SAMPLES,
+            &lz4_flex::compress_prepend_size(bytes),
+        ),
+        false => write_frame(writer, KIND_SAMPLES, bytes),
+    }
+}
+
+fn write_frame(writer: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame too long",
+        ));
+    }
+
+    let mut header = [kind, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+fn samples_from_bytes(bytes: &[u8]) -> io::Result<Vec<Complex<i8>>> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(invalid_data("odd number of sample bytes"));
+    }
+    Ok(sample::from_bytes(bytes).to_vec())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Cursor},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{
+        connect, read_frame, read_message, write_message, write_samples, ConnectOptions, Frame,
+        RemoteServer, Request, KIND_COMPRESSED_SAMPLES, KIND_SAMPLES, MAX_FRAME_LENGTH,
+    };
+    use crate::{sim::Simulator, Config, HackRf};
+
+    #[test]
+    fn frames_round_trip() {
+        let request = Request::Configure {
+            config: Config {
+                freq: Some(915_000_000),
+                lna_gain: Some(24),
+                ..Config::default()
+            },
+        };
+        let samples = (0..10_000)
+            .map(|n| Complex::new((n % 16) as i8, -((n % 7) as i8)))
+            .collect::<Vec<_>>();
+
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &request).unwrap();
+        let start = bytes.len();
+        write_samples(&mut bytes, &samples, false).unwrap();
+        assert_eq!(bytes[start], KIND_SAMPLES);
+        let start = bytes.len();
+        write_samples(&mut bytes, &samples, true).unwrap();
+        assert_eq!(bytes[start], KIND_COMPRESSED_SAMPLES);
+        assert!(bytes.len() - start < samples.len());
+
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(read_frame(&mut reader).unwrap(), Frame::Message(request));
+        for _ in 0..2 {
+            let frame = read_frame::<Request>(&mut reader).unwrap();
+            assert_eq!(frame, Frame::Samples(samples.clone()));
+        }
+        let end = read_frame::<Request>(&mut reader).unwrap_err();
+        assert_eq!(end.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn refuses_long_frames() {
+        let mut header = vec![0];
+        header.extend_from_slice(&(MAX_FRAME_LENGTH as u32 + 1).to_le_bytes());
+        let err = read_frame::<Request>(&mut Cursor::new(header)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A compressed frame claiming to decompress to 4 GiB
+        let frame = [KIND_COMPRESSED_SAMPLES, 4, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+        let err = read_frame::<Request>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hello_must_be_a_message() {
+        let mut bytes = Vec::new();
+        write_samples(&mut bytes, &[Complex::new(1, 2)], true).unwrap();
+        let err = read_message::<Request>(&mut Cursor::new(bytes)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Receives the same sample at 10 MHz.
+    struct Constant;
+
+    impl Simulator for Constant {
+        fn initial_config(&self) -> Config {
+            Config {
+                sample_rate: Some(10_000_000),
+                ..Config::default()
+            }
+        }
+
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(Complex::new(3, -4));
+            true
+        }
+    }
+
+    type Received = Arc<Mutex<Vec<Complex<i8>>>>;
+
+    #[test]
+    fn controls_and_streams_from_server() {
+        let device = HackRf::simulated(Constant);
+        let server = RemoteServer::bind(device.clone(), "127.0.0.1:0")
+            .unwrap()
+            .with_token("secret");
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let wrong = ConnectOptions {
+            token: Some("secreT".into()),
+            compression: false,
+        };
+        let err = connect(addr, &wrong).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let options = ConnectOptions {
+            token: Some("secret".into()),
+            compression: true,
+        };
+        let remote = connect(addr, &options).unwrap();
+        assert!(remote.is_remote() && !remote.is_simulated());
+        assert_eq!(remote.config().sample_rate, Some(10_000_000));
+        assert_eq!(remote.version(), device.version());
+
+        remote.set_freq(915_000_000).unwrap();
+        remote.set_lna_gain(24).unwrap();
+        assert_eq!(device.config().freq, Some(915_000_000));
+        assert_eq!(device.config().lna_gain, Some(24));
+
+        let received = Received::default();
+        remote
+            .start_rx(
+                |_, samples, _, user| {
+                    let received = user.downcast_ref::<Received>().unwrap();
+                    received.lock().unwrap().extend_from_slice(samples);
+                },
+                received.clone(),
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        while received.lock().unwrap().len() < 500_000 {
+            assert!(start.elapsed() < Duration::from_secs(5), "nothing received");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(device.is_streaming());
+        remote.stop_rx().unwrap();
+
+        let start = Instant::now();
+        while device.is_streaming() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "server kept streaming"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        let received = received.lock().unwrap();
+        assert!(received.iter().all(|x| *x == Complex::new(3, -4)));
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    any::Any,
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use num_complex::Complex;
+
+use super::{
+    read_frame, read_message, write_message, write_samples, Frame, Request, Response,
+    PROTOCOL_VERSION,
+};
+use crate::{error::Result, stats::StreamMode, DeviceType, HackRf, TransferInfo};
+
+/// Compares in a time that only depends on the lengths, so response times do not reveal how
+/// much of a guessed token is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    a.len() == b.len() && std::hint::black_box(difference) == 0
+}
+
+/// Frames buffered for a client before received samples are dropped.
+const QUEUE_FRAMES: usize = 16;
+/// Transmit samples buffered from a client before it has to wait.
+const TX_QUEUE_SAMPLES: usize = 4 * 131_072;
+/// Time to wait for the transmit queue to drain before checking the stream again.
+const TX_WAIT: Duration = Duration::from_millis(100);
+
+/// Serves a device to remote clients created with [`super::connect`].
+pub struct RemoteServer {
+    hackrf: HackRf,
+    listener: TcpListener,
+    token: Option<String>,
+}
+
+/// Samples sent by the client, waiting to be transmitted.
+#[derive(Default)]
+struct TxQueue {
+    samples: Mutex<VecDeque<Complex<i8>>>,
+    /// Notified when samples are taken from the queue.
+    drained: Condvar,
+}
+
+impl RemoteServer {
+    pub fn bind(hackrf: HackRf, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            hackrf,
+            listener: TcpListener::bind(addr)?,
+            token: None,
+        })
+    }
+
+    /// Only accepts clients that send this token.
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients one after another until accepting a connection fails.
+    pub fn serve(&self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            // A client disconnecting is not an error of the server
+            let _ = self.serve_client(stream);
+        }
+    }
+
+    /// Handles the requests of a client until it disconnects, then stops any stream it left
+    /// running.
+    pub fn serve_client(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream.try_clone()?);
+
+        let compression = match read_message(&mut reader)? {
+            Request::Hello {
+                version,
+                token,
+                compression,
+            } => {
+                if let Some(reason) = self.check_hello(version, token) {
+                    write_message(&mut writer, &Response::Denied { reason })?;
+                    return Ok(());
+                }
+                compression
+            }
+            _ => {
+                let reason = "expected hello".into();
+                write_message(&mut writer, &Response::Denied { reason })?;
+                return Ok(());
+            }
+        };
+        write_message(&mut writer, &self.hello())?;
+
+        // Responses and received samples are written by a separate thread, so a slow client
+        // only causes received samples to be dropped.
+        let (sender, receiver) = mpsc::sync_channel::<Frame<Response>>(QUEUE_FRAMES);
+        let writer = thread::spawn(move || {
+            for frame in receiver {
+                let result = match frame {
+                    Frame::Message(response) => write_message(&mut writer, &response),
+                    Frame::Samples(samples) => write_samples(&mut writer, &samples, compression),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let tx_queue = Arc::new(TxQueue::default());
+        let mut mode = None;
+        let result = loop {
+            let request = match read_frame(&mut reader) {
+                Ok(Frame::Message(request)) => request,
+                Ok(Frame::Samples(samples)) => {
+                    if mode == Some(StreamMode::Transmit) {
+                        self.queue_tx(&tx_queue, samples);
+                    }
+                    continue;
+                }
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+                Err(err) => break Err(err),
+            };
+
+            let response = match self.handle(request, &mut mode, &sender, &tx_queue) {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error { code: err.id() },
+            };
+            if sender.send(Frame::Message(response)).is_err() {
+                break Ok(());
+            }
+        };
+
+        let _ = self.stop(&mut mode);
+        let _ = stream.shutdown(Shutdown::Both);
+        drop(sender);
+        let _ = writer.join();
+        result
+    }
+
+    fn check_hello(&self, version: u32, token: Option<String>) -> Option<String> {
+        if version != PROTOCOL_VERSION {
+            return Some(format!("unsupported protocol version {version}"));
+        }
+
+        let valid = match (&self.token, token) {
+            (None, _) => true,
+            (Some(expected), Some(token)) => {
+                constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            (Some(_), None) => false,
+        };
+        match valid {
+            true => None,
+            false => Some("invalid token".into()),
+        }
+    }
+
+    fn hello(&self) -> Response {
+        let serial_number = self.hackrf.get_serial_number().unwrap_or_default();
+        Response::Hello {
+            device_type: self
+                .hackrf
+                .get_device_type()
+                .unwrap_or(DeviceType::Undetected) as u8,
+            part_id: serial_number.part_id,
+            serial_no: serial_number.serial_no,
+            version: self.hackrf.version(),
+            config: self.hackrf.config(),
+        }
+    }
+
+    fn handle(
+        &self,
+        request: Request,
+        mode: &mut Option<StreamMode>,
+        sender: &SyncSender<Frame<Response>>,
+        tx_queue: &Arc<TxQueue>,
+    ) -> Result<()> {
+        match request {
+            Request::Hello { .. } => Ok(()),
+            Request::Configure { config } => config.apply(&self.hackrf),
+            Request::StartRx => {
+                self.stop(mode)?;
+                self.hackrf.start_rx(rx_callback, sender.clone())?;
+                *mode = Some(StreamMode::Receive);
+                Ok(())
+            }
+            Request::StartTx => {
+                self.stop(mode)?;
+                tx_queue.samples.lock().unwrap().clear();
+                self.hackrf.start_tx(tx_callback, tx_queue.clone())?;
+                *mode = Some(StreamMode::Transmit);
+                Ok(())
+            }
+            Request::Stop => self.stop(mode),
+        }
+    }
+
+    fn stop(&self, mode: &mut Option<StreamMode>) -> Result<()> {
+        match mode.take() {
+            Some(StreamMode::Receive) => self.hackrf.stop_rx(),
+            Some(StreamMode::Transmit) => self.hackrf.stop_tx(),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues samples for transmission, waiting while the queue is full so the client is
+    /// slowed down to the sample rate.
+    fn queue_tx(&self, tx_queue: &TxQueue, samples: Vec<Complex<i8>>) {
+        let mut queue = tx_queue.samples.lock().unwrap();
+        while queue.len() > TX_QUEUE_SAMPLES && self.hackrf.is_streaming() {
+            queue = tx_queue.drained.wait_timeout(queue, TX_WAIT).unwrap().0;
+        }
+        queue.extend(samples);
+    }
+}
+
+fn rx_callback(_hackrf: &HackRf, samples: &[Complex<i8>], _info: &TransferInfo, user: &dyn Any) {
+    let sender = user.downcast_ref::<SyncSender<Frame<Response>>>().unwrap();
+    let _ = sender.try_send(Frame::Samples(samples.to_vec()));
+}
+
+fn tx_callback(
+    _hackrf: &HackRf,
+    samples: &mut [Complex<i8>],
+    _info: &TransferInfo,
+    user: &dyn Any,
+) {
+    let tx_queue = user.downcast_ref::<Arc<TxQueue>>().unwrap();
+    let mut queue = tx_queue.samples.lock().unwrap();
+
+    // Transmit silence when the client falls behind
+    let available = queue.len().min(samples.len());
+    for (sample, queued) in samples.iter_mut().zip(queue.drain(..available)) {
+        *sample = queued;
+    }
+    samples[available..].fill(Complex::default());
+    tx_queue.drained.notify_all();
+}
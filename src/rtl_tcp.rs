@@ -0,0 +1,382 @@
+//! A server speaking the `rtl_tcp` protocol, so software written for RTL-SDR dongles can
+//! receive from a HackRF over the network.
+//!
+//! Clients are served one at a time. Each client is sent a header describing an R820T tuner
+//! followed by a stream of unsigned 8-bit IQ samples, and controls the device with 5-byte
+//! commands. Automatic gain is provided by a [`HardwareAgc`] and commands without a HackRF
+//! equivalent are ignored.
+
+use std::{
+    any::Any,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use num_complex::Complex;
+
+use crate::{
+    agc::{split_gain, HardwareAgc},
+    error::{HackrfError, Result},
+    sample, HackRf, TransferInfo,
+};
+
+/// Gains of the R820T tuner in tenths of a dB, which clients select by index.
+pub const GAINS: [u32; 29] = [
+    0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254, 280, 297, 328, 338, 364, 372,
+    386, 402, 421, 434, 439, 445, 480, 496,
+];
+/// LNA and VGA gain in dB that automatic gain starts from.
+pub const AUTO_GAIN: (u32, u32) = (16, 16);
+/// Level in dBFS that automatic gain keeps the samples at.
+pub const AGC_TARGET: f32 = -18.0;
+
+const MAGIC: &[u8; 4] = b"RTL0";
+const TUNER_R820T: u32 = 5;
+/// Transfers buffered for a client before samples are dropped.
+const QUEUE_BLOCKS: usize = 16;
+
+/// Commands sent by `rtl_tcp` clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetFreq(u32),
+    SetSampleRate(u32),
+    /// Zero for automatic gain, otherwise manual.
+    SetGainMode(u32),
+    /// Gain in tenths of a dB.
+    SetGain(u32),
+    /// Error of the reference clock in parts per million.
+    SetFreqCorrection(i32),
+    /// The digital AGC of the RTL2832, which enables automatic gain like [`Command::SetGainMode`].
+    SetAgcMode(bool),
+    /// Index into [`GAINS`].
+    SetGainByIndex(u32),
+    /// A command without a HackRF equivalent.
+    Unsupported(u8, u32),
+}
+
+/// Serves the samples received by a device to `rtl_tcp` clients.
+pub struct RtlTcpServer {
+    hackrf: HackRf,
+    listener: TcpListener,
+}
+
+/// Settings of the current client that are not stored in the device configuration.
+#[derive(Default)]
+struct ClientState {
+    freq: Option<u32>,
+    ppm: i32,
+    manual_gain: bool,
+    agc_mode: bool,
+    gain: u32,
+    /// Cleared to stop the automatic gain control started for the client.
+    agc: Option<Arc<AtomicBool>>,
+}
+
+impl Command {
+    /// Parses a 5-byte command.
+    pub fn parse(bytes: [u8; 5]) -> Self {
+        let param = u32::from_be_bytes(bytes[1..].try_into().unwrap());
+        match bytes[0] {
+            0x01 => Command::SetFreq(param),
+            0x02 => Command::SetSampleRate(param),
+            0x03 => Command::SetGainMode(param),
+            0x04 => Command::SetGain(param),
+            0x05 => Command::SetFreqCorrection(param as i32),
+            0x08 => Command::SetAgcMode(param != 0),
+            0x0d => Command::SetGainByIndex(param),
+            command => Command::Unsupported(command, param),
+        }
+    }
+
+    /// Encodes the command as sent by a client.
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let (command, param) = match *self {
+            Command::SetFreq(x) => (0x01, x),
+            Command::SetSampleRate(x) => (0x02, x),
+            Command::SetGainMode(x) => (0x03, x),
+            Command::SetGain(x) => (0x04, x),
+            Command::SetFreqCorrection(x) => (0x05, x as u32),
+            Command::SetAgcMode(x) => (0x08, x as u32),
+            Command::SetGainByIndex(x) => (0x0d, x),
+            Command::Unsupported(command, param) => (command, param),
+        };
+
+        let mut bytes = [command, 0, 0, 0, 0];
+        bytes[1..].copy_from_slice(&param.to_be_bytes());
+        bytes
+    }
+}
+
+impl RtlTcpServer {
+    pub fn bind(hackrf: HackRf, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            hackrf,
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients one after another until accepting a connection fails or the device
+    /// cannot start streaming.
+    pub fn serve(&self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            match self.serve_client(stream) {
+                Err(err) if err.get_ref().is_some_and(|x| x.is::<HackrfError>()) => {
+                    return Err(err)
+                }
+                // A client disconnecting is not an error of the server
+                _ => {}
+            }
+        }
+    }
+
+    /// Streams samples to a client until it disconnects or the device stops streaming.
+    /// Errors of the device are returned wrapping a [`HackrfError`], to tell them apart from
+    /// errors of the connection.
+    pub fn serve_client(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+
+        let mut header = [0; 12];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&TUNER_R820T.to_be_bytes());
+        header[8..].copy_from_slice(&(GAINS.len() as u32).to_be_bytes());
+        stream.write_all(&header)?;
+
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(QUEUE_BLOCKS);
+        self.hackrf
+            .start_rx(rx_callback, sender)
+            .map_err(io::Error::other)?;
+
+        let commands = {
+            let (hackrf, mut stream) = (self.hackrf.clone(), stream.try_clone()?);
+            thread::spawn(move || {
+                let mut state = ClientState::default();
+                let mut command = [0; 5];
+                while stream.read_exact(&mut command).is_ok() {
+                    let _ = state.apply(&hackrf, Command::parse(command));
+                }
+                let _ = stream.shutdown(Shutdown::Both);
+            })
+        };
+
+        let result = loop {
+            match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(block) => {
+                    if let Err(err) = stream.write_all(&block) {
+                        break Err(err);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) if self.hackrf.is_streaming() => {}
+                Err(_) => break Ok(()),
+            }
+        };
+
+        let _ = stream.shutdown(Shutdown::Both);
+        let _ = self.hackrf.stop_rx();
+        let _ = commands.join();
+        result
+    }
+}
+
+impl ClientState {
+    fn apply(&mut self, hackrf: &HackRf, command: Command) -> Result<()> {
+        match command {
+            Command::SetFreq(freq) => {
+                self.freq = Some(freq);
+                self.tune(hackrf)
+            }
+            Command::SetFreqCorrection(ppm) => {
+                self.ppm = ppm;
+                self.tune(hackrf)
+            }
+            Command::SetSampleRate(sample_rate) => {
+                hackrf.set_sample_rate(sample_rate)?;
+                hackrf.set_baseband_filter_bandwidth(sample_rate / 4 * 3)
+            }
+            Command::SetGainMode(mode) => {
+                self.manual_gain = mode != 0;
+                self.apply_gain(hackrf)
+            }
+            Command::SetGain(gain) => {
+                self.gain = gain;
+                self.apply_gain(hackrf)
+            }
+            Command::SetGainByIndex(index) => {
+                self.gain = GAINS[(index as usize).min(GAINS.len() - 1)];
+                self.apply_gain(hackrf)
+            }
+            Command::SetAgcMode(enabled) => {
+                self.agc_mode = enabled;
+                self.apply_gain(hackrf)
+            }
+            Command::Unsupported(..) => Ok(()),
+        }
+    }
+
+    /// Tunes to the requested frequency, compensating a reference clock that runs `ppm` fast,
+    /// which shifts every frequency it synthesizes up by the same factor.
+    fn tune(&self, hackrf: &HackRf) -> Result<()> {
+        match self.freq {
+            Some(freq) => {
+                let corrected = freq as f64 / (1.0 + self.ppm as f64 * 1e-6);
+                hackrf.set_freq(corrected.round() as u64)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the manual gain, or starts automatic gain control if the client asked for it.
+    fn apply_gain(&mut self, hackrf: &HackRf) -> Result<()> {
+        if self.manual_gain && !self.agc_mode {
+            self.stop_agc();
+            let (lna, vga) = split_gain((self.gain + 5) / 10);
+            hackrf.set_lna_gain(lna)?;
+            return hackrf.set_rxvga_gain(vga);
+        }
+
+        if self.agc.is_none() {
+            hackrf.set_lna_gain(AUTO_GAIN.0)?;
+            hackrf.set_rxvga_gain(AUTO_GAIN.1)?;
+
+            let enabled = Arc::new(AtomicBool::new(true));
+            HardwareAgc::new(AGC_TARGET).spawn_while(hackrf.clone(), enabled.clone());
+            self.agc = Some(enabled);
+        }
+        Ok(())
+    }
+
+    fn stop_agc(&mut self) {
+        if let Some(enabled) = self.agc.take() {
+            enabled.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for ClientState {
+    fn drop(&mut self) {
+        self.stop_agc();
+    }
+}
+
+fn rx_callback(_hackrf: &HackRf, samples: &[Complex<i8>], _info: &TransferInfo, user: &dyn Any) {
+    let sender = user.downcast_ref::<SyncSender<Vec<u8>>>().unwrap();
+    let block = sample::as_bytes(samples).iter().map(|x| x ^ 0x80).collect();
+    let _ = sender.try_send(block);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{ClientState, Command, RtlTcpServer, AUTO_GAIN, GAINS};
+    use crate::{sim::Simulator, Config, HackRf};
+
+    /// Receives the same sample over and over.
+    struct Constant(Complex<i8>);
+
+    impl Simulator for Constant {
+        fn initial_config(&self) -> Config {
+            Config {
+                sample_rate: Some(10_000_000),
+                ..Config::default()
+            }
+        }
+
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(self.0);
+            true
+        }
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn serves_samples_and_commands() {
+        let hackrf = HackRf::simulated(Constant(Complex::new(10, -20)));
+        let server = RtlTcpServer::bind(hackrf.clone(), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let thread = thread::spawn(move || {
+            let (stream, _) = server.listener.accept().unwrap();
+            server.serve_client(stream)
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut header = [0; 12];
+        client.read_exact(&mut header).unwrap();
+        assert_eq!(header[..4], *b"RTL0");
+        assert_eq!(header[4..8], 5u32.to_be_bytes());
+        assert_eq!(header[8..], (GAINS.len() as u32).to_be_bytes());
+
+        for command in [
+            Command::SetSampleRate(8_000_000),
+            Command::SetFreqCorrection(10),
+            Command::SetFreq(433_920_000),
+        ] {
+            client.write_all(&command.to_bytes()).unwrap();
+        }
+
+        // Unsigned samples with the sign bit flipped
+        let mut samples = [0; 4096];
+        client.read_exact(&mut samples).unwrap();
+        assert!(samples.chunks(2).all(|x| x == [138, 108]));
+
+        // A clock running 10 ppm fast is tuned 10 ppm low
+        wait_for(|| hackrf.config().freq == Some(433_915_661));
+        assert_eq!(hackrf.config().sample_rate, Some(8_000_000));
+        assert_eq!(hackrf.config().baseband_filter_bandwidth, Some(6_000_000));
+
+        // The server stops streaming once writing to the closed connection fails
+        drop(client);
+        assert!(thread.join().unwrap().is_err());
+        assert!(!hackrf.is_streaming());
+    }
+
+    #[test]
+    fn automatic_gain_runs_until_manual_gain() {
+        let hackrf = HackRf::simulated(Constant(Complex::new(127, 127)));
+        hackrf.start_rx(|_, _, _, _| {}, ()).unwrap();
+        let gain = || {
+            let config = hackrf.config();
+            (config.lna_gain.unwrap(), config.rxvga_gain.unwrap())
+        };
+
+        let mut state = ClientState::default();
+        state.apply(&hackrf, Command::SetAgcMode(true)).unwrap();
+        assert_eq!(gain(), AUTO_GAIN);
+        // The clipping samples make the AGC back off
+        wait_for(|| gain().0 + gain().1 < AUTO_GAIN.0 + AUTO_GAIN.1);
+
+        state.apply(&hackrf, Command::SetAgcMode(false)).unwrap();
+        state.apply(&hackrf, Command::SetGainMode(1)).unwrap();
+        state.apply(&hackrf, Command::SetGain(200)).unwrap();
+        assert_eq!(gain(), (16, 4));
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(gain(), (16, 4));
+
+        hackrf.stop_rx().unwrap();
+    }
+}
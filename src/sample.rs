@@ -0,0 +1,134 @@
+use std::mem;
+
+use bytemuck::{Pod, Zeroable};
+use num_complex::Complex;
+
+/// An interleaved signed 8-bit IQ sample, the native sample format of the HackRF.
+/// Has the same layout as a `Complex<i8>` and as a pair of bytes in a `.cs8` file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(C)]
+pub struct IqI8 {
+    pub i: i8,
+    pub q: i8,
+}
+
+const _: () = {
+    assert!(mem::size_of::<IqI8>() == 2);
+    assert!(mem::align_of::<IqI8>() == 1);
+    assert!(mem::offset_of!(IqI8, q) == 1);
+
+    assert!(mem::size_of::<Complex<i8>>() == 2);
+    assert!(mem::align_of::<Complex<i8>>() == 1);
+    assert!(mem::offset_of!(Complex<i8>, im) == 1);
+};
+
+/// Sample types that can be reinterpreted from the raw bytes of a transfer buffer.
+pub trait IqSample: Pod + sealed::Sealed {}
+
+impl IqSample for IqI8 {}
+impl IqSample for Complex<i8> {}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::IqI8 {}
+    impl Sealed for num_complex::Complex<i8> {}
+}
+
+impl IqI8 {
+    pub const ZERO: Self = Self::new(0, 0);
+
+    pub const fn new(i: i8, q: i8) -> Self {
+        Self { i, q }
+    }
+}
+
+impl From<Complex<i8>> for IqI8 {
+    fn from(value: Complex<i8>) -> Self {
+        Self::new(value.re, value.im)
+    }
+}
+
+impl From<IqI8> for Complex<i8> {
+    fn from(value: IqI8) -> Self {
+        Complex::new(value.i, value.q)
+    }
+}
+
+/// Reinterprets interleaved IQ bytes as samples.
+/// A trailing byte that does not make up a full sample is ignored.
+pub fn from_bytes<T: IqSample>(bytes: &[u8]) -> &[T] {
+    bytemuck::cast_slice(&bytes[..bytes.len() & !1])
+}
+
+/// Reinterprets interleaved IQ bytes as mutable samples.
+/// A trailing byte that does not make up a full sample is ignored.
+pub fn from_bytes_mut<T: IqSample>(bytes: &mut [u8]) -> &mut [T] {
+    let len = bytes.len() & !1;
+    bytemuck::cast_slice_mut(&mut bytes[..len])
+}
+
+/// Reinterprets samples as interleaved IQ bytes, ready to be written to a `.cs8` file.
+pub fn as_bytes<T: IqSample>(samples: &[T]) -> &[u8] {
+    bytemuck::cast_slice(samples)
+}
+
+/// Reinterprets samples as mutable interleaved IQ bytes.
+pub fn as_bytes_mut<T: IqSample>(samples: &mut [T]) -> &mut [u8] {
+    bytemuck::cast_slice_mut(samples)
+}
+
+/// Reinterprets `Complex<i8>` samples as [`IqI8`] samples.
+pub fn from_complex(samples: &[Complex<i8>]) -> &[IqI8] {
+    bytemuck::cast_slice(samples)
+}
+
+/// Reinterprets mutable `Complex<i8>` samples as [`IqI8`] samples.
+pub fn from_complex_mut(samples: &mut [Complex<i8>]) -> &mut [IqI8] {
+    bytemuck::cast_slice_mut(samples)
+}
+
+/// Reinterprets [`IqI8`] samples as `Complex<i8>` samples.
+pub fn as_complex(samples: &[IqI8]) -> &[Complex<i8>] {
+    bytemuck::cast_slice(samples)
+}
+
+/// Reinterprets mutable [`IqI8`] samples as `Complex<i8>` samples.
+pub fn as_complex_mut(samples: &mut [IqI8]) -> &mut [Complex<i8>] {
+    bytemuck::cast_slice_mut(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::{as_bytes, as_complex, from_bytes, from_bytes_mut, from_complex, IqI8};
+
+    const BYTES: [u8; 5] = [1, 0xFF, 0x80, 0x7F, 9];
+
+    #[test]
+    fn reads_interleaved_bytes() {
+        let samples = from_bytes::<IqI8>(&BYTES);
+        assert_eq!(samples, [IqI8::new(1, -1), IqI8::new(-128, 127)]);
+
+        let samples = from_bytes::<Complex<i8>>(&BYTES);
+        assert_eq!(samples, [Complex::new(1, -1), Complex::new(-128, 127)]);
+        assert_eq!(as_bytes(samples), &BYTES[..4]);
+    }
+
+    #[test]
+    fn shares_memory_without_copying() {
+        let mut bytes = BYTES;
+        let samples = from_bytes_mut::<IqI8>(&mut bytes);
+        samples[1].q = -2;
+        let pointer = samples.as_ptr() as *const u8;
+        assert_eq!(pointer, bytes.as_ptr());
+        assert_eq!(bytes, [1, 0xFF, 0x80, 0xFE, 9]);
+
+        let samples = from_bytes::<IqI8>(&bytes);
+        let complex = as_complex(samples);
+        assert_eq!(complex.as_ptr() as *const u8, bytes.as_ptr());
+        assert_eq!(complex[1], Complex::from(samples[1]));
+        assert_eq!(from_complex(complex), samples);
+        assert_eq!(as_bytes(from_complex(complex)), &bytes[..4]);
+    }
+}
@@ -0,0 +1,852 @@
+//! A device independent SDR interface modeled on the API of SoapySDR, so applications can be
+//! written against [`SdrDevice`] and run on a [`HackRf`] or any other radio implementing it.
+//!
+//! Devices have a number of channels in each direction, each with a frequency range, named
+//! gain elements, a list of sample rates and bandwidths and a set of stream formats. Streams
+//! are set up in a format chosen from [`SdrDevice::stream_formats`] and read or written in
+//! blocks, with samples converted from the native format of the device as needed.
+
+use std::{
+    any::Any,
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use num_complex::Complex;
+
+use crate::{
+    error::{HackrfError, Result},
+    sim::TRANSFER_SIZE,
+    stats::StreamMode,
+    util, HackRf, TransferInfo,
+};
+
+/// Samples per transfer of a HackRF.
+const HACKRF_MTU: usize = TRANSFER_SIZE / 2;
+/// Transfers buffered by a HackRF stream before received samples are dropped or writes block.
+const HACKRF_QUEUE_TRANSFERS: usize = 16;
+
+/// Baseband filter bandwidths of the MAX2837 in Hz.
+const HACKRF_BANDWIDTHS: [f64; 16] = [
+    1.75e6, 2.5e6, 3.5e6, 5e6, 5.5e6, 6e6, 7e6, 8e6, 9e6, 10e6, 12e6, 14e6, 15e6, 20e6, 24e6, 28e6,
+];
+
+/// A range of values, such as of a frequency or gain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+    /// Resolution of values within the range, or zero if continuous.
+    pub step: f64,
+}
+
+/// Sample formats of a stream, named like the formats of SoapySDR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Interleaved signed 8-bit IQ.
+    Cs8,
+    /// Interleaved signed 16-bit IQ.
+    Cs16,
+    /// Interleaved 32-bit float IQ in the range [-1, 1].
+    Cf32,
+}
+
+/// A buffer of samples to transmit in any [`StreamFormat`].
+#[derive(Debug, Clone, Copy)]
+pub enum Samples<'a> {
+    Cs8(&'a [Complex<i8>]),
+    Cs16(&'a [Complex<i16>]),
+    Cf32(&'a [Complex<f32>]),
+}
+
+/// A buffer for received samples in any [`StreamFormat`].
+#[derive(Debug)]
+pub enum SamplesMut<'a> {
+    Cs8(&'a mut [Complex<i8>]),
+    Cs16(&'a mut [Complex<i16>]),
+    Cf32(&'a mut [Complex<f32>]),
+}
+
+/// A receive stream created with [`SdrDevice::setup_rx_stream`].
+pub trait RxStream: Send {
+    fn format(&self) -> StreamFormat;
+
+    /// Number of samples the device delivers at once.
+    fn mtu(&self) -> usize;
+
+    /// Starts streaming.
+    fn activate(&mut self) -> Result<()>;
+
+    /// Stops streaming. Samples already received can still be read.
+    fn deactivate(&mut self) -> Result<()>;
+
+    /// Reads received samples into a buffer of the stream format, waiting up to `timeout` for
+    /// the first. Returns the number of samples read, which is zero on timeout.
+    fn read(&mut self, buffer: SamplesMut<'_>, timeout: Duration) -> Result<usize>;
+}
+
+/// A transmit stream created with [`SdrDevice::setup_tx_stream`].
+pub trait TxStream: Send {
+    fn format(&self) -> StreamFormat;
+
+    /// Number of samples the device takes at once.
+    fn mtu(&self) -> usize;
+
+    /// Starts streaming. Silence is transmitted while no samples are queued.
+    fn activate(&mut self) -> Result<()>;
+
+    /// Stops streaming and discards samples that were not transmitted yet.
+    fn deactivate(&mut self) -> Result<()>;
+
+    /// Queues samples of the stream format for transmission, waiting up to `timeout` for
+    /// space. Returns the number of samples queued, which is zero on timeout.
+    fn write(&mut self, buffer: Samples<'_>, timeout: Duration) -> Result<usize>;
+}
+
+/// A software defined radio.
+///
+/// Channels are numbered from zero in each direction. Getters return `None` for values that
+/// have not been set yet.
+pub trait SdrDevice {
+    /// Name of the driver, such as `hackrf`.
+    fn driver_key(&self) -> String;
+
+    /// Name of the hardware model.
+    fn hardware_key(&self) -> String;
+
+    /// Further information about the hardware, such as serial number and firmware version.
+    fn hardware_info(&self) -> Vec<(String, String)>;
+
+    fn num_channels(&self, direction: StreamMode) -> usize;
+
+    /// Returns true if the channel can stream while the other direction is streaming.
+    fn full_duplex(&self, direction: StreamMode, channel: usize) -> bool;
+
+    /// Tunable center frequencies in Hz.
+    fn frequency_range(&self, direction: StreamMode, channel: usize) -> Vec<Range>;
+
+    /// Center frequency in Hz.
+    fn frequency(&self, direction: StreamMode, channel: usize) -> Option<f64>;
+
+    fn set_frequency(&self, direction: StreamMode, channel: usize, frequency: f64) -> Result<()>;
+
+    /// Names of the gain elements, ordered from the antenna to the converter.
+    fn list_gains(&self, direction: StreamMode, channel: usize) -> Vec<String>;
+
+    /// Gain range of an element in dB.
+    fn gain_element_range(
+        &self,
+        direction: StreamMode,
+        channel: usize,
+        name: &str,
+    ) -> Result<Range>;
+
+    /// Gain of an element in dB.
+    fn gain_element(&self, direction: StreamMode, channel: usize, name: &str) -> Result<f64>;
+
+    fn set_gain_element(
+        &self,
+        direction: StreamMode,
+        channel: usize,
+        name: &str,
+        gain: f64,
+    ) -> Result<()>;
+
+    /// Range of the overall gain in dB, from the sum of the element ranges.
+    fn gain_range(&self, direction: StreamMode, channel: usize) -> Result<Range> {
+        let mut range = Range::new(0.0, 0.0, 0.0);
+        for name in self.list_gains(direction, channel) {
+            let element = self.gain_element_range(direction, channel, &name)?;
+            range.min += element.min;
+            range.max += element.max;
+        }
+        Ok(range)
+    }
+
+    /// Overall gain in dB, the sum of the element gains.
+    fn gain(&self, direction: StreamMode, channel: usize) -> Result<f64> {
+        let mut gain = 0.0;
+        for name in self.list_gains(direction, channel) {
+            gain += self.gain_element(direction, channel, &name)?;
+        }
+        Ok(gain)
+    }
+
+    /// Sets the overall gain in dB, filling the elements in the order of
+    /// [`SdrDevice::list_gains`].
+    fn set_gain(&self, direction: StreamMode, channel: usize, gain: f64) -> Result<()> {
+        let mut remaining = gain - self.gain_range(direction, channel)?.min;
+        for name in self.list_gains(direction, channel) {
+            let range = self.gain_element_range(direction, channel, &name)?;
+            let element = range.clip(range.min + remaining);
+            remaining -= element - range.min;
+            self.set_gain_element(direction, channel, &name, element)?;
+        }
+        Ok(())
+    }
+
+    /// Supported sample rates in Hz.
+    fn list_sample_rates(&self, direction: StreamMode, channel: usize) -> Vec<f64>;
+
+    /// Sample rate in Hz.
+    fn sample_rate(&self, direction: StreamMode, channel: usize) -> Option<f64>;
+
+    fn set_sample_rate(&self, direction: StreamMode, channel: usize, rate: f64) -> Result<()>;
+
+    /// Supported analog filter bandwidths in Hz.
+    fn list_bandwidths(&self, direction: StreamMode, channel: usize) -> Vec<f64>;
+
+    /// Analog filter bandwidth in Hz.
+    fn bandwidth(&self, direction: StreamMode, channel: usize) -> Option<f64>;
+
+    fn set_bandwidth(&self, direction: StreamMode, channel: usize, bandwidth: f64) -> Result<()>;
+
+    /// Formats streams of the channel can be set up with.
+    fn stream_formats(&self, direction: StreamMode, channel: usize) -> Vec<StreamFormat>;
+
+    /// Format used by the hardware, which needs no conversion, and its full scale value, which
+    /// [`StreamFormat::Cf32`] streams scale to one.
+    fn native_stream_format(&self, direction: StreamMode, channel: usize) -> (StreamFormat, f64);
+
+    fn setup_rx_stream(
+        &self,
+        format: StreamFormat,
+        channels: &[usize],
+    ) -> Result<Box<dyn RxStream>>;
+
+    fn setup_tx_stream(
+        &self,
+        format: StreamFormat,
+        channels: &[usize],
+    ) -> Result<Box<dyn TxStream>>;
+}
+
+impl Range {
+    pub const fn new(min: f64, max: f64, step: f64) -> Self {
+        Self { min, max, step }
+    }
+
+    /// Clamps a value to the range and rounds it down to a multiple of the step.
+    pub fn clip(&self, value: f64) -> f64 {
+        let value = value.clamp(self.min, self.max);
+        match self.step > 0.0 {
+            true => self.min + ((value - self.min) / self.step + 1e-9).floor() * self.step,
+            false => value,
+        }
+    }
+}
+
+impl StreamFormat {
+    /// The name SoapySDR uses for the format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamFormat::Cs8 => "CS8",
+            StreamFormat::Cs16 => "CS16",
+            StreamFormat::Cf32 => "CF32",
+        }
+    }
+
+    /// Size of a sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            StreamFormat::Cs8 => 2,
+            StreamFormat::Cs16 => 4,
+            StreamFormat::Cf32 => 8,
+        }
+    }
+}
+
+impl Samples<'_> {
+    pub fn format(&self) -> StreamFormat {
+        match self {
+            Samples::Cs8(_) => StreamFormat::Cs8,
+            Samples::Cs16(_) => StreamFormat::Cs16,
+            Samples::Cf32(_) => StreamFormat::Cf32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::Cs8(x) => x.len(),
+            Samples::Cs16(x) => x.len(),
+            Samples::Cf32(x) => x.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SamplesMut<'_> {
+    pub fn format(&self) -> StreamFormat {
+        match self {
+            SamplesMut::Cs8(_) => StreamFormat::Cs8,
+            SamplesMut::Cs16(_) => StreamFormat::Cs16,
+            SamplesMut::Cf32(_) => StreamFormat::Cf32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SamplesMut::Cs8(x) => x.len(),
+            SamplesMut::Cs16(x) => x.len(),
+            SamplesMut::Cf32(x) => x.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> From<&'a [Complex<i8>]> for Samples<'a> {
+    fn from(value: &'a [Complex<i8>]) -> Self {
+        Samples::Cs8(value)
+    }
+}
+
+impl<'a> From<&'a [Complex<i16>]> for Samples<'a> {
+    fn from(value: &'a [Complex<i16>]) -> Self {
+        Samples::Cs16(value)
+    }
+}
+
+impl<'a> From<&'a [Complex<f32>]> for Samples<'a> {
+    fn from(value: &'a [Complex<f32>]) -> Self {
+        Samples::Cf32(value)
+    }
+}
+
+impl<'a> From<&'a mut [Complex<i8>]> for SamplesMut<'a> {
+    fn from(value: &'a mut [Complex<i8>]) -> Self {
+        SamplesMut::Cs8(value)
+    }
+}
+
+impl<'a> From<&'a mut [Complex<i16>]> for SamplesMut<'a> {
+    fn from(value: &'a mut [Complex<i16>]) -> Self {
+        SamplesMut::Cs16(value)
+    }
+}
+
+impl<'a> From<&'a mut [Complex<f32>]> for SamplesMut<'a> {
+    fn from(value: &'a mut [Complex<f32>]) -> Self {
+        SamplesMut::Cf32(value)
+    }
+}
+
+/// The HackRF has a single half duplex channel. The frequency, sample rate and bandwidth are
+/// shared between directions, and its gain elements are `AMP`, `LNA` and `VGA` for receiving
+/// and `AMP` and `VGA` for transmitting.
+impl SdrDevice for HackRf {
+    fn driver_key(&self) -> String {
+        "hackrf".into()
+    }
+
+    fn hardware_key(&self) -> String {
+        self.get_device_type()
+            .map(|x| x.name())
+            .unwrap_or("HackRF")
+            .into()
+    }
+
+    fn hardware_info(&self) -> Vec<(String, String)> {
+        let mut info = vec![("version".into(), self.version())];
+        if let Ok(serial_number) = self.get_serial_number() {
+            info.push(("serial".into(), serial_number.to_string()));
+        }
+        info
+    }
+
+    fn num_channels(&self, _direction: StreamMode) -> usize {
+        1
+    }
+
+    fn full_duplex(&self, _direction: StreamMode, _channel: usize) -> bool {
+        false
+    }
+
+    fn frequency_range(&self, _direction: StreamMode, _channel: usize) -> Vec<Range> {
+        vec![Range::new(0.0, 7.25e9, 0.0)]
+    }
+
+    fn frequency(&self, _direction: StreamMode, _channel: usize) -> Option<f64> {
+        self.config().freq.map(|x| x as f64)
+    }
+
+    fn set_frequency(&self, _direction: StreamMode, channel: usize, frequency: f64) -> Result<()> {
+        check_channel(channel)?;
+        self.set_freq(frequency.round() as u64)
+    }
+
+    fn list_gains(&self, direction: StreamMode, _channel: usize) -> Vec<String> {
+        let names: &[&str] = match direction {
+            StreamMode::Receive => &["AMP", "LNA", "VGA"],
+            StreamMode::Transmit => &["AMP", "VGA"],
+        };
+        names.iter().map(|&x| x.into()).collect()
+    }
+
+    fn gain_element_range(
+        &self,
+        direction: StreamMode,
+        channel: usize,
+        name: &str,
+    ) -> Result<Range> {
+        check_channel(channel)?;
+        Ok(match (direction, name) {
+            (_, "AMP") => Range::new(0.0, 14.0, 14.0),
+            (StreamMode::Receive, "LNA") => Range::new(0.0, 40.0, 8.0),
+            (StreamMode::Receive, "VGA") => Range::new(0.0, 62.0, 2.0),
+            (StreamMode::Transmit, "VGA") => Range::new(0.0, 47.0, 1.0),
+            _ => return Err(HackrfError::InvalidParam),
+        })
+    }
+
+    /// Gains that have not been set are reported as zero.
+    fn gain_element(&self, direction: StreamMode, channel: usize, name: &str) -> Result<f64> {
+        check_channel(channel)?;
+        let config = self.config();
+        let gain = match (direction, name) {
+            (_, "AMP") => match config.amp_enable {
+                Some(true) => 14,
+                _ => 0,
+            },
+            (StreamMode::Receive, "LNA") => config.lna_gain.unwrap_or_default(),
+            (StreamMode::Receive, "VGA") => config.rxvga_gain.unwrap_or_default(),
+            (StreamMode::Transmit, "VGA") => config.txvga_gain.unwrap_or_default(),
+            _ => return Err(HackrfError::InvalidParam),
+        };
+        Ok(gain as f64)
+    }
+
+    fn set_gain_element(
+        &self,
+        direction: StreamMode,
+        channel: usize,
+        name: &str,
+        gain: f64,
+    ) -> Result<()> {
+        let gain = self
+            .gain_element_range(direction, channel, name)?
+            .clip(gain) as u32;
+        match (direction, name) {
+            (_, "AMP") => self.set_amp_enable(gain > 0),
+            (StreamMode::Receive, "LNA") => self.set_lna_gain(gain),
+            (StreamMode::Receive, "VGA") => self.set_rxvga_gain(gain),
+            (StreamMode::Transmit, "VGA") => self.set_txvga_gain(gain),
+            _ => Err(HackrfError::InvalidParam),
+        }
+    }
+
+    fn list_sample_rates(&self, _direction: StreamMode, _channel: usize) -> Vec<f64> {
+        (2..=20).map(|x| x as f64 * 1e6).collect()
+    }
+
+    fn sample_rate(&self, _direction: StreamMode, _channel: usize) -> Option<f64> {
+        self.config().sample_rate.map(|x| x as f64)
+    }
+
+    fn set_sample_rate(&self, _direction: StreamMode, channel: usize, rate: f64) -> Result<()> {
+        check_channel(channel)?;
+        HackRf::set_sample_rate(self, rate.round() as u32)
+    }
+
+    fn list_bandwidths(&self, _direction: StreamMode, _channel: usize) -> Vec<f64> {
+        HACKRF_BANDWIDTHS.to_vec()
+    }
+
+    fn bandwidth(&self, _direction: StreamMode, _channel: usize) -> Option<f64> {
+        self.config().baseband_filter_bandwidth.map(|x| x as f64)
+    }
+
+    fn set_bandwidth(&self, _direction: StreamMode, channel: usize, bandwidth: f64) -> Result<()> {
+        check_channel(channel)?;
+        self.set_baseband_filter_bandwidth(bandwidth.round() as u32)
+    }
+
+    fn stream_formats(&self, _direction: StreamMode, _channel: usize) -> Vec<StreamFormat> {
+        vec![StreamFormat::Cs8, StreamFormat::Cs16, StreamFormat::Cf32]
+    }
+
+    fn native_stream_format(&self, _direction: StreamMode, _channel: usize) -> (StreamFormat, f64) {
+        (StreamFormat::Cs8, 127.0)
+    }
+
+    fn setup_rx_stream(
+        &self,
+        format: StreamFormat,
+        channels: &[usize],
+    ) -> Result<Box<dyn RxStream>> {
+        check_channels(channels)?;
+        Ok(Box::new(HackRfStream::new(self.clone(), format)))
+    }
+
+    fn setup_tx_stream(
+        &self,
+        format: StreamFormat,
+        channels: &[usize],
+    ) -> Result<Box<dyn TxStream>> {
+        check_channels(channels)?;
+        Ok(Box::new(HackRfStream::new(self.clone(), format)))
+    }
+}
+
+fn check_channel(channel: usize) -> Result<()> {
+    match channel {
+        0 => Ok(()),
+        _ => Err(HackrfError::InvalidParam),
+    }
+}
+
+fn check_channels(channels: &[usize]) -> Result<()> {
+    match channels {
+        [] | [0] => Ok(()),
+        _ => Err(HackrfError::InvalidParam),
+    }
+}
+
+/// A stream of a [`HackRf`] in either direction, buffering samples between the transfer
+/// callbacks and the reads or writes of the application.
+struct HackRfStream {
+    hackrf: HackRf,
+    format: StreamFormat,
+    queue: Arc<StreamQueue>,
+    active: Option<StreamMode>,
+    /// Native samples converted to or from the stream format.
+    scratch: Vec<Complex<i8>>,
+}
+
+#[derive(Default)]
+struct StreamQueue {
+    samples: Mutex<VecDeque<Complex<i8>>>,
+    /// Notified when samples are added or taken.
+    changed: Condvar,
+}
+
+impl HackRfStream {
+    fn new(hackrf: HackRf, format: StreamFormat) -> Self {
+        Self {
+            hackrf,
+            format,
+            queue: Arc::new(StreamQueue::default()),
+            active: None,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn activate(&mut self, mode: StreamMode) -> Result<()> {
+        if self.active.is_some() {
+            return Ok(());
+        }
+
+        self.queue.samples.lock().unwrap().clear();
+        match mode {
+            StreamMode::Receive => self.hackrf.start_rx(rx_callback, self.queue.clone())?,
+            StreamMode::Transmit => self.hackrf.start_tx(tx_callback, self.queue.clone())?,
+        }
+        self.active = Some(mode);
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        match self.active.take() {
+            Some(StreamMode::Receive) => self.hackrf.stop_rx(),
+            Some(StreamMode::Transmit) => {
+                let result = self.hackrf.stop_tx();
+                self.queue.samples.lock().unwrap().clear();
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_format(&self, format: StreamFormat) -> Result<()> {
+        match format == self.format {
+            true => Ok(()),
+            false => Err(HackrfError::InvalidParam),
+        }
+    }
+}
+
+impl RxStream for HackRfStream {
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    fn mtu(&self) -> usize {
+        HACKRF_MTU
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        HackRfStream::activate(self, StreamMode::Receive)
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        HackRfStream::deactivate(self)
+    }
+
+    fn read(&mut self, buffer: SamplesMut<'_>, timeout: Duration) -> Result<usize> {
+        self.check_format(buffer.format())?;
+
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.queue.samples.lock().unwrap();
+        while queue.is_empty() && self.active.is_some() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            queue = self.queue.changed.wait_timeout(queue, timeout).unwrap().0;
+        }
+
+        let count = queue.len().min(buffer.len());
+        self.scratch.clear();
+        self.scratch.extend(queue.drain(..count));
+        drop(queue);
+
+        match buffer {
+            SamplesMut::Cs8(buffer) => buffer[..count].copy_from_slice(&self.scratch),
+            SamplesMut::Cs16(buffer) => util::i8_to_i16(&self.scratch, &mut buffer[..count]),
+            SamplesMut::Cf32(buffer) => util::i8_to_f32(&self.scratch, &mut buffer[..count]),
+        }
+        Ok(count)
+    }
+}
+
+impl TxStream for HackRfStream {
+    fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    fn mtu(&self) -> usize {
+        HACKRF_MTU
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        HackRfStream::activate(self, StreamMode::Transmit)
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        HackRfStream::deactivate(self)
+    }
+
+    fn write(&mut self, buffer: Samples<'_>, timeout: Duration) -> Result<usize> {
+        self.check_format(buffer.format())?;
+
+        let limit = HACKRF_QUEUE_TRANSFERS * HACKRF_MTU;
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.queue.samples.lock().unwrap();
+        while queue.len() >= limit && self.active.is_some() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            queue = self.queue.changed.wait_timeout(queue, timeout).unwrap().0;
+        }
+
+        let count = limit.saturating_sub(queue.len()).min(buffer.len());
+        match buffer {
+            Samples::Cs8(buffer) => queue.extend(&buffer[..count]),
+            Samples::Cs16(buffer) => queue.extend(
+                buffer[..count]
+                    .iter()
+                    .map(|x| Complex::new((x.re >> 8) as i8, (x.im >> 8) as i8)),
+            ),
+            Samples::Cf32(buffer) => {
+                self.scratch.resize(count, Complex::default());
+                util::f32_to_i8(&buffer[..count], &mut self.scratch);
+                queue.extend(&self.scratch);
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for HackRfStream {
+    fn drop(&mut self) {
+        let _ = HackRfStream::deactivate(self);
+    }
+}
+
+fn rx_callback(_hackrf: &HackRf, samples: &[Complex<i8>], _info: &TransferInfo, user: &dyn Any) {
+    let queue = user.downcast_ref::<Arc<StreamQueue>>().unwrap();
+    let mut samples_queue = queue.samples.lock().unwrap();
+
+    // Drop the oldest samples when the application falls behind
+    samples_queue.extend(samples);
+    let excess = samples_queue
+        .len()
+        .saturating_sub(HACKRF_QUEUE_TRANSFERS * HACKRF_MTU);
+    samples_queue.drain(..excess);
+    queue.changed.notify_all();
+}
+
+fn tx_callback(
+    _hackrf: &HackRf,
+    samples: &mut [Complex<i8>],
+    _info: &TransferInfo,
+    user: &dyn Any,
+) {
+    let queue = user.downcast_ref::<Arc<StreamQueue>>().unwrap();
+    let mut samples_queue = queue.samples.lock().unwrap();
+
+    let available = samples_queue.len().min(samples.len());
+    for (sample, queued) in samples.iter_mut().zip(samples_queue.drain(..available)) {
+        *sample = queued;
+    }
+    samples[available..].fill(Complex::default());
+    queue.changed.notify_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{Range, SdrDevice, StreamFormat};
+    use crate::{sim::Simulator, stats::StreamMode, Config, HackRf};
+
+    /// Receives the same sample and keeps the samples transmitted other than silence.
+    #[derive(Default)]
+    struct Loop {
+        transmitted: Arc<Mutex<Vec<Complex<i8>>>>,
+    }
+
+    impl Simulator for Loop {
+        fn initial_config(&self) -> Config {
+            Config {
+                sample_rate: Some(2_000_000),
+                ..Config::default()
+            }
+        }
+
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(Complex::new(-128, 64));
+            true
+        }
+
+        fn transmit(&self, samples: &[Complex<i8>], _config: &Config) -> bool {
+            let mut transmitted = self.transmitted.lock().unwrap();
+            transmitted.extend(samples.iter().filter(|x| **x != Complex::default()));
+            true
+        }
+    }
+
+    #[test]
+    fn clips_to_steps() {
+        let range = Range::new(0.0, 40.0, 8.0);
+        assert_eq!(range.clip(39.0), 32.0);
+        assert_eq!(range.clip(-5.0), 0.0);
+        assert_eq!(range.clip(100.0), 40.0);
+        assert_eq!(range.clip(16.0), 16.0);
+        assert_eq!(Range::new(1.0, 10.0, 3.0).clip(6.9), 4.0);
+        assert_eq!(Range::new(0.0, 1.0, 0.0).clip(0.3), 0.3);
+    }
+
+    #[test]
+    fn splits_gain_across_stages() {
+        let hackrf = HackRf::simulated(Loop::default());
+        let rx = StreamMode::Receive;
+        let elements = |direction| {
+            hackrf
+                .list_gains(direction, 0)
+                .iter()
+                .map(|name| hackrf.gain_element(direction, 0, name).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            hackrf.gain_range(rx, 0).unwrap(),
+            Range::new(0.0, 116.0, 0.0)
+        );
+        hackrf.set_gain(rx, 0, 50.0).unwrap();
+        assert_eq!(elements(rx), [14.0, 32.0, 4.0]);
+        assert_eq!(hackrf.gain(rx, 0).unwrap(), 50.0);
+
+        hackrf.set_gain(rx, 0, 200.0).unwrap();
+        assert_eq!(elements(rx), [14.0, 40.0, 62.0]);
+        hackrf.set_gain(rx, 0, 10.0).unwrap();
+        assert_eq!(elements(rx), [0.0, 8.0, 2.0]);
+        assert_eq!(hackrf.config().amp_enable, Some(false));
+
+        hackrf.set_gain(StreamMode::Transmit, 0, 20.0).unwrap();
+        assert_eq!(elements(StreamMode::Transmit), [14.0, 6.0]);
+        assert_eq!(hackrf.config().txvga_gain, Some(6));
+    }
+
+    #[test]
+    fn converts_received_samples() {
+        let hackrf = HackRf::simulated(Loop::default());
+        let timeout = Duration::from_secs(5);
+
+        let mut stream = hackrf.setup_rx_stream(StreamFormat::Cs8, &[0]).unwrap();
+        stream.activate().unwrap();
+        let mut cs8 = [Complex::<i8>::default(); 4];
+        assert_eq!(stream.read((&mut cs8[..]).into(), timeout).unwrap(), 4);
+        assert_eq!(cs8, [Complex::new(-128, 64); 4]);
+        let mut cs16 = [Complex::<i16>::default(); 4];
+        assert!(stream.read((&mut cs16[..]).into(), timeout).is_err());
+        stream.deactivate().unwrap();
+
+        let mut stream = hackrf.setup_rx_stream(StreamFormat::Cs16, &[0]).unwrap();
+        stream.activate().unwrap();
+        assert_eq!(stream.read((&mut cs16[..]).into(), timeout).unwrap(), 4);
+        assert_eq!(cs16, [Complex::new(-32768, 16384); 4]);
+        stream.deactivate().unwrap();
+
+        let mut stream = hackrf.setup_rx_stream(StreamFormat::Cf32, &[0]).unwrap();
+        stream.activate().unwrap();
+        let mut cf32 = [Complex::<f32>::default(); 4];
+        assert_eq!(stream.read((&mut cf32[..]).into(), timeout).unwrap(), 4);
+        assert_eq!(cf32, [Complex::new(-128.0 / 127.0, 64.0 / 127.0); 4]);
+        stream.deactivate().unwrap();
+
+        let (format, full_scale) = hackrf.native_stream_format(StreamMode::Receive, 0);
+        assert_eq!(format, StreamFormat::Cs8);
+        assert_eq!(cf32[0].im, (cs8[0].im as f64 / full_scale) as f32);
+    }
+
+    #[test]
+    fn converts_transmitted_samples() {
+        let sim = Loop::default();
+        let transmitted = sim.transmitted.clone();
+        let hackrf = HackRf::simulated(sim);
+        let timeout = Duration::from_secs(5);
+
+        let cs8 = [Complex::new(5i8, -7)];
+        let cs16 = [Complex::new(0x1234i16, -256)];
+        let cf32 = [Complex::new(0.5f32, -2.0)];
+        for (count, (format, samples)) in [
+            (StreamFormat::Cs8, cs8[..].into()),
+            (StreamFormat::Cs16, cs16[..].into()),
+            (StreamFormat::Cf32, cf32[..].into()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut stream = hackrf.setup_tx_stream(format, &[0]).unwrap();
+            stream.activate().unwrap();
+            assert_eq!(stream.write(samples, timeout).unwrap(), 1);
+
+            let start = Instant::now();
+            while transmitted.lock().unwrap().len() <= count {
+                assert!(start.elapsed() < timeout, "nothing transmitted");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            stream.deactivate().unwrap();
+        }
+
+        let expected = [
+            Complex::new(5, -7),
+            Complex::new(0x12, -1),
+            Complex::new(64, -127),
+        ];
+        assert_eq!(*transmitted.lock().unwrap(), expected);
+    }
+}
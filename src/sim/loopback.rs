@@ -0,0 +1,311 @@
+//! A simulated transmitter and receiver connected by a channel model, for testing transmit
+//! chains end to end without radiating.
+
+use std::{
+    collections::VecDeque,
+    f64::consts::TAU,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use num_complex::Complex;
+
+use super::{Pacing, Simulator, TRANSFER_SIZE};
+use crate::{
+    stats::StreamMode,
+    util::{ToComplexF32, ToComplexI8},
+    Config, HackRf,
+};
+
+/// Maximum transmit gain, at which a full scale sample is transmitted at full power.
+const MAX_TXVGA_GAIN: f32 = 47.0;
+/// Gain of the RF amplifier when enabled.
+const AMP_GAIN: f32 = 14.0;
+/// Transfers the transmitter can get ahead of the receiver before it has to wait.
+const QUEUE_TRANSFERS: usize = 8;
+/// Time the receiver waits for transmitted samples before filling the rest with silence.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Impairments applied between the transmitter and receiver of a [`pair`].
+///
+/// The received signal is scaled by the transmit gain relative to its maximum of 47 dB, the
+/// path loss and the receive LNA and VGA gains, with 14 dB added on each side that has its
+/// amplifier enabled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Channel {
+    /// Attenuation between the antennas in dB.
+    pub path_loss: f32,
+    /// Signal to noise ratio in dB at the receiver input of a full scale transmission at the
+    /// maximum transmit gain, or `None` for a noiseless channel.
+    pub snr: Option<f32>,
+    /// Error of the transmitter sample clock relative to the receiver in parts per million.
+    pub clock_error_ppm: f64,
+    pub pacing: Pacing,
+    /// Seed of the noise generator, so runs are repeatable.
+    pub seed: u64,
+}
+
+/// Creates a connected transmitter and receiver. Samples sent with [`HackRf::start_tx`] on the
+/// first device are received by [`HackRf::start_rx`] on the second, shifted by the difference
+/// of their center frequencies and resampled from the transmit to the receive sample rate.
+pub fn pair(channel: Channel) -> (HackRf, HackRf) {
+    let shared = Arc::new(Shared {
+        channel,
+        state: Mutex::new(State {
+            tx_config: Config::default(),
+            tx_streaming: false,
+            rx_streaming: false,
+            queue: VecDeque::new(),
+            position: 0.0,
+            phase: 0.0,
+            noise: Noise::new(channel.seed),
+        }),
+        changed: Condvar::new(),
+    });
+
+    (
+        HackRf::simulated(Transmitter(shared.clone())),
+        HackRf::simulated(Receiver(shared)),
+    )
+}
+
+struct Transmitter(Arc<Shared>);
+struct Receiver(Arc<Shared>);
+
+struct Shared {
+    channel: Channel,
+    state: Mutex<State>,
+    /// Notified when samples are queued or taken, or a stream starts or stops.
+    changed: Condvar,
+}
+
+struct State {
+    tx_config: Config,
+    tx_streaming: bool,
+    rx_streaming: bool,
+    /// Transmitted samples, scaled by the transmit gain.
+    queue: VecDeque<Complex<f32>>,
+    /// Position of the next received sample in the queue, in transmitted samples.
+    position: f64,
+    phase: f64,
+    noise: Noise,
+}
+
+/// Gaussian noise from a xorshift generator.
+struct Noise {
+    state: u64,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            path_loss: 30.0,
+            snr: Some(30.0),
+            clock_error_ppm: 0.0,
+            pacing: Pacing::RealTime,
+            seed: 1,
+        }
+    }
+}
+
+impl Shared {
+    fn set_streaming(&self, mode: StreamMode, streaming: bool) {
+        let mut state = self.state.lock().unwrap();
+        match mode {
+            StreamMode::Transmit => state.tx_streaming = streaming,
+            StreamMode::Receive => state.rx_streaming = streaming,
+        }
+
+        if !state.rx_streaming {
+            state.queue.clear();
+            state.position = 0.0;
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl Simulator for Transmitter {
+    fn configure(&self, config: &Config) -> crate::error::Result<()> {
+        self.0.state.lock().unwrap().tx_config = *config;
+        Ok(())
+    }
+
+    fn stream_started(&self, mode: StreamMode) {
+        if mode == StreamMode::Transmit {
+            self.0.set_streaming(mode, true);
+        }
+    }
+
+    fn stream_stopped(&self, mode: StreamMode) {
+        if mode == StreamMode::Transmit {
+            self.0.set_streaming(mode, false);
+        }
+    }
+
+    fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+        samples.fill(Complex::default());
+        true
+    }
+
+    fn transmit(&self, samples: &[Complex<i8>], config: &Config) -> bool {
+        let mut state = self.0.state.lock().unwrap();
+        state.tx_config = *config;
+
+        // Wait for the receiver to catch up, as long as it is still listening
+        let limit = QUEUE_TRANSFERS * TRANSFER_SIZE / 2;
+        while state.rx_streaming && state.queue.len() > limit {
+            state = self
+                .0
+                .changed
+                .wait_timeout(state, RECEIVE_TIMEOUT)
+                .unwrap()
+                .0;
+        }
+
+        if state.rx_streaming {
+            let gain = db_to_amplitude(tx_gain(config));
+            state
+                .queue
+                .extend(samples.iter().map(|x| x.to_f32() * gain));
+            self.0.changed.notify_all();
+        }
+
+        true
+    }
+
+    fn pacing(&self) -> Pacing {
+        self.0.channel.pacing
+    }
+}
+
+impl Simulator for Receiver {
+    fn stream_started(&self, mode: StreamMode) {
+        if mode == StreamMode::Receive {
+            self.0.set_streaming(mode, true);
+        }
+    }
+
+    fn stream_stopped(&self, mode: StreamMode) {
+        if mode == StreamMode::Receive {
+            self.0.set_streaming(mode, false);
+        }
+    }
+
+    fn receive(&self, samples: &mut [Complex<i8>], config: &Config) -> bool {
+        let channel = &self.0.channel;
+        let mut state = self.0.state.lock().unwrap();
+        let tx_config = state.tx_config;
+
+        let rx_rate = config.sample_rate.unwrap_or_default() as f64;
+        let tx_rate = tx_config.sample_rate.unwrap_or_default() as f64;
+        let step = match rx_rate > 0.0 && tx_rate > 0.0 {
+            true => tx_rate * (1.0 + channel.clock_error_ppm * 1e-6) / rx_rate,
+            false => 1.0,
+        };
+
+        // Wait until enough samples for the whole buffer have been transmitted
+        let needed = (state.position + samples.len() as f64 * step).ceil() as usize + 1;
+        while state.tx_streaming && state.queue.len() < needed {
+            let (next, timeout) = self.0.changed.wait_timeout(state, RECEIVE_TIMEOUT).unwrap();
+            state = next;
+            if timeout.timed_out() {
+                break;
+            }
+        }
+
+        let offset = match (tx_config.freq, config.freq) {
+            (Some(tx), Some(rx)) => tx as f64 - rx as f64,
+            _ => 0.0,
+        };
+        // Signals outside of the receive bandwidth are filtered out
+        let visible = rx_rate == 0.0 || offset.abs() < rx_rate / 2.0;
+        let phase_step = match rx_rate > 0.0 {
+            true => TAU * offset / rx_rate,
+            false => 0.0,
+        };
+
+        let rx_gain = db_to_amplitude(rx_gain(config));
+        let noise = channel.snr.map_or(0.0, |snr| {
+            db_to_amplitude(-channel.path_loss - snr) / 2f32.sqrt()
+        });
+        let path_gain = db_to_amplitude(-channel.path_loss);
+
+        let state = &mut *state;
+        for sample in samples.iter_mut() {
+            let index = state.position as usize;
+            let t = state.position.fract() as f32;
+            let a = state.queue.get(index).copied().unwrap_or_default();
+            let b = state.queue.get(index + 1).copied().unwrap_or_default();
+            state.position += step;
+
+            let mut value = Complex::default();
+            if visible {
+                let rotation = Complex::from_polar(1.0, state.phase as f32);
+                value = (a + (b - a) * t) * rotation * path_gain;
+            }
+            state.phase = (state.phase + phase_step) % TAU;
+
+            value += state.noise.complex() * noise;
+            *sample = (value * rx_gain).to_i8();
+        }
+
+        let consumed = (state.position as usize).min(state.queue.len());
+        state.queue.drain(..consumed);
+        state.position = match state.queue.is_empty() {
+            true => state.position.fract(),
+            false => state.position - consumed as f64,
+        };
+        self.0.changed.notify_all();
+
+        true
+    }
+
+    fn pacing(&self) -> Pacing {
+        self.0.channel.pacing
+    }
+}
+
+fn tx_gain(config: &Config) -> f32 {
+    let txvga = config.txvga_gain.unwrap_or_default() as f32;
+    txvga - MAX_TXVGA_GAIN + amp_gain(config)
+}
+
+fn rx_gain(config: &Config) -> f32 {
+    let lna = config.lna_gain.unwrap_or_default() as f32;
+    let vga = config.rxvga_gain.unwrap_or_default() as f32;
+    lna + vga + amp_gain(config)
+}
+
+fn amp_gain(config: &Config) -> f32 {
+    match config.amp_enable {
+        Some(true) => AMP_GAIN,
+        _ => 0.0,
+    }
+}
+
+fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    fn uniform(&mut self) -> f64 {
+        // xorshift64
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A sample with a standard deviation of one in each component, from the Box-Muller
+    /// transform.
+    fn complex(&mut self) -> Complex<f32> {
+        let radius = (-2.0 * (1.0 - self.uniform()).ln()).sqrt();
+        let angle = TAU * self.uniform();
+        let (sin, cos) = angle.sin_cos();
+        Complex::new((radius * cos) as f32, (radius * sin) as f32)
+    }
+}
@@ -0,0 +1,220 @@
+//! Simulated devices that stand in for a HackRF, so code using [`HackRf`](crate::HackRf) can be
+//! tested without hardware. Create one with [`HackRf::simulated`](crate::HackRf::simulated).
+
+use std::{
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use num_complex::Complex;
+
+use crate::{
+    error::Result,
+    ffi::{HackrfTransfer, SerialNumber},
+    sample,
+    stats::StreamMode,
+    transfer::{rx_callback, tx_callback, ContextHandle},
+    Config, DeviceType, WeakHackRf,
+};
+
+pub mod loopback;
+pub mod replay;
+
+/// Size of a simulated transfer in bytes, the same as the transfers of libhackrf.
+pub const TRANSFER_SIZE: usize = 262_144;
+
+/// How fast a simulated stream delivers transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// At the configured sample rate, like a real device.
+    RealTime,
+    /// As fast as the callback handles them.
+    Fast,
+}
+
+/// The hardware behind a simulated [`HackRf`](crate::HackRf).
+pub trait Simulator: Send + Sync {
+    /// Configuration the device starts with.
+    fn initial_config(&self) -> Config {
+        Config::default()
+    }
+
+    /// Checks a new configuration before it is applied, returning an error to refuse it.
+    fn configure(&self, _config: &Config) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called on the stream thread before the first transfer of a stream.
+    fn stream_started(&self, _mode: StreamMode) {}
+
+    /// Called on the stream thread after the last transfer of a stream.
+    fn stream_stopped(&self, _mode: StreamMode) {}
+
+    /// Fills a buffer with received samples. Returns false once the stream should end.
+    fn receive(&self, samples: &mut [Complex<i8>], config: &Config) -> bool;
+
+    /// Takes a buffer of transmitted samples. Returns false once the stream should end.
+    fn transmit(&self, _samples: &[Complex<i8>], _config: &Config) -> bool {
+        true
+    }
+
+    fn pacing(&self) -> Pacing {
+        Pacing::RealTime
+    }
+
+    /// Whether this stands in for real hardware attached elsewhere, such as a remote device.
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Hackrf1R9
+    }
+
+    fn serial_number(&self) -> SerialNumber {
+        SerialNumber::default()
+    }
+
+    /// Firmware version reported by [`HackRf::version`](crate::HackRf::version).
+    fn version(&self) -> String {
+        "simulated".into()
+    }
+}
+
+/// The thread running the stream of a simulated device.
+pub(crate) struct SimStream {
+    running: Arc<AtomicBool>,
+    /// Context of a stream stopped from its own callback, freed by the thread once it exits.
+    orphan: Arc<Mutex<Option<ContextHandle>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// A transfer context pointer, only dereferenced by the transfer callbacks.
+struct Context(*mut c_void);
+unsafe impl Send for Context {}
+
+impl SimStream {
+    /// Starts calling the transfer callback for `mode` with the context on a new thread.
+    pub fn spawn(hackrf: WeakHackRf, mode: StreamMode, context: *mut c_void) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let orphan = Arc::new(Mutex::new(None::<ContextHandle>));
+        let context = Context(context);
+
+        let thread = thread::spawn({
+            let (running, orphan) = (running.clone(), orphan.clone());
+            move || {
+                let context = context;
+                run(&hackrf, mode, context.0, &running);
+                running.store(false, Ordering::Relaxed);
+
+                if let Some(context) = orphan.lock().unwrap().take() {
+                    unsafe { context.free() };
+                }
+            }
+        });
+
+        Self {
+            running,
+            orphan,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stops the stream and waits for the current transfer to finish, then frees the context.
+    ///
+    /// When stopped from within its own callback the transfer is still using the context, so it
+    /// is freed by the stream thread once the callback returns instead.
+    pub fn stop(&mut self, context: Option<ContextHandle>) {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) if thread.thread().id() == thread::current().id() => {
+                *self.orphan.lock().unwrap() = context;
+                return;
+            }
+            Some(thread) => {
+                let _ = thread.join();
+            }
+            None => {}
+        }
+
+        if let Some(context) = context {
+            unsafe { context.free() };
+        }
+    }
+}
+
+/// Runs the stream until it is stopped, the simulator ends it or the device is dropped.
+/// The device is only held for one transfer at a time, so dropping the last handle ends the
+/// stream.
+fn run(hackrf: &WeakHackRf, mode: StreamMode, context: *mut c_void, running: &AtomicBool) {
+    let Some(device) = hackrf.upgrade() else {
+        return;
+    };
+
+    let mut buffer = vec![0u8; TRANSFER_SIZE];
+    let mut transfer = HackrfTransfer {
+        device: device.device(),
+        buffer: buffer.as_mut_ptr(),
+        buffer_length: TRANSFER_SIZE as i32,
+        valid_length: TRANSFER_SIZE as i32,
+        rx_ctx: context,
+        tx_ctx: context,
+    };
+
+    if let Some(simulator) = device.simulator() {
+        simulator.stream_started(mode);
+    }
+    drop(device);
+
+    let start = Instant::now();
+    let mut elapsed = Duration::ZERO;
+    while running.load(Ordering::Relaxed) {
+        let Some(device) = hackrf.upgrade() else {
+            return;
+        };
+        let Some(simulator) = device.simulator() else {
+            return;
+        };
+
+        let config = device.config();
+        // Like libhackrf, the stream ends when the callback returns non-zero, after the last
+        // transmitted transfer has been sent
+        let more = match mode {
+            StreamMode::Receive => {
+                let samples = sample::from_bytes_mut(&mut buffer);
+                let more = simulator.receive(samples, &config);
+                let end = rx_callback(&mut transfer) != 0;
+                more && !end
+            }
+            StreamMode::Transmit => {
+                let end = tx_callback(&mut transfer) != 0;
+                simulator.transmit(sample::from_bytes(&buffer), &config) && !end
+            }
+        };
+
+        if !more {
+            break;
+        }
+
+        let sample_rate = config.sample_rate.unwrap_or_default();
+        let pacing = simulator.pacing();
+        drop(device);
+        if pacing == Pacing::RealTime && sample_rate > 0 {
+            elapsed += Duration::from_secs_f64((TRANSFER_SIZE / 2) as f64 / sample_rate as f64);
+            thread::sleep(elapsed.saturating_sub(start.elapsed()));
+        }
+    }
+
+    if let Some(simulator) = hackrf.upgrade().as_ref().and_then(|x| x.simulator()) {
+        simulator.stream_stopped(mode);
+    }
+}
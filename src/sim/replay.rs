@@ -0,0 +1,236 @@
+//! A simulated receiver that plays back a `.cs8` or SigMF recording.
+
+use std::{env, f64::consts::TAU, io, path::Path, sync::Mutex};
+
+use num_complex::Complex;
+
+use super::{Pacing, Simulator};
+use crate::{
+    error::{HackrfError, Result},
+    recording::{cs8::Cs8Source, sigmf::SigMfSource, Repeat, SampleSource},
+    util::{ToComplexF32, ToComplexI8},
+    Config,
+};
+
+/// Path of a recording to replay instead of opening a device in [`crate::HackRf::open`].
+pub const REPLAY_ENV: &str = "HACKRF_REPLAY";
+/// Sample rate of a `.cs8` recording set with [`REPLAY_ENV`].
+pub const REPLAY_SAMPLE_RATE_ENV: &str = "HACKRF_REPLAY_SAMPLE_RATE";
+/// Center frequency of a `.cs8` recording set with [`REPLAY_ENV`].
+pub const REPLAY_FREQ_ENV: &str = "HACKRF_REPLAY_FREQ";
+/// Replays the recording set with [`REPLAY_ENV`] as fast as possible when set.
+pub const REPLAY_FAST_ENV: &str = "HACKRF_REPLAY_FAST";
+
+/// Plays back a recording as the received samples of a simulated device.
+///
+/// The device is fixed to the sample rate of the recording. Tuning within the bandwidth of the
+/// recording shifts the samples so the new center frequency is at DC, and tuning outside of it
+/// is refused. The stream ends at the end of the recording.
+pub struct Replay {
+    source: Box<dyn SampleSource>,
+    sample_rate: u32,
+    center_freq: Option<u64>,
+    pacing: Pacing,
+    phase: Mutex<f64>,
+}
+
+impl Replay {
+    /// Replays a `.cs8` file, which does not record its sample rate or center frequency.
+    pub fn cs8(
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        center_freq: Option<u64>,
+    ) -> io::Result<Self> {
+        let source = Cs8Source::open(path, Repeat::Once)?;
+        Ok(Self::new(source, sample_rate, center_freq))
+    }
+
+    /// Replays a SigMF recording at the sample rate and frequency of its first capture.
+    pub fn sigmf(path: impl AsRef<Path>) -> io::Result<Self> {
+        let source = SigMfSource::open(path, Repeat::Once)?;
+        let sample_rate = source.sample_rate().ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "recording has no sample rate",
+        ))?;
+        let center_freq = source.metadata().captures.first().and_then(|x| x.frequency);
+
+        Ok(Self::new(
+            source,
+            sample_rate.round() as u32,
+            center_freq.map(|x| x as u64),
+        ))
+    }
+
+    /// Replays samples from any source.
+    pub fn new(
+        source: impl SampleSource + 'static,
+        sample_rate: u32,
+        center_freq: Option<u64>,
+    ) -> Self {
+        Self {
+            source: Box::new(source),
+            sample_rate,
+            center_freq,
+            pacing: Pacing::RealTime,
+            phase: Mutex::new(0.0),
+        }
+    }
+
+    /// Replays the recording set with the [`REPLAY_ENV`] environment variable, if any.
+    /// Recordings with a `.sigmf-meta` or `.sigmf-data` extension are read as SigMF and all
+    /// others as `.cs8`.
+    pub fn from_env() -> Option<io::Result<Self>> {
+        let path = env::var_os(REPLAY_ENV)?;
+        let path = Path::new(&path);
+
+        let replay = match path.extension().and_then(|x| x.to_str()) {
+            Some("sigmf-meta" | "sigmf-data") => Self::sigmf(path),
+            _ => match env::var(REPLAY_SAMPLE_RATE_ENV)
+                .ok()
+                .and_then(|x| x.parse().ok())
+            {
+                Some(sample_rate) => {
+                    let freq = env::var(REPLAY_FREQ_ENV).ok().and_then(|x| x.parse().ok());
+                    Self::cs8(path, sample_rate, freq)
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the sample rate of .cs8 recordings must be set",
+                )),
+            },
+        };
+
+        let pacing = match env::var_os(REPLAY_FAST_ENV) {
+            Some(_) => Pacing::Fast,
+            None => Pacing::RealTime,
+        };
+        Some(replay.map(|x| x.with_pacing(pacing)))
+    }
+
+    pub fn with_pacing(self, pacing: Pacing) -> Self {
+        Self { pacing, ..self }
+    }
+
+    /// Sample rate of the recording in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Center frequency of the recording in Hz, if known.
+    pub fn center_freq(&self) -> Option<u64> {
+        self.center_freq
+    }
+
+    /// Offset of the tuned frequency from the center of the recording in Hz.
+    fn offset(&self, config: &Config) -> f64 {
+        match (config.freq, self.center_freq) {
+            (Some(freq), Some(center)) => freq as f64 - center as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Simulator for Replay {
+    fn initial_config(&self) -> Config {
+        Config {
+            freq: self.center_freq,
+            sample_rate: Some(self.sample_rate),
+            ..Config::default()
+        }
+    }
+
+    fn configure(&self, config: &Config) -> Result<()> {
+        if config.sample_rate.is_some_and(|x| x != self.sample_rate) {
+            return Err(HackrfError::InvalidParam);
+        }
+
+        if self.offset(config).abs() > self.sample_rate as f64 / 2.0 {
+            return Err(HackrfError::InvalidParam);
+        }
+
+        Ok(())
+    }
+
+    fn receive(&self, samples: &mut [Complex<i8>], config: &Config) -> bool {
+        let more = self.source.read(samples);
+
+        let offset = self.offset(config);
+        if offset != 0.0 {
+            let step = -TAU * offset / self.sample_rate as f64;
+            let mut phase = self.phase.lock().unwrap();
+            for sample in samples.iter_mut() {
+                let rotation = Complex::from_polar(1.0, *phase as f32);
+                *sample = (sample.to_f32() * rotation).to_i8();
+                *phase = (*phase + step) % TAU;
+            }
+        }
+
+        more
+    }
+
+    fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::Replay;
+    use crate::{error::HackrfError, recording::temp_path, sample, sim::Pacing, HackRf};
+
+    #[test]
+    fn replays_recording_through_stream() {
+        let path = temp_path("replay.cs8");
+        let recording = (0..200_000)
+            .map(|n| Complex::new((n % 255 - 127) as i8, (n / 1_000) as i8))
+            .collect::<Vec<_>>();
+        fs::write(&path, sample::as_bytes(&recording)).unwrap();
+
+        let replay = Replay::cs8(&path, 2_000_000, Some(100_000_000))
+            .unwrap()
+            .with_pacing(Pacing::Fast);
+        let hackrf = HackRf::simulated(replay);
+        assert_eq!(hackrf.config().sample_rate, Some(2_000_000));
+        assert!(matches!(
+            hackrf.set_sample_rate(10_000_000),
+            Err(HackrfError::InvalidParam)
+        ));
+
+        let received = Arc::new(Mutex::new(Vec::<Complex<i8>>::new()));
+        hackrf
+            .start_rx(
+                |_, samples, _, user| {
+                    let received = user.downcast_ref::<Arc<Mutex<Vec<Complex<i8>>>>>().unwrap();
+                    received.lock().unwrap().extend_from_slice(samples);
+                },
+                received.clone(),
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        while hackrf.is_streaming() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "replay did not end"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        hackrf.stop_rx().unwrap();
+        fs::remove_file(path).unwrap();
+
+        // The last transfer is padded with zeros
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 262_144);
+        assert_eq!(received[..200_000], recording);
+        assert!(received[200_000..].iter().all(|x| *x == Complex::default()));
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use num_complex::Complex;
+
+use crate::{transfer::TransferInfo, HackRf};
+
+const HISTOGRAM_BUCKETS: usize = 24;
+/// Time between reads of the shortfalls counted by the firmware.
+#[cfg(feature = "m0-state")]
+const SHORTFALL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The direction of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamMode {
+    Receive,
+    Transmit,
+}
+
+/// Counters describing the health of a stream.
+/// A new instance is created every time a stream is started, see [`crate::HackRf::stream_stats`].
+pub struct StreamStats {
+    mode: StreamMode,
+    sample_rate: u32,
+    created: Instant,
+    running: AtomicBool,
+
+    transfers: AtomicU64,
+    samples: AtomicU64,
+    dropped_samples: AtomicU64,
+    gaps: AtomicU64,
+    /// Shortfalls counted by the firmware since the stream started, if it reports them.
+    shortfalls: AtomicU64,
+    shortfalls_reported: AtomicBool,
+
+    first_transfer: AtomicU64,
+    first_samples: AtomicU64,
+    last_transfer: AtomicU64,
+    callback_time: AtomicU64,
+    latency: Histogram,
+
+    /// Measure the level of received samples, which is only done when something uses it.
+    measure_level: AtomicBool,
+    measured_samples: AtomicU64,
+    power: AtomicU64,
+    clipped_samples: AtomicU64,
+}
+
+/// Histogram of durations with power-of-two microsecond buckets.
+pub struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+}
+
+/// Checks the achieved sample rate of a stream at a fixed interval and reports or stops
+/// the stream when it falls behind. Set with [`crate::HackRf::set_watchdog`].
+#[derive(Clone, Copy)]
+pub struct Watchdog {
+    /// Fraction of the configured sample rate the stream must achieve.
+    pub threshold: f64,
+    /// Time between checks.
+    pub interval: Duration,
+    /// Called when the achieved sample rate is below the threshold.
+    pub callback: Option<fn(hack_rf: &HackRf, stats: &StreamStats)>,
+    /// Stop the stream when the achieved sample rate is below the threshold.
+    pub stop: bool,
+}
+
+impl StreamStats {
+    pub(crate) fn new(mode: StreamMode, sample_rate: u32, measure_level: bool) -> Self {
+        Self {
+            mode,
+            sample_rate,
+            created: Instant::now(),
+            running: AtomicBool::new(true),
+
+            transfers: AtomicU64::new(0),
+            samples: AtomicU64::new(0),
+            dropped_samples: AtomicU64::new(0),
+            gaps: AtomicU64::new(0),
+            shortfalls: AtomicU64::new(0),
+            shortfalls_reported: AtomicBool::new(false),
+
+            first_transfer: AtomicU64::new(0),
+            first_samples: AtomicU64::new(0),
+            last_transfer: AtomicU64::new(0),
+            callback_time: AtomicU64::new(0),
+            latency: Histogram::new(),
+
+            measure_level: AtomicBool::new(measure_level),
+            measured_samples: AtomicU64::new(0),
+            power: AtomicU64::new(0),
+            clipped_samples: AtomicU64::new(0),
+        }
+    }
+
+    /// The direction of the stream.
+    pub fn mode(&self) -> StreamMode {
+        self.mode
+    }
+
+    /// The sample rate the device was configured with when the stream started.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns true until the stream is stopped.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Number of transfers delivered to the callback.
+    pub fn transfers(&self) -> u64 {
+        self.transfers.load(Ordering::Relaxed)
+    }
+
+    /// Number of samples delivered to the callback.
+    pub fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    /// Estimated number of samples lost between transfers, or dropped after a transfer because
+    /// the application fell behind.
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    /// Number of transfers that were preceded by a gap in the stream or dropped.
+    pub fn gaps(&self) -> u64 {
+        self.gaps.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffer shortfalls counted by the firmware since the stream started, or None
+    /// when they are not available. Requires the `m0-state` feature and firmware 2023.01.1 or
+    /// newer.
+    pub fn firmware_shortfalls(&self) -> Option<u64> {
+        self.shortfalls_reported
+            .load(Ordering::Relaxed)
+            .then(|| self.shortfalls.load(Ordering::Relaxed))
+    }
+
+    /// Number of gaps in a receive stream, caused by the host not reading samples fast enough.
+    pub fn overruns(&self) -> u64 {
+        match self.mode {
+            StreamMode::Receive => self.gaps(),
+            StreamMode::Transmit => 0,
+        }
+    }
+
+    /// Number of gaps in a transmit stream, caused by the host not supplying samples fast enough.
+    pub fn underruns(&self) -> u64 {
+        match self.mode {
+            StreamMode::Receive => 0,
+            StreamMode::Transmit => self.gaps(),
+        }
+    }
+
+    /// Sample rate achieved between the first and the most recent transfer, in Hz.
+    pub fn achieved_sample_rate(&self) -> f64 {
+        let first = self.first_transfer.load(Ordering::Relaxed);
+        let last = self.last_transfer.load(Ordering::Relaxed);
+        if last <= first {
+            return 0.0;
+        }
+
+        let samples = self.samples() - self.first_samples.load(Ordering::Relaxed);
+        samples as f64 / Duration::from_nanos(last - first).as_secs_f64()
+    }
+
+    /// Total time spent inside the user callback.
+    pub fn callback_time(&self) -> Duration {
+        Duration::from_nanos(self.callback_time.load(Ordering::Relaxed))
+    }
+
+    /// Fraction of the stream's wall-clock time spent inside the user callback.
+    pub fn callback_load(&self) -> f64 {
+        self.callback_time().as_secs_f64() / self.created.elapsed().as_secs_f64()
+    }
+
+    /// Histogram of the time between consecutive callbacks.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Number of received samples included in the level measurements. Levels are only measured
+    /// while an automatic gain control is active, see [`crate::HackRf::set_agc`].
+    pub fn measured_samples(&self) -> u64 {
+        self.measured_samples.load(Ordering::Relaxed)
+    }
+
+    /// Sum of the squared magnitudes of the measured samples, in 8-bit units.
+    pub fn power(&self) -> u64 {
+        self.power.load(Ordering::Relaxed)
+    }
+
+    /// Number of measured samples with I or Q at the limit of the converter, at ±127 or -128.
+    pub fn clipped_samples(&self) -> u64 {
+        self.clipped_samples.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, info: &TransferInfo) {
+        let now = self.nanos(info.timestamp);
+        let last = self.last_transfer.swap(now, Ordering::Relaxed);
+        if self.transfers.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.first_transfer.store(now, Ordering::Relaxed);
+            self.first_samples
+                .store(info.samples() as u64, Ordering::Relaxed);
+        } else {
+            self.latency.record(Duration::from_nanos(now - last));
+        }
+
+        self.samples
+            .fetch_add(info.samples() as u64, Ordering::Relaxed);
+
+        if info.dropped_samples > 0 {
+            self.dropped_samples
+                .fetch_add(info.dropped_samples, Ordering::Relaxed);
+            self.gaps.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a transfer that was received but dropped because the application fell behind,
+    /// which counts as a gap.
+    pub(crate) fn record_dropped(&self, samples: usize) {
+        self.dropped_samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+        self.gaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "m0-state")]
+    pub(crate) fn record_shortfalls(&self, shortfalls: u64) {
+        self.shortfalls.store(shortfalls, Ordering::Relaxed);
+        self.shortfalls_reported.store(true, Ordering::Relaxed);
+    }
+
+    /// Starts or stops measuring the level of the following transfers.
+    pub(crate) fn set_measure_level(&self, measure: bool) {
+        self.measure_level.store(measure, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_level(&self, samples: &[Complex<i8>]) {
+        if !self.measure_level.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let (mut power, mut clipped) = (0, 0);
+        for x in samples {
+            let (re, im) = (x.re as i32, x.im as i32);
+            power += (re * re + im * im) as u64;
+            clipped += (re.abs() >= 127 || im.abs() >= 127) as u64;
+        }
+
+        self.power.fetch_add(power, Ordering::Relaxed);
+        self.clipped_samples.fetch_add(clipped, Ordering::Relaxed);
+        self.measured_samples
+            .fetch_add(samples.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_callback(&self, duration: Duration) {
+        self.callback_time
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    fn nanos(&self, time: Instant) -> u64 {
+        (time - self.created).as_nanos() as u64
+    }
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().max(1) as u64;
+        let bucket = (micros.ilog2() as usize).min(HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the lower bound and count of every bucket.
+    /// The last bucket also contains all durations above its lower bound.
+    pub fn buckets(&self) -> Vec<(Duration, u64)> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (Duration::from_micros(1 << i), x.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Total number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|x| x.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns an upper bound of the given quantile (0-1) of the recorded durations.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let target = (count as f64 * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= target {
+                return Some(Duration::from_micros(2 << i));
+            }
+        }
+
+        None
+    }
+}
+
+/// Reads the shortfalls counted by the firmware into the stats of the current stream until it
+/// stops. The M0 state is read with a control transfer, which must not be done from the
+/// transfer callback, so it is polled on its own thread.
+#[cfg(feature = "m0-state")]
+pub(crate) fn poll_shortfalls(hackrf: HackRf) {
+    let Some(stats) = hackrf.stream_stats() else {
+        return;
+    };
+
+    let hackrf = hackrf.downgrade();
+    thread::spawn(move || {
+        // Older firmware does not report its state, so detection falls back to the time estimate
+        let Some(Ok(start)) = hackrf.upgrade().map(|x| x.get_m0_state()) else {
+            return;
+        };
+
+        while stats.is_running() {
+            let Some(device) = hackrf.upgrade() else {
+                break;
+            };
+            if let Ok(state) = device.get_m0_state() {
+                let shortfalls = state.num_shortfalls.wrapping_sub(start.num_shortfalls);
+                stats.record_shortfalls(shortfalls as u64);
+            }
+            thread::sleep(SHORTFALL_POLL_INTERVAL);
+        }
+    });
+}
+
+impl Watchdog {
+    /// Creates a watchdog that only reports when the stream achieves less than `threshold`
+    /// of the configured sample rate, checking every second.
+    pub fn new(threshold: f64, callback: fn(hack_rf: &HackRf, stats: &StreamStats)) -> Self {
+        Self {
+            threshold,
+            interval: Duration::from_secs(1),
+            callback: Some(callback),
+            stop: false,
+        }
+    }
+
+    pub(crate) fn spawn(self, hackrf: HackRf) {
+        let Some(stats) = hackrf.stream_stats() else {
+            return;
+        };
+
+        let hackrf = hackrf.downgrade();
+        thread::spawn(move || {
+            let mut last = (Instant::now(), stats.samples());
+            while stats.is_running() {
+                thread::sleep(self.interval);
+                let Some(hackrf) = hackrf.upgrade() else {
+                    break;
+                };
+
+                let now = (Instant::now(), stats.samples());
+                let rate = (now.1 - last.1) as f64 / (now.0 - last.0).as_secs_f64();
+                last = now;
+
+                let expected = stats.sample_rate() as f64 * self.threshold;
+                if !stats.is_running() || rate >= expected {
+                    continue;
+                }
+
+                if let Some(callback) = self.callback {
+                    callback(&hackrf, &stats);
+                }
+
+                if self.stop {
+                    let _ = match stats.mode() {
+                        StreamMode::Receive => hackrf.stop_rx(),
+                        StreamMode::Transmit => hackrf.stop_tx(),
+                    };
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{error::HackrfError, HackRf};
+
+/// Monitors a stream for errors and optionally reconnects to the device when it is unplugged.
+/// Set with [`HackRf::set_supervisor`].
+#[derive(Clone, Copy)]
+pub struct Supervisor {
+    /// Called when the stream stops because of an error or stops delivering transfers.
+    pub on_error: Option<fn(hack_rf: &HackRf, error: HackrfError)>,
+    /// Called after the device has been reopened and the stream restarted.
+    pub on_reconnect: Option<fn(hack_rf: &HackRf)>,
+    /// Reopen the device with the same serial number when it reappears, re-apply the last
+    /// configuration and restart the stream.
+    pub reconnect: bool,
+    /// Time between checks of the stream state.
+    pub poll_interval: Duration,
+    /// Time without any transfers after which the stream is considered stopped.
+    pub stall_timeout: Duration,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that reports streaming errors without reconnecting.
+    pub fn new(on_error: fn(hack_rf: &HackRf, error: HackrfError)) -> Self {
+        Self {
+            on_error: Some(on_error),
+            on_reconnect: None,
+            reconnect: false,
+            poll_interval: Duration::from_millis(100),
+            stall_timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub(crate) fn spawn(self, hackrf: HackRf) {
+        let Some(stats) = hackrf.stream_stats() else {
+            return;
+        };
+
+        let hackrf = hackrf.downgrade();
+        thread::spawn(move || {
+            let mut last = (Instant::now(), stats.transfers());
+            while stats.is_running() {
+                thread::sleep(self.poll_interval);
+                let Some(hackrf) = hackrf.upgrade() else {
+                    break;
+                };
+
+                let transfers = stats.transfers();
+                if transfers != last.1 {
+                    last = (Instant::now(), transfers);
+                }
+
+                let error = match hackrf.check_streaming() {
+                    Err(err) => err,
+                    Ok(()) if last.0.elapsed() >= self.stall_timeout => {
+                        HackrfError::StreamingStopped
+                    }
+                    Ok(()) => continue,
+                };
+
+                // The stream was stopped by the user
+                if !stats.is_running() {
+                    break;
+                }
+
+                if let Some(callback) = self.on_error {
+                    callback(&hackrf, error);
+                }
+
+                if !self.reconnect {
+                    stats.finish();
+                    break;
+                }
+
+                while stats.is_running() && hackrf.reopen().is_err() {
+                    thread::sleep(self.poll_interval);
+                }
+
+                if stats.is_running() && hackrf.restart_stream(stats.mode()).is_ok() {
+                    last = (Instant::now(), stats.transfers());
+                    if let Some(callback) = self.on_reconnect {
+                        callback(&hackrf);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::Supervisor;
+    use crate::{
+        error::{HackrfError, Result},
+        sim::{Pacing, Simulator},
+        Config, HackRf,
+    };
+
+    /// Stops delivering transfers after the first few until it is released.
+    struct Stall {
+        transfers: AtomicUsize,
+        released: Arc<AtomicBool>,
+    }
+
+    /// Ends the stream once after the first few transfers, as if the device was unplugged.
+    struct Unplug {
+        transfers: AtomicUsize,
+        configured: Arc<AtomicUsize>,
+    }
+
+    impl Simulator for Stall {
+        fn receive(&self, _samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            if self.transfers.fetch_add(1, Ordering::Relaxed) >= 2 {
+                while !self.released.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+            true
+        }
+
+        fn pacing(&self) -> Pacing {
+            Pacing::Fast
+        }
+    }
+
+    impl Simulator for Unplug {
+        fn configure(&self, _config: &Config) -> Result<()> {
+            self.configured.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn receive(&self, _samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            self.transfers.fetch_add(1, Ordering::Relaxed) != 2
+        }
+
+        fn pacing(&self) -> Pacing {
+            Pacing::Fast
+        }
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn reports_stalled_stream() {
+        static ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let released = Arc::new(AtomicBool::new(false));
+        let hackrf = HackRf::simulated(Stall {
+            transfers: AtomicUsize::new(0),
+            released: released.clone(),
+        });
+        hackrf.set_supervisor(Some(Supervisor {
+            poll_interval: Duration::from_millis(5),
+            stall_timeout: Duration::from_millis(50),
+            ..Supervisor::new(|_, error| ERRORS.lock().unwrap().push(error.to_string()))
+        }));
+        hackrf.start_rx(|_, _, _, _| {}, ()).unwrap();
+
+        let stats = hackrf.stream_stats().unwrap();
+        wait_for(|| !stats.is_running());
+        assert_eq!(stats.transfers(), 2);
+        assert_eq!(
+            *ERRORS.lock().unwrap(),
+            [HackrfError::StreamingStopped.to_string()]
+        );
+
+        released.store(true, Ordering::Relaxed);
+        hackrf.stop_rx().unwrap();
+    }
+
+    #[test]
+    fn reconnects_and_reapplies_config() {
+        static ERRORS: AtomicUsize = AtomicUsize::new(0);
+        static RECONNECTS: AtomicUsize = AtomicUsize::new(0);
+
+        let configured = Arc::new(AtomicUsize::new(0));
+        let hackrf = HackRf::simulated(Unplug {
+            transfers: AtomicUsize::new(0),
+            configured: configured.clone(),
+        });
+        hackrf.set_freq(100_000_000).unwrap();
+        hackrf.set_supervisor(Some(Supervisor {
+            on_reconnect: Some(|_| {
+                RECONNECTS.fetch_add(1, Ordering::Relaxed);
+            }),
+            reconnect: true,
+            poll_interval: Duration::from_millis(5),
+            ..Supervisor::new(|_, _| {
+                ERRORS.fetch_add(1, Ordering::Relaxed);
+            })
+        }));
+        hackrf.start_rx(|_, _, _, _| {}, ()).unwrap();
+
+        let stats = hackrf.stream_stats().unwrap();
+        wait_for(|| RECONNECTS.load(Ordering::Relaxed) == 1);
+        wait_for(|| stats.transfers() > 10);
+        assert!(hackrf.is_streaming());
+        assert_eq!(ERRORS.load(Ordering::Relaxed), 1);
+
+        // The frequency was set again after reopening
+        assert_eq!(configured.load(Ordering::Relaxed), 2);
+        assert_eq!(hackrf.config().freq, Some(100_000_000));
+        hackrf.stop_rx().unwrap();
+    }
+}
@@ -1,38 +1,252 @@
-use std::{any::Any, mem, slice};
+use std::{any::Any, cell::Cell, ffi::c_void, slice, sync::Arc, time::Instant};
 
 use num_complex::Complex;
 
-use super::{ffi, HackRf};
+use super::{ffi, sample, stats::StreamStats, HackRf, WeakHackRf};
 
-pub type TransmitCallback = fn(hack_rf: &HackRf, samples: &mut [Complex<i8>], user: &dyn Any);
-pub type ReceiveCallback = fn(hack_rf: &HackRf, samples: &[Complex<i8>], user: &dyn Any);
+pub type TransmitCallback =
+    fn(hack_rf: &HackRf, samples: &mut [Complex<i8>], info: &TransferInfo, user: &dyn Any);
+pub type ReceiveCallback =
+    fn(hack_rf: &HackRf, samples: &[Complex<i8>], info: &TransferInfo, user: &dyn Any);
+
+/// Metadata about a single transfer, passed to the stream callback alongside the samples.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferInfo {
+    /// Monotonic host time at which the transfer was handed to the callback.
+    pub timestamp: Instant,
+    /// Index of the transfer within the stream, starting at zero.
+    pub sequence: u64,
+    /// Position of the first sample of this transfer within the stream.
+    /// Includes any samples estimated to have been dropped.
+    pub sample_index: u64,
+    /// Size of the transfer buffer in bytes.
+    pub buffer_length: usize,
+    /// Number of bytes in the buffer that hold samples.
+    pub valid_length: usize,
+    /// Number of samples estimated to have been lost right before this transfer.
+    pub dropped_samples: u64,
+}
+
+impl TransferInfo {
+    /// Number of valid samples in the transfer.
+    pub fn samples(&self) -> usize {
+        self.valid_length / 2
+    }
+}
+
+/// Largest difference between the sample clock of the device and the host clock that is
+/// followed, as a fraction of the sample rate. Crystals are usually within 20 ppm.
+const MAX_CLOCK_DRIFT: f64 = 100e-6;
+
+/// Estimates dropped samples by comparing the samples received to the wall-clock time
+/// elapsed since the stream started at the configured sample rate.
+///
+/// The clocks of the device and host drift apart, so the lag is measured from the lowest lag
+/// seen, which follows a device running fast at once and one running slow by up to
+/// [`MAX_CLOCK_DRIFT`]. When the firmware reports its buffer shortfalls, a gap is only assumed
+/// after a new shortfall, and the time estimate is only used for its size.
+struct DropDetector {
+    sample_rate: u32,
+    start: Option<Instant>,
+    sequence: u64,
+    sample_index: u64,
+    /// Lowest lag in samples, from which the lag of a transfer is measured.
+    baseline: f64,
+    /// Shortfalls reported by the firmware so far.
+    shortfalls: u64,
+}
+
+impl DropDetector {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            start: None,
+            sequence: 0,
+            sample_index: 0,
+            baseline: 0.0,
+            shortfalls: 0,
+        }
+    }
+
+    fn next(
+        &mut self,
+        buffer_length: usize,
+        valid_length: usize,
+        shortfalls: Option<u64>,
+    ) -> TransferInfo {
+        self.next_at(Instant::now(), buffer_length, valid_length, shortfalls)
+    }
+
+    fn next_at(
+        &mut self,
+        timestamp: Instant,
+        buffer_length: usize,
+        valid_length: usize,
+        shortfalls: Option<u64>,
+    ) -> TransferInfo {
+        let samples = valid_length as u64 / 2;
+
+        let start = *self.start.get_or_insert(timestamp);
+        let mut dropped_samples = 0;
+        if self.sample_rate > 0 && self.sequence > 0 {
+            let expected = (timestamp - start).as_secs_f64() * self.sample_rate as f64;
+            let lag = expected - self.sample_index as f64;
+            let drift = (buffer_length / 2) as f64 * MAX_CLOCK_DRIFT;
+            self.baseline = lag.min(self.baseline + drift);
+            let lag = lag - self.baseline;
+
+            // Transfers are delivered in bursts, so allow up to two buffers of jitter
+            // before assuming samples were lost. The lag of a gap remains until it is counted,
+            // so a shortfall read after the transfer that followed it is still attributed.
+            let slack = (buffer_length / 2) as f64;
+            let gap = match shortfalls {
+                Some(shortfalls) => {
+                    let new = shortfalls > self.shortfalls;
+                    self.shortfalls = shortfalls;
+                    new && lag > slack
+                }
+                None => lag > slack * 2.0,
+            };
+            if gap {
+                dropped_samples = (lag - slack) as u64;
+            }
+        }
+
+        let info = TransferInfo {
+            timestamp,
+            sequence: self.sequence,
+            sample_index: self.sample_index + dropped_samples,
+            buffer_length,
+            valid_length,
+            dropped_samples,
+        };
+
+        self.sequence += 1;
+        self.sample_index += dropped_samples + samples;
+        info
+    }
+}
+
+thread_local! {
+    /// Set while a stream callback runs on this thread.
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns true on a thread that is running a stream callback, where the device must not be
+/// closed as libhackrf joins the transfer thread when closing.
+pub(super) fn in_callback() -> bool {
+    IN_CALLBACK.with(Cell::get)
+}
+
+/// Marks the thread as running a callback until dropped. Declared before the upgraded handle,
+/// so the handle is dropped while the thread is still marked.
+struct CallbackGuard;
+
+impl CallbackGuard {
+    fn new() -> Self {
+        IN_CALLBACK.with(|x| x.set(true));
+        Self
+    }
+}
+
+impl Drop for CallbackGuard {
+    fn drop(&mut self) {
+        IN_CALLBACK.with(|x| x.set(false));
+    }
+}
 
 pub struct TransferContext<Callback> {
     callback: Callback,
-    hackrf: HackRf,
+    /// Does not keep the device open, so dropping the last handle stops a running stream.
+    hackrf: WeakHackRf,
     user_data: Box<dyn Any>,
+    stats: Arc<StreamStats>,
+    detector: DropDetector,
 }
 
 impl<Callback> TransferContext<Callback> {
-    pub(super) fn new(callback: Callback, hackrf: HackRf, user_data: Box<dyn Any>) -> Self {
+    pub(super) fn new(
+        callback: Callback,
+        hackrf: WeakHackRf,
+        user_data: Box<dyn Any>,
+        stats: Arc<StreamStats>,
+        sample_rate: u32,
+    ) -> Self {
         Self {
             callback,
             hackrf,
             user_data,
+            stats,
+            detector: DropDetector::new(sample_rate),
         }
     }
+
+    fn next_info(&mut self, transfer: &ffi::HackrfTransfer) -> TransferInfo {
+        let info = self.detector.next(
+            transfer.buffer_length as usize,
+            transfer.valid_length as usize,
+            self.stats.firmware_shortfalls(),
+        );
+        self.stats.record(&info);
+        info
+    }
+}
+
+/// An owned pointer to a [`TransferContext`] that remembers its callback type, so it can be
+/// freed without knowing the direction of the stream.
+pub(super) struct ContextHandle {
+    pointer: *mut c_void,
+    free: unsafe fn(*mut c_void),
+}
+
+unsafe impl Send for ContextHandle {}
+
+impl ContextHandle {
+    pub fn new<Callback>(context: TransferContext<Callback>) -> Self {
+        unsafe fn free<Callback>(pointer: *mut c_void) {
+            drop(Box::from_raw(pointer as *mut TransferContext<Callback>));
+        }
+
+        Self {
+            pointer: Box::into_raw(Box::new(context)) as *mut c_void,
+            free: free::<Callback>,
+        }
+    }
+
+    pub fn pointer(&self) -> *mut c_void {
+        self.pointer
+    }
+
+    /// Frees the context, which must no longer be used by a stream.
+    pub unsafe fn free(self) {
+        (self.free)(self.pointer);
+    }
 }
 
 pub(super) extern "C" fn tx_callback(transfer: *mut ffi::HackrfTransfer) -> i32 {
     unsafe {
         let transfer = &mut *transfer;
-        let context = &*(transfer.tx_ctx as *mut TransferContext<TransmitCallback>);
+        let context = &mut *(transfer.tx_ctx as *mut TransferContext<TransmitCallback>);
+        let _guard = CallbackGuard::new();
+        let Some(hackrf) = context.hackrf.upgrade() else {
+            return -1;
+        };
+        let info = context.next_info(transfer);
 
-        let buffer = slice::from_raw_parts_mut(
-            mem::transmute::<*mut u8, *mut Complex<i8>>(transfer.buffer),
-            transfer.valid_length as usize / 2,
-        );
-        (context.callback)(&context.hackrf, buffer, &*context.user_data);
+        let buffer = slice::from_raw_parts_mut(transfer.buffer, transfer.valid_length as usize);
+        let buffer = sample::from_bytes_mut(buffer);
+        let start = Instant::now();
+        (context.callback)(&hackrf, buffer, &info, &*context.user_data);
+        context.stats.record_callback(start.elapsed());
+
+        // The stream ends once the callback returns non-zero
+        if hackrf.stream_ended() {
+            context.stats.finish();
+            return -1;
+        }
+
+        // Dropping the last handle frees the context, so it must not be used afterwards
+        drop(hackrf);
     }
 
     0
@@ -41,14 +255,139 @@ pub(super) extern "C" fn tx_callback(transfer: *mut ffi::HackrfTransfer) -> i32
 pub(super) extern "C" fn rx_callback(transfer: *mut ffi::HackrfTransfer) -> i32 {
     unsafe {
         let transfer = &*transfer;
-        let context = &*(transfer.rx_ctx as *mut TransferContext<ReceiveCallback>);
+        let context = &mut *(transfer.rx_ctx as *mut TransferContext<ReceiveCallback>);
+        let _guard = CallbackGuard::new();
+        let Some(hackrf) = context.hackrf.upgrade() else {
+            return -1;
+        };
+        let info = context.next_info(transfer);
 
-        let buffer = slice::from_raw_parts(
-            mem::transmute::<*mut u8, *const Complex<i8>>(transfer.buffer),
-            transfer.valid_length as usize / 2,
-        );
-        (context.callback)(&context.hackrf, buffer, &*context.user_data);
+        let buffer = slice::from_raw_parts(transfer.buffer, transfer.valid_length as usize);
+        let buffer = sample::from_bytes(buffer);
+        context.stats.record_level(buffer);
+        let start = Instant::now();
+        (context.callback)(&hackrf, buffer, &info, &*context.user_data);
+        context.stats.record_callback(start.elapsed());
+
+        // The stream ends once the callback returns non-zero
+        if hackrf.stream_ended() {
+            context.stats.finish();
+            return -1;
+        }
+
+        // Dropping the last handle frees the context, so it must not be used afterwards
+        drop(hackrf);
     }
 
     0
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use num_complex::Complex;
+
+    use super::{in_callback, DropDetector};
+    use crate::{sim::Simulator, Config, HackRf};
+
+    /// A detector that has received one transfer, with the stream started a second ago so that
+    /// almost a second of samples is missing.
+    fn lagging() -> DropDetector {
+        let mut detector = DropDetector::new(1_000_000);
+        detector.next(262_144, 262_144, Some(0));
+        detector.start = Some(Instant::now() - Duration::from_secs(1));
+        detector
+    }
+
+    #[test]
+    fn shortfalls_decide_gaps() {
+        // Without a shortfall the lag is taken as delivery jitter
+        let info = lagging().next(262_144, 262_144, Some(0));
+        assert_eq!(info.dropped_samples, 0);
+
+        let mut detector = lagging();
+        let info = detector.next(262_144, 262_144, Some(1));
+        assert!(info.dropped_samples > 700_000);
+        assert_eq!(info.sample_index, 131_072 + info.dropped_samples);
+
+        // The gap is counted once
+        let info = detector.next(262_144, 262_144, Some(1));
+        assert_eq!(info.dropped_samples, 0);
+    }
+
+    #[test]
+    fn time_estimate_without_shortfalls() {
+        let info = lagging().next(262_144, 262_144, None);
+        assert!(info.dropped_samples > 700_000);
+    }
+
+    #[test]
+    fn follows_clock_drift() {
+        // An hour of 20 MS/s from devices 20 ppm slow and fast, delivered with some jitter
+        for ppm in [-20.0, 20.0] {
+            let mut detector = DropDetector::new(20_000_000);
+            let rate = 20e6 * (1.0 + ppm * 1e-6);
+            let start = Instant::now();
+            let mut dropped = 0;
+            for k in 0..550_000u64 {
+                // Ten transfers are lost after half an hour
+                let k = if k >= 275_000 { k + 10 } else { k };
+                let jitter = Duration::from_millis(k % 5);
+                let time = start + Duration::from_secs_f64(k as f64 * 131_072.0 / rate) + jitter;
+                dropped += detector
+                    .next_at(time, 262_144, 262_144, None)
+                    .dropped_samples;
+            }
+            assert!(
+                (8 * 131_072..=10 * 131_072).contains(&dropped),
+                "{ppm} ppm: {dropped}"
+            );
+        }
+    }
+
+    struct Silence;
+
+    impl Simulator for Silence {
+        fn receive(&self, samples: &mut [Complex<i8>], _config: &Config) -> bool {
+            samples.fill(Complex::default());
+            true
+        }
+    }
+
+    #[test]
+    fn marks_callback_threads() {
+        let called = Arc::new(AtomicBool::new(false));
+        let hackrf = HackRf::simulated(Silence);
+        hackrf
+            .start_rx(
+                |hackrf, _, _, user| {
+                    // Dropping the last handle here closes the device from another thread
+                    assert!(in_callback());
+                    let called = user.downcast_ref::<Arc<AtomicBool>>().unwrap();
+                    called.store(true, Ordering::Relaxed);
+                    hackrf.end_stream();
+                },
+                called.clone(),
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        while hackrf.is_streaming() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "stream kept running"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(called.load(Ordering::Relaxed));
+        assert!(!in_callback());
+    }
+}
@@ -9,8 +9,10 @@ pub trait ToComplexF32 {
 }
 
 impl ToComplexI8 for Complex<f32> {
+    /// Converts a sample in the range [-1, 1] to an 8-bit sample.
+    /// Values outside of the range are saturated.
     fn to_i8(self) -> Complex<i8> {
-        Complex::new((self.re * 127.0) as i8, (self.im * 127.0) as i8)
+        Complex::new(f32_to_i8_sample(self.re), f32_to_i8_sample(self.im))
     }
 }
 
@@ -19,3 +21,187 @@ impl ToComplexF32 for Complex<i8> {
         Complex::new(self.re as f32 / 127.0, self.im as f32 / 127.0)
     }
 }
+
+#[inline(always)]
+fn f32_to_i8_sample(value: f32) -> i8 {
+    // Rounds half away from zero. Written without `f32::round` and with a clamp before the
+    // integer conversion so the loops using it are vectorized.
+    let value = (value * 127.0).clamp(-127.0, 127.0);
+    (value + 0.5f32.copysign(value)) as i32 as i8
+}
+
+/// Converts 8-bit samples to floating point samples, scaled so 127 is 1.0.
+/// The range is [-1, 1] except for -128, which becomes -128 / 127.
+///
+/// # Panics
+/// If the slices have different lengths.
+pub fn i8_to_f32(input: &[Complex<i8>], output: &mut [Complex<f32>]) {
+    assert_eq!(input.len(), output.len());
+    let (input, output) = (flatten(input), flatten_mut(output));
+
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = x as f32 * (1.0 / 127.0);
+    }
+}
+
+/// Converts 8-bit samples to double precision floating point samples, scaled so 127 is 1.0.
+/// The range is [-1, 1] except for -128, which becomes -128 / 127.
+///
+/// # Panics
+/// If the slices have different lengths.
+pub fn i8_to_f64(input: &[Complex<i8>], output: &mut [Complex<f64>]) {
+    assert_eq!(input.len(), output.len());
+    let (input, output) = (flatten(input), flatten_mut(output));
+
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = x as f64 * (1.0 / 127.0);
+    }
+}
+
+/// Converts 8-bit samples to 16-bit samples, scaled so full scale stays full scale.
+///
+/// # Panics
+/// If the slices have different lengths.
+pub fn i8_to_i16(input: &[Complex<i8>], output: &mut [Complex<i16>]) {
+    assert_eq!(input.len(), output.len());
+    let (input, output) = (flatten(input), flatten_mut(output));
+
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = (x as i16) << 8;
+    }
+}
+
+/// Converts floating point samples in the range [-1, 1] to 8-bit samples.
+/// Values outside of the range are saturated.
+///
+/// # Panics
+/// If the slices have different lengths.
+pub fn f32_to_i8(input: &[Complex<f32>], output: &mut [Complex<i8>]) {
+    assert_eq!(input.len(), output.len());
+    let (input, output) = (flatten(input), flatten_mut(output));
+
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = f32_to_i8_sample(x);
+    }
+}
+
+/// Converts floating point samples in the range [-1, 1] to 8-bit samples, adding
+/// triangular dither to decorrelate the quantization error from the signal.
+/// Values outside of the range are saturated.
+///
+/// # Panics
+/// If the slices have different lengths.
+pub fn f32_to_i8_dithered(input: &[Complex<f32>], output: &mut [Complex<i8>], dither: &mut Dither) {
+    assert_eq!(input.len(), output.len());
+    let (input, output) = (flatten(input), flatten_mut(output));
+
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = f32_to_i8_sample(x + dither.next() * (1.0 / 127.0));
+    }
+}
+
+/// A fast pseudo-random source of triangular dither with an amplitude of ±1 LSB.
+pub struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    fn next(&mut self) -> f32 {
+        let value = self.next_u32();
+        let a = (value & 0xFFFF) as f32 / 65535.0;
+        let b = (value >> 16) as f32 / 65535.0;
+        a - b
+    }
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self::new(0x2545_F491)
+    }
+}
+
+fn flatten<T: bytemuck::Pod>(samples: &[Complex<T>]) -> &[T] {
+    bytemuck::cast_slice(samples)
+}
+
+fn flatten_mut<T: bytemuck::Pod>(samples: &mut [Complex<T>]) -> &mut [T] {
+    bytemuck::cast_slice_mut(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::{f32_to_i8, f32_to_i8_dithered, f32_to_i8_sample, i8_to_f32, Dither};
+
+    #[test]
+    fn saturates_out_of_range() {
+        assert_eq!(f32_to_i8_sample(1.0), 127);
+        assert_eq!(f32_to_i8_sample(-1.0), -127);
+        assert_eq!(f32_to_i8_sample(1.5), 127);
+        assert_eq!(f32_to_i8_sample(-1.5), -127);
+        assert_eq!(f32_to_i8_sample(1e30), 127);
+        assert_eq!(f32_to_i8_sample(f32::NEG_INFINITY), -127);
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(f32_to_i8_sample(0.0), 0);
+        assert_eq!(f32_to_i8_sample(0.5 / 127.0), 1);
+        assert_eq!(f32_to_i8_sample(-0.5 / 127.0), -1);
+        assert_eq!(f32_to_i8_sample(0.49 / 127.0), 0);
+        assert_eq!(f32_to_i8_sample(-1.49 / 127.0), -1);
+        assert_eq!(f32_to_i8_sample(100.5 / 127.0), 101);
+    }
+
+    #[test]
+    fn round_trips_every_value() {
+        let input = (-127..=127)
+            .map(|x| Complex::new(x as i8, -x as i8))
+            .collect::<Vec<_>>();
+        let mut floats = vec![Complex::default(); input.len()];
+        i8_to_f32(&input, &mut floats);
+        assert_eq!(floats[0], Complex::new(-1.0, 1.0));
+
+        let mut output = vec![Complex::default(); input.len()];
+        f32_to_i8(&floats, &mut output);
+        assert_eq!(output, input);
+
+        let mut floats = [Complex::default()];
+        i8_to_f32(&[Complex::new(-128, 0)], &mut floats);
+        assert_eq!(floats[0].re, -128.0 / 127.0);
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb() {
+        let mut dither = Dither::default();
+        let (mut min, mut max, mut sum) = (f32::MAX, f32::MIN, 0.0);
+        for _ in 0..100_000 {
+            let value = dither.next();
+            min = min.min(value);
+            max = max.max(value);
+            sum += value as f64;
+        }
+        assert!(min >= -1.0 && max <= 1.0);
+        assert!(min < -0.9 && max > 0.9);
+        assert!((sum / 100_000.0).abs() < 0.01);
+
+        // Dither never moves a sample by more than one step or past full scale
+        let input = [Complex::new(0.0, 1.0); 1_000];
+        let mut output = [Complex::default(); 1_000];
+        f32_to_i8_dithered(&input, &mut output, &mut Dither::new(1));
+        assert!(output.iter().all(|x| x.re.abs() <= 1 && x.im >= 126));
+    }
+}